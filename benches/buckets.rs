@@ -60,7 +60,25 @@ fn bench(c: &mut Criterion) {
         })
     });
 
-    let functions = vec![increment, set, get];
+    let set_single_bits = Fun::new("SetSingleBits", |b, _| {
+        let mut buckets = Buckets::new(10_000, 1);
+        let data: Vec<usize> = (0..10_000).step_by(3).collect();
+
+        b.iter(|| {
+            for i in data.iter() {
+                buckets.set(*i, 1);
+            }
+        })
+    });
+
+    let set_bits_bulk = Fun::new("SetBitsBulk", |b, _| {
+        let mut buckets = Buckets::new(10_000, 1);
+        let data: Vec<usize> = (0..10_000).step_by(3).collect();
+
+        b.iter(|| buckets.set_bits_bulk(&data).count())
+    });
+
+    let functions = vec![increment, set, get, set_single_bits, set_bits_bulk];
     c.bench_functions("Buckets", functions, 0);
 }
 
@@ -1,4 +1,8 @@
 use criterion::{criterion_group, criterion_main, Criterion, Fun};
+#[cfg(feature = "ahash")]
+use stable_bloom_filter::hash::AHashBuilder;
+#[cfg(feature = "xxhash")]
+use stable_bloom_filter::hash::XxHashBuilder;
 use stable_bloom_filter::stable::StableBloomFilter;
 use stable_bloom_filter::Filter;
 
@@ -45,9 +49,102 @@ fn bench(c: &mut Criterion) {
         })
     });
 
-    let functions = vec![add, test, test_and_add];
+    let test_negative = Fun::new("TestNegative", |b, _| {
+        let mut s = StableBloomFilter::new_default(200, 0.01);
+        for i in 0..100_000 {
+            s.test_and_add(i.to_string().as_bytes());
+        }
+        let mut data = Vec::new();
+        for i in 100_000..200_000 {
+            data.push(i.to_string().into_bytes());
+        }
+
+        b.iter(|| {
+            for i in data.iter() {
+                s.test(i);
+            }
+        })
+    });
+
+    let test_negative_ordered = Fun::new("TestNegativeOrderedProbes", |b, _| {
+        let mut s = StableBloomFilter::new_default(200, 0.01);
+        s.enable_probe_ordering();
+        for i in 0..100_000 {
+            s.test_and_add(i.to_string().as_bytes());
+        }
+        let mut data = Vec::new();
+        for i in 100_000..200_000 {
+            data.push(i.to_string().into_bytes());
+        }
+
+        b.iter(|| {
+            for i in data.iter() {
+                s.test(i);
+            }
+        })
+    });
+
+    let add_large = Fun::new("AddLarge", |b, _| {
+        let mut s = StableBloomFilter::new_default(200, 0.01);
+        let data = large_payloads();
+
+        b.iter(|| {
+            for i in data.iter() {
+                s.add(i);
+            }
+        })
+    });
+
+    #[allow(unused_mut)]
+    let mut functions = vec![
+        add,
+        test,
+        test_and_add,
+        test_negative,
+        test_negative_ordered,
+        add_large,
+    ];
+
+    #[cfg(feature = "xxhash")]
+    functions.push(Fun::new("AddLargeXxHash", |b, _| {
+        let mut s = StableBloomFilter::with_hasher(200, 1, 0.01, XxHashBuilder::default());
+        let data = large_payloads();
+
+        b.iter(|| {
+            for i in data.iter() {
+                s.add(i);
+            }
+        })
+    }));
+
+    #[cfg(feature = "ahash")]
+    functions.push(Fun::new("AddAHash", |b, _| {
+        let mut s = StableBloomFilter::with_hasher(200, 1, 0.01, AHashBuilder::default());
+        let mut data = Vec::new();
+        for i in 0..100_000 {
+            data.push(i.to_string().into_bytes());
+        }
+
+        b.iter(|| {
+            for i in data.iter() {
+                s.add(i);
+            }
+        })
+    }));
+
     c.bench_functions("StableBloomFilter", functions, 0);
 }
 
+/// Returns 1,000 distinct 4 KiB payloads.
+fn large_payloads() -> Vec<Vec<u8>> {
+    (0..1000)
+        .map(|i: u32| {
+            let mut payload = vec![0u8; 4096];
+            payload[..4].copy_from_slice(&i.to_le_bytes());
+            payload
+        })
+        .collect()
+}
+
 criterion_group!(benches, bench);
 criterion_main!(benches);
@@ -0,0 +1,46 @@
+/* C bindings for stable-bloom-filter, built with the `ffi` feature. */
+#ifndef SBF_H
+#define SBF_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque Stable Bloom Filter. */
+typedef struct sbf sbf;
+
+/* Creates a filter with m cells of d bits optimized for fp_rate, or returns
+ * NULL if the parameters are invalid. */
+sbf *sbf_new(size_t m, uint8_t d, double fp_rate);
+
+/* Releases a filter. Does nothing if filter is NULL. */
+void sbf_free(sbf *filter);
+
+/* Adds the len bytes at data to the filter. */
+void sbf_add(sbf *filter, const uint8_t *data, size_t len);
+
+/* Returns true if the len bytes at data are a member of the filter. */
+bool sbf_test(const sbf *filter, const uint8_t *data, size_t len);
+
+/* Tests for membership of the data, then adds it. */
+bool sbf_test_and_add(sbf *filter, const uint8_t *data, size_t len);
+
+/* Returns a snapshot of the filter and stores its length in out_len. The
+ * buffer must be released with sbf_buffer_free. */
+uint8_t *sbf_serialize(const sbf *filter, size_t *out_len);
+
+/* Releases a buffer returned by sbf_serialize. */
+void sbf_buffer_free(uint8_t *buf, size_t len);
+
+/* Reads a filter from a snapshot, or returns NULL if it is invalid. */
+sbf *sbf_deserialize(const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SBF_H */
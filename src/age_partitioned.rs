@@ -0,0 +1,278 @@
+use crate::buckets::Buckets;
+use crate::fnv::FnvHasher;
+use crate::stable::split_hash;
+use crate::Filter;
+use std::hash::Hasher;
+
+/// AgePartitionedBloomFilter implements an Age-Partitioned Bloom Filter as
+/// described by Shtul, Baquero and Almeida in Age-Partitioned Bloom Filters:
+///
+/// https://arxiv.org/abs/2001.03147
+///
+/// An Age-Partitioned Bloom Filter (APBF) keeps k + l slices of bits.
+/// Elements are added to the first k slices, so every slice receives the
+/// elements of k generations of g elements, after which it is sized to be
+/// half full. Once g elements have been added, the
+/// slices shift by one: the oldest slice is cleared and becomes the first
+/// one. An element is a member if it is found in k consecutive slices, which
+/// holds for l shifts after it was added.
+///
+/// Every element added within the last l generations is therefore a member,
+/// with no false negatives, and is forgotten once its run of k slices no
+/// longer fits, giving a sharper recency cutoff than the probabilistic
+/// eviction of a Stable Bloom Filter.
+pub struct AgePartitionedBloomFilter {
+    /// filter data, k + l slices in a ring
+    slices: Buckets,
+    /// hash function (kernel for all k + l functions)
+    hash: FnvHasher,
+    /// number of slices an element is added to
+    k: usize,
+    /// number of slices beyond k, i.e. generations an element survives
+    l: usize,
+    /// number of elements per generation
+    g: usize,
+    /// slice size in bits
+    s: usize,
+    /// physical index of the first slice
+    base: usize,
+    /// number of elements added to the current generation
+    count: usize,
+}
+
+impl AgePartitionedBloomFilter {
+    /// Creates a new Age-Partitioned Bloom Filter which adds elements to k
+    /// slices, keeps l extra slices and shifts every g elements. Panics if k
+    /// or g is zero.
+    pub fn new(k: usize, l: usize, g: usize) -> Self {
+        assert!(
+            k > 0,
+            "an age-partitioned filter needs at least one hash function"
+        );
+        assert!(
+            g > 0,
+            "an age-partitioned filter needs a non-empty generation"
+        );
+        let s = (((k * g) as f64) / std::f64::consts::LN_2).ceil() as usize;
+
+        AgePartitionedBloomFilter {
+            slices: Buckets::new((k + l) * s, 1),
+            hash: FnvHasher::default(),
+            k,
+            l,
+            g,
+            s,
+            base: 0,
+            count: 0,
+        }
+    }
+
+    /// Creates a new Age-Partitioned Bloom Filter which adds elements to k
+    /// slices and remembers at least the last window elements across l
+    /// generations. Panics if k or l is zero.
+    pub fn with_window(window: usize, k: usize, l: usize) -> Self {
+        assert!(
+            l > 0,
+            "an age-partitioned filter needs at least one generation"
+        );
+        Self::new(k, l, window.max(1).div_ceil(l))
+    }
+
+    /// Returns the filter capacity, the number of bits across all slices.
+    pub fn capacity(&self) -> usize {
+        (self.k + self.l) * self.s
+    }
+
+    /// Returns the number of slices an element is added to.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of slices beyond k.
+    pub fn l(&self) -> usize {
+        self.l
+    }
+
+    /// Returns the number of elements per generation.
+    pub fn generation_size(&self) -> usize {
+        self.g
+    }
+
+    /// Returns the number of most recent elements which are guaranteed to be
+    /// members, l generations.
+    pub fn window(&self) -> usize {
+        self.l * self.g
+    }
+
+    /// Returns the number of elements added to the current generation.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns an upper bound on false positives for elements which were
+    /// never added: one is reported if any of the l + 1 runs of k slices
+    /// matches, each slice being at most half full. Recently expired
+    /// elements still match most of their slices and are reported more
+    /// often.
+    pub fn false_positive_rate(&self) -> f64 {
+        (((self.l + 1) as f64) * 0.5f64.powi(self.k as i32)).min(1.0)
+    }
+
+    /// Returns the ratio of set bits across all slices.
+    pub fn fill_ratio(&self) -> f64 {
+        (self.slices.iter_nonzero().count() as f64) / (self.capacity() as f64)
+    }
+
+    /// Shifts the slices by one, clearing the oldest slice and making it the
+    /// first one. It returns the filter to allow for chaining.
+    pub fn shift(&mut self) -> &Self {
+        let slices = self.k + self.l;
+        self.base = (self.base + slices - 1) % slices;
+        let start = self.base * self.s;
+        for i in start..start + self.s {
+            self.slices.set(i, 0);
+        }
+        self.count = 0;
+        self
+    }
+
+    /// Restores the filter to its original state. It returns the filter to
+    /// allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.slices.reset();
+        self.base = 0;
+        self.count = 0;
+        self
+    }
+
+    /// Returns the 32-bit lower and upper halves of the hash of the data.
+    fn hash_kernel(&self, data: &[u8]) -> (u32, u32) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        split_hash(hasher.finish())
+    }
+
+    /// Returns the bit index of the data in the i-th slice, counted from the
+    /// first one. Each physical slice has its own hash function, so elements
+    /// keep their bits as the slices shift.
+    #[inline]
+    fn location(&self, lower: u32, upper: u32, i: usize) -> usize {
+        let slice = (self.base + i) % (self.k + self.l);
+        slice * self.s + (lower as usize + upper as usize * slice) % self.s
+    }
+
+    /// Returns true if the hash is found in k consecutive slices.
+    fn test_kernel(&self, lower: u32, upper: u32) -> bool {
+        let mut run = 0;
+        for i in 0..(self.k + self.l) {
+            if self.slices.get(self.location(lower, upper, i)) != 0 {
+                run += 1;
+                if run == self.k {
+                    return true;
+                }
+            } else if i >= self.l {
+                // No run of k slices can start after slice l.
+                return false;
+            } else {
+                run = 0;
+            }
+        }
+        false
+    }
+
+    /// Adds the hash to the first k slices, shifting first if the current
+    /// generation is full.
+    fn add_kernel(&mut self, lower: u32, upper: u32) {
+        if self.count == self.g {
+            self.shift();
+        }
+        for i in 0..(self.k) {
+            let location = self.location(lower, upper, i);
+            self.slices.set(location, 1);
+        }
+        self.count += 1;
+    }
+}
+
+impl Filter for AgePartitionedBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. Elements added within the last l generations
+    /// are always members.
+    fn test(&self, data: &[u8]) -> bool {
+        let (lower, upper) = self.hash_kernel(data);
+        self.test_kernel(lower, upper)
+    }
+
+    /// Will add the data to the filter. It returns the filter to allow for
+    /// chaining.
+    fn add(&mut self, data: &[u8]) {
+        let (lower, upper) = self.hash_kernel(data);
+        self.add_kernel(lower, upper);
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        let (lower, upper) = self.hash_kernel(data);
+        let member = self.test_kernel(lower, upper);
+        self.add_kernel(lower, upper);
+        member
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AgePartitionedBloomFilter;
+    use crate::Filter;
+
+    // Ensures that the filter is sized from its window.
+    #[test]
+    fn test_with_window() {
+        let f = AgePartitionedBloomFilter::with_window(1000, 10, 7);
+        assert_eq!(f.generation_size(), 143);
+        assert_eq!(f.window(), 1001);
+        assert_eq!(f.capacity(), 17 * 2064);
+        assert!(f.false_positive_rate() < 0.01);
+    }
+
+    // Ensures that every element within the window is a member and that
+    // older elements are forgotten.
+    #[test]
+    fn test_window() {
+        let mut f = AgePartitionedBloomFilter::new(10, 7, 100);
+        for i in 0..10_000u64 {
+            f.add(&i.to_le_bytes());
+            for j in i.saturating_sub(699)..=i {
+                if j % 31 == 0 {
+                    assert!(f.test(&j.to_le_bytes()));
+                }
+            }
+        }
+
+        // Recently expired elements still match most of their slices, so
+        // only those well past the window are expected to be forgotten.
+        let stale = (0..8000u64).filter(|i| f.test(&i.to_le_bytes())).count();
+        assert!((stale as f64) < 8000.0 * f.false_positive_rate());
+    }
+
+    // Ensures that test_and_add reports membership before adding, that the
+    // filter shifts every generation and that reset clears it.
+    #[test]
+    fn test_test_and_add_and_reset() {
+        let mut f = AgePartitionedBloomFilter::new(4, 2, 10);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        for i in 0..8 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert_eq!(f.count(), 10);
+        f.add(b"b");
+        assert_eq!(f.count(), 1);
+        assert!(f.fill_ratio() > 0.0);
+
+        f.reset();
+        assert_eq!(f.count(), 0);
+        assert_eq!(f.fill_ratio(), 0.0);
+        assert!(!f.test(b"a"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Zero-copy snapshots of Stable Bloom Filters archived with rkyv. Unlike
+//! `read_from`, which copies the cells of a snapshot into a new filter,
+//! `ArchivedFilter::from_bytes` tests for membership directly in the
+//! archived bytes, e.g. a memory-mapped file, so that opening a filter of
+//! any size takes constant time:
+//!
+//!     use stable_bloom_filter::archive::ArchivedFilter;
+//!     use stable_bloom_filter::stable::StableBloomFilter;
+//!     use stable_bloom_filter::Filter;
+//!
+//!     let mut f = StableBloomFilter::new(10_000, 2, 0.01);
+//!     f.add(b"a");
+//!     let bytes = f.to_archived_bytes();
+//!
+//!     let archived = ArchivedFilter::from_bytes(&bytes).unwrap();
+//!     assert!(archived.test(b"a"));
+//!
+//! Only filters hashing data with FNV-1a, like those `read_from` accepts,
+//! can be archived. The archive is validated when accessed, but the cells
+//! are used as they are, so damaged cell data goes unnoticed.
+
+use crate::buckets::{bucket_value, expected_byte_len, Buckets};
+use crate::error::SnapshotError;
+use crate::fnv::FnvBuildHasher;
+use crate::hash::HashScheme;
+use crate::stable::{location, split_hash, StableBloomFilter};
+use rkyv::rancor;
+use rkyv::util::AlignedVec;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+
+/// FilterArchive holds the parameters and cells of a Stable Bloom Filter as
+/// they are archived by `StableBloomFilter::to_archived_bytes`. Its archived
+/// form is `ArchivedFilter`.
+#[derive(rkyv::Archive, rkyv::Serialize)]
+#[rkyv(archived = ArchivedFilter)]
+pub struct FilterArchive {
+    /// identifier of the hash scheme, see `HashScheme::id`
+    scheme: u8,
+    /// bits per cell
+    d: u8,
+    /// number of hash functions
+    k: u32,
+    /// number of cells to decrement
+    p: u64,
+    /// number of cells
+    m: u64,
+    /// target false-positive rate
+    fp_rate: f64,
+    /// FNV-1a hash kernel offset basis
+    key: u64,
+    /// cells laid out as returned by `Buckets::as_bytes`
+    cells: Vec<u8>,
+}
+
+impl StableBloomFilter {
+    /// Returns the filter's parameters and cells archived with rkyv, to be
+    /// accessed in place with `ArchivedFilter::from_bytes`.
+    pub fn to_archived_bytes(&self) -> AlignedVec {
+        let archive = FilterArchive {
+            scheme: self.hash_scheme().id(),
+            d: self.d(),
+            k: self.k() as u32,
+            p: self.p() as u64,
+            m: self.cells() as u64,
+            fp_rate: self.target_fp_rate(),
+            key: self.hasher().key(),
+            cells: self.buckets().as_bytes().to_vec(),
+        };
+        rkyv::to_bytes::<rancor::Error>(&archive).expect("archiving into memory doesn't fail")
+    }
+}
+
+impl ArchivedFilter {
+    /// Accesses the filter archived in bytes by `to_archived_bytes`, without
+    /// copying its cells. The bytes must be aligned to 16 bytes like the
+    /// returned `AlignedVec`, which memory-mapped files are. Returns an error
+    /// if the archive is malformed or its parameters are invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<&ArchivedFilter, SnapshotError> {
+        let archived = rkyv::access::<ArchivedFilter, rancor::Error>(bytes)
+            .map_err(|_| SnapshotError::Corrupted)?;
+        archived.validate()?;
+        Ok(archived)
+    }
+
+    /// Checks that the archived parameters describe a filter this crate can
+    /// test for membership.
+    fn validate(&self) -> Result<(), SnapshotError> {
+        match HashScheme::from_id(self.scheme) {
+            Some(HashScheme::FnvV1) | Some(HashScheme::FnvV2) => {}
+            _ => return Err(SnapshotError::UnsupportedHashScheme(self.scheme)),
+        }
+        if self.d == 0 || self.d > 8 {
+            return Err(SnapshotError::InvalidBucketSize(self.d));
+        }
+        if self.k == 0 {
+            return Err(SnapshotError::InvalidHashFunctions);
+        }
+        let m =
+            usize::try_from(self.m.to_native()).map_err(|_| SnapshotError::UnsupportedLayout)?;
+        usize::try_from(self.p.to_native()).map_err(|_| SnapshotError::UnsupportedLayout)?;
+        if m == 0
+            || m.checked_mul(usize::from(self.d)).is_none()
+            || self.cells.len() != expected_byte_len(m, self.d)
+        {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+        Ok(())
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not, exactly like the archived filter.
+    pub fn test(&self, data: &[u8]) -> bool {
+        let mut hasher = FnvBuildHasher::with_key(self.key.to_native()).build_hasher();
+        hasher.write(data);
+        let (lower, upper) = split_hash(hasher.finish());
+        let probe = self
+            .hash_scheme()
+            .probe_scheme()
+            .expect("FNV schemes have a probe scheme");
+
+        let m = self.cells();
+        (0..self.k())
+            .all(|i| bucket_value(&self.cells, location(probe, lower, upper, i, m), self.d) != 0)
+    }
+
+    /// Returns the number of cells.
+    pub fn cells(&self) -> usize {
+        self.m.to_native() as usize
+    }
+
+    /// Returns the number of bits per cell.
+    pub fn d(&self) -> u8 {
+        self.d
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k.to_native() as usize
+    }
+
+    /// Returns the number of cells decremented on every add.
+    pub fn p(&self) -> usize {
+        self.p.to_native() as usize
+    }
+
+    /// Returns the target false-positive rate the filter was created with.
+    pub fn target_fp_rate(&self) -> f64 {
+        self.fp_rate.to_native()
+    }
+
+    /// Returns the hash scheme of the archived filter.
+    pub fn hash_scheme(&self) -> HashScheme {
+        HashScheme::from_id(self.scheme).expect("the scheme was validated")
+    }
+
+    /// Returns a filter holding a copy of the archived cells, which can be
+    /// modified. The insert count starts at zero and runtime settings are
+    /// left at their defaults.
+    pub fn to_filter(&self) -> StableBloomFilter {
+        let cells = Buckets::from_raw_parts(self.cells.to_vec(), self.cells(), self.d)
+            .expect("the cells were validated");
+        let mut f = StableBloomFilter::from_parts(
+            cells,
+            FnvBuildHasher::with_key(self.key.to_native()),
+            self.k(),
+            self.p(),
+            self.target_fp_rate(),
+        );
+        f.set_probe_scheme(
+            self.hash_scheme()
+                .probe_scheme()
+                .expect("FNV schemes have a probe scheme"),
+        );
+        f
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchivedFilter, FilterArchive};
+    use crate::error::SnapshotError;
+    use crate::stable::{ProbeScheme, StableBloomFilter};
+    use crate::Filter;
+    use rkyv::rancor;
+    use rkyv::util::AlignedVec;
+
+    // Ensures that an archived filter tests like the filter it was archived
+    // from, for both probe schemes, and that its cells aren't copied.
+    #[test]
+    fn test_archived_filter() {
+        for probe in [
+            ProbeScheme::DoubleHashing,
+            ProbeScheme::EnhancedDoubleHashing,
+        ]
+        .iter()
+        {
+            let mut f = StableBloomFilter::new(10_000, 3, 0.01);
+            f.set_probe_scheme(*probe);
+            for i in 0..500 {
+                f.add(i.to_string().as_bytes());
+            }
+
+            let bytes = f.to_archived_bytes();
+            let archived = ArchivedFilter::from_bytes(&bytes).unwrap();
+            assert!(bytes.as_ptr_range().contains(&archived.cells.as_ptr()));
+            assert_eq!(archived.hash_scheme(), f.hash_scheme());
+            assert_eq!(archived.cells(), f.cells());
+            assert_eq!(archived.d(), f.d());
+            assert_eq!(archived.k(), f.k());
+            assert_eq!(archived.p(), f.p());
+            assert_eq!(archived.target_fp_rate(), 0.01);
+            for i in 0..1000 {
+                let data = i.to_string();
+                assert_eq!(archived.test(data.as_bytes()), f.test(data.as_bytes()));
+            }
+
+            let copy = archived.to_filter();
+            assert_eq!(copy.buckets(), f.buckets());
+            assert_eq!(copy.probe_scheme(), *probe);
+        }
+    }
+
+    // Ensures that archives of filters larger than 2^32 bits test and copy
+    // the cells past 2^32 bits like the filter they were archived from.
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_archived_filter_large() {
+        let m = (1usize << 30) + (1 << 28);
+        let mut f = StableBloomFilter::new(m, 4, 0.01);
+        let items: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        for data in items.iter() {
+            f.add(data.as_bytes());
+        }
+        assert!(items
+            .iter()
+            .flat_map(|data| f.hash_indices(data.as_bytes()).collect::<Vec<_>>())
+            .any(|i| i * 4 >= 1 << 32));
+
+        let bytes = f.to_archived_bytes();
+        // Safety: the bytes were just archived by `to_archived_bytes`. They
+        // aren't checked byte by byte like in `from_bytes`, which is slow for
+        // cells of this size in debug builds.
+        let archived = unsafe { rkyv::access_unchecked::<ArchivedFilter>(&bytes) };
+        archived.validate().unwrap();
+        for data in items.iter() {
+            assert!(archived.test(data.as_bytes()));
+        }
+
+        let copy = archived.to_filter();
+        drop(bytes);
+        assert_eq!(copy.buckets(), f.buckets());
+    }
+
+    // Ensures that malformed archives and archives with invalid parameters
+    // are rejected.
+    #[test]
+    fn test_invalid_archives() {
+        let f = StableBloomFilter::new(100, 2, 0.01);
+        let bytes = f.to_archived_bytes();
+        let mut bad = AlignedVec::<16>::new();
+        bad.extend_from_slice(&bytes[..bytes.len() - 1]);
+        match ArchivedFilter::from_bytes(&bad) {
+            Err(SnapshotError::Corrupted) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let archive = |scheme, d, k, m, cells| {
+            let archive = FilterArchive {
+                scheme,
+                d,
+                k,
+                p: 10,
+                m,
+                fp_rate: 0.01,
+                key: 0,
+                cells: vec![0; cells],
+            };
+            rkyv::to_bytes::<rancor::Error>(&archive).unwrap()
+        };
+        let cases = vec![
+            (
+                archive(3, 2, 3, 100, 25),
+                SnapshotError::UnsupportedHashScheme(3),
+            ),
+            (
+                archive(2, 9, 3, 100, 25),
+                SnapshotError::InvalidBucketSize(9),
+            ),
+            (
+                archive(2, 2, 0, 100, 25),
+                SnapshotError::InvalidHashFunctions,
+            ),
+            (archive(2, 2, 3, 100, 24), SnapshotError::InconsistentHeader),
+            (archive(2, 2, 3, 0, 0), SnapshotError::InconsistentHeader),
+        ];
+        for (bytes, expected) in cases {
+            match ArchivedFilter::from_bytes(&bytes) {
+                Err(err) => assert_eq!(err.to_string(), expected.to_string()),
+                Ok(_) => panic!("expected {:?}", expected),
+            }
+        }
+        assert!(ArchivedFilter::from_bytes(&archive(2, 2, 3, 100, 25)).is_ok());
+    }
+}
@@ -0,0 +1,199 @@
+//! sbf inspects and manipulates Stable Bloom Filter snapshots, in the format
+//! of the `serialization` module. Keys are read from stdin, one per line.
+
+use stable_bloom_filter::stable::StableBloomFilter;
+use stable_bloom_filter::Filter;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "usage:
+    sbf create <snapshot> --cells <m> [--bits <d>] [--fp <rate>]
+    sbf add <snapshot>
+    sbf test <snapshot>
+    sbf dedupe [--snapshot <snapshot>] [--cells <m>] [--bits <d>] [--fp <rate>]
+    sbf merge <output> <snapshot>...
+    sbf stats <snapshot>
+
+add, test and dedupe read keys from stdin, one per line. test prints
+whether each key is a member, dedupe prints the lines not seen before.";
+
+/// Default number of cells of a filter created by dedupe.
+const DEFAULT_CELLS: usize = 1 << 20;
+
+/// Default number of bits per cell.
+const DEFAULT_BITS: u8 = 1;
+
+/// Default target false-positive rate.
+const DEFAULT_FP: f64 = 0.01;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("sbf: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs the subcommand in args.
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => usage(),
+    };
+    let (positional, options) = parse(args)?;
+
+    match (command, positional.as_slice()) {
+        ("create", [path]) => {
+            let m = options.cells.ok_or("create needs --cells")?;
+            let f = StableBloomFilter::try_new(m, options.bits, options.fp)?;
+            save(&f, path)
+        }
+        ("add", [path]) => {
+            let mut f = load(path)?;
+            for key in keys()? {
+                f.add(&key?);
+            }
+            save(&f, path)
+        }
+        ("test", [path]) => {
+            let f = load(path)?;
+            let mut out = BufWriter::new(io::stdout().lock());
+            for key in keys()? {
+                let key = key?;
+                write!(out, "{}\t", f.test(&key))?;
+                out.write_all(&key)?;
+                out.write_all(b"\n")?;
+            }
+            Ok(out.flush()?)
+        }
+        ("dedupe", []) => {
+            let mut f = match options.snapshot {
+                Some(ref path) => load(path)?,
+                None => StableBloomFilter::try_new(
+                    options.cells.unwrap_or(DEFAULT_CELLS),
+                    options.bits,
+                    options.fp,
+                )?,
+            };
+            let mut out = BufWriter::new(io::stdout().lock());
+            for key in keys()? {
+                let key = key?;
+                if !f.test_and_add(&key) {
+                    out.write_all(&key)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            out.flush()?;
+            match options.snapshot {
+                Some(ref path) => save(&f, path),
+                None => Ok(()),
+            }
+        }
+        ("merge", [output, first, rest @ ..]) => {
+            let mut f = load(first)?;
+            for path in rest {
+                f.merge(&load(path)?)?;
+            }
+            save(&f, output)
+        }
+        ("stats", [path]) => {
+            let f = load(path)?;
+            println!("cells: {}", f.cells());
+            println!("bits per cell: {}", f.d());
+            println!("hash functions: {}", f.k());
+            println!("decrements per add: {}", f.p());
+            println!("target false-positive rate: {}", f.target_fp_rate());
+            println!("false-positive rate bound: {:.6}", f.false_positive_rate());
+            println!("fill ratio: {:.6}", f.fill_ratio());
+            println!("estimated count: {:.0}", f.estimated_count());
+            Ok(())
+        }
+        _ => usage(),
+    }
+}
+
+/// Options shared by the subcommands.
+struct Options {
+    cells: Option<usize>,
+    bits: u8,
+    fp: f64,
+    snapshot: Option<String>,
+}
+
+/// Splits args into positional arguments and options.
+fn parse(args: &[String]) -> Result<(Vec<&String>, Options), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut options = Options {
+        cells: None,
+        bits: DEFAULT_BITS,
+        fp: DEFAULT_FP,
+        snapshot: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--cells" => options.cells = Some(value.parse()?),
+            "--bits" => options.bits = value.parse()?,
+            "--fp" => options.fp = value.parse()?,
+            "--snapshot" => options.snapshot = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg).into()),
+        }
+    }
+    Ok((positional, options))
+}
+
+/// Returns the keys read from stdin, without their line endings.
+fn keys() -> io::Result<impl Iterator<Item = io::Result<Vec<u8>>>> {
+    Ok(io::stdin().lock().split(b'\n').map(|line| {
+        line.map(|mut key| {
+            if key.last() == Some(&b'\r') {
+                key.pop();
+            }
+            key
+        })
+    }))
+}
+
+/// Reads the snapshot at path.
+fn load(path: &str) -> Result<StableBloomFilter, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    StableBloomFilter::read_from(BufReader::new(file))
+        .map_err(|err| format!("{}: {}", path, err).into())
+}
+
+/// Writes a snapshot of the filter to path. The snapshot is written to a
+/// temporary file next to path, which then replaces path, so that path holds
+/// either the previous or the new snapshot if writing fails midway.
+fn save(f: &StableBloomFilter, path: &str) -> Result<(), Box<dyn Error>> {
+    let tmp = format!("{}.{}.tmp", path, process::id());
+    let result = write_snapshot(f, &tmp).and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.map_err(|err| format!("{}: {}", path, err).into())
+}
+
+/// Writes a snapshot of the filter to a new file at path and syncs it to
+/// disk.
+fn write_snapshot(f: &StableBloomFilter, path: &str) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    f.write_to(&mut w)?;
+    let file = w.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()
+}
+
+/// Prints the usage and exits.
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
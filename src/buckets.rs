@@ -1,7 +1,27 @@
+use crate::error::SbfError;
+#[cfg(feature = "mmap")]
+use memmap2::MmapMut;
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "mmap")]
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+/// Returns the number of bytes needed to store m buckets of bucket_size bits.
+pub fn expected_byte_len(m: usize, bucket_size: u8) -> usize {
+    (m * usize::from(bucket_size)).div_ceil(8)
+}
+
 /// Buckets is a fast, space-efficient array of buckets where each bucket can
 /// store up to a configured maximum value.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "BucketsState"))]
 pub struct Buckets {
-    data: Vec<u8>,
+    data: Storage,
     bucket_size: u8,
     max: u8,
     count: usize,
@@ -17,11 +37,114 @@ impl Buckets {
         Buckets {
             count,
             bucket_size,
-            data: vec![0; (count * usize::from(bucket_size) + 7) / 8],
+            data: Storage::Heap(vec![0; expected_byte_len(count, bucket_size)]),
             max: ((1u16 << u16::from(bucket_size)) - 1) as u8,
         }
     }
 
+    /// Creates a new Buckets like `new`, but returns an error instead of
+    /// panicking if the bucket size isn't between 1 and 8 bits.
+    pub fn try_new(count: usize, bucket_size: u8) -> Result<Self, SbfError> {
+        if bucket_size == 0 || bucket_size > 8 {
+            return Err(SbfError::InvalidBucketSize(bucket_size));
+        }
+        Ok(Self::new(count, bucket_size))
+    }
+
+    /// Creates a new Buckets with the provided number of buckets of the
+    /// specified number of bits, backed by data laid out as returned by
+    /// `as_bytes`. The data is used as is, without copying. Returns an error
+    /// if the bucket size isn't between 1 and 8 bits or the length of data
+    /// doesn't match the number of buckets.
+    pub fn from_raw_parts(data: Vec<u8>, count: usize, bucket_size: u8) -> Result<Self, SbfError> {
+        if bucket_size == 0 || bucket_size > 8 {
+            return Err(SbfError::InvalidBucketSize(bucket_size));
+        }
+        if count.checked_mul(usize::from(bucket_size)).is_none() {
+            return Err(SbfError::InconsistentState("too many buckets"));
+        }
+        let expected = expected_byte_len(count, bucket_size);
+        if data.len() != expected {
+            return Err(SbfError::DataLength {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        let mut b = Self::new(0, bucket_size);
+        b.count = count;
+        b.data = Storage::Heap(data);
+        Ok(b)
+    }
+
+    /// Creates a new Buckets with the provided number of buckets of the
+    /// specified number of bits, backed by the file at path which is mapped
+    /// into memory. A missing or empty file is created and zero-filled, an
+    /// existing file keeps its buckets, so the buckets survive restarts
+    /// without being serialized. Changes are written back to the file by the
+    /// operating system, or explicitly with `flush`. Returns an error if the
+    /// file can't be mapped or its length doesn't match the number of buckets.
+    ///
+    /// The file must not be modified by other processes or mappings while the
+    /// buckets are in use.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P, count: usize, bucket_size: u8) -> io::Result<Self> {
+        if count.checked_mul(usize::from(bucket_size)).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                SbfError::InconsistentState("too many buckets"),
+            ));
+        }
+        let expected = expected_byte_len(count, bucket_size);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.set_len(expected as u64)?;
+        } else if len != expected as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                SbfError::DataLength {
+                    expected,
+                    actual: len as usize,
+                },
+            ));
+        }
+
+        // Safety: the file is opened for reading and writing and has the
+        // expected length. Callers must not modify it concurrently, as
+        // documented above.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        let mut b = Self::new(0, bucket_size);
+        b.count = count;
+        b.data = Storage::Mmap(map);
+        Ok(b)
+    }
+
+    /// Writes outstanding changes of memory-mapped buckets back to their file.
+    /// Does nothing for buckets held in memory.
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.data {
+            Storage::Heap(_) => Ok(()),
+            #[cfg(feature = "mmap")]
+            Storage::Mmap(map) => map.flush(),
+        }
+    }
+
+    /// Returns the packed bucket data, which is `expected_byte_len` bytes
+    /// long. Buckets are stored back to back, least significant bit first, so
+    /// that bucket i occupies bits i * bucket_size to (i + 1) * bucket_size - 1
+    /// and a bucket may span two bytes. Unused bits of the last byte are zero.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     /// Returns the maximum value that can be stored in a bucket.
     pub fn max_bucket_value(&self) -> u8 {
         self.max
@@ -32,6 +155,11 @@ impl Buckets {
         self.count
     }
 
+    /// Returns the number of bits allocated per bucket.
+    pub fn bucket_size(&self) -> u8 {
+        self.bucket_size
+    }
+
     /// Decrease the value in the specified bucket by the provided delta.
     /// The value is clamped to zero and the maximum bucket value.
     /// Returns itself to allow for chaining.
@@ -41,7 +169,7 @@ impl Buckets {
             .saturating_sub(delta);
 
         self.set_bits(
-            (bucket as u32) * u32::from(self.bucket_size),
+            bucket * usize::from(self.bucket_size),
             self.bucket_size,
             val,
         );
@@ -58,7 +186,7 @@ impl Buckets {
             .min(self.max);
 
         self.set_bits(
-            (bucket as u32) * u32::from(self.bucket_size),
+            bucket * usize::from(self.bucket_size),
             self.bucket_size,
             val,
         );
@@ -66,72 +194,491 @@ impl Buckets {
     }
 
     /// Set the bucket value. The value is clamped to zero and the maximum
-    /// bucket value. Returns itself to allow for chaining.
+    /// bucket value. Returns itself to allow for chaining. Panics if the
+    /// bucket is out of range.
     #[inline]
     pub fn set(&mut self, bucket: usize, value: u8) -> &Self {
+        self.check_bucket(bucket);
         let value = value.min(self.max);
 
         self.set_bits(
-            (bucket as u32) * u32::from(self.bucket_size),
+            bucket * usize::from(self.bucket_size),
             self.bucket_size,
             value,
         );
         self
     }
 
+    /// Sets every specified bucket to the maximum bucket value. For 1-bit
+    /// buckets, the bits falling into the same byte are set with a single
+    /// write, so sorted indices are the fastest to load. Returns itself to
+    /// allow for chaining. Panics if a bucket is out of range.
+    pub fn set_bits_bulk(&mut self, indices: &[usize]) -> &Self {
+        if self.bucket_size != 1 {
+            for i in indices.iter() {
+                self.set(*i, self.max);
+            }
+            return self;
+        }
+
+        let mut iter = indices.iter();
+        if let Some(first) = iter.next() {
+            self.check_bucket(*first);
+            let mut byte_index = first / 8;
+            let mut mask = 1u8 << (first % 8);
+            for i in iter {
+                self.check_bucket(*i);
+                if i / 8 != byte_index {
+                    self.data[byte_index] |= mask;
+                    byte_index = i / 8;
+                    mask = 0;
+                }
+                mask |= 1 << (i % 8);
+            }
+            self.data[byte_index] |= mask;
+        }
+        self
+    }
+
     /// Returns the value in the specified bucket.
     #[inline]
     pub fn get(&self, bucket: usize) -> u8 {
-        self.get_bits(bucket * usize::from(self.bucket_size), self.bucket_size) as u8
+        bucket_value(&self.data, bucket, self.bucket_size)
+    }
+
+    /// Returns true if the buckets at all of the indices are non-zero. With
+    /// the simd feature, 1-bit and 8-bit buckets are loaded with vector
+    /// gathers on CPUs which support them.
+    #[cfg(feature = "simd")]
+    #[inline]
+    pub(crate) fn all_nonzero(&self, indices: &[usize]) -> bool {
+        #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+        {
+            if let Some(all) = crate::simd::all_nonzero(&self.data, self.bucket_size, indices) {
+                return all;
+            }
+        }
+        indices.iter().all(|i| self.get(*i) != 0)
+    }
+
+    /// Returns an iterator over the bucket values, in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            buckets: self,
+            offset: 0,
+            end: self.count * usize::from(self.bucket_size),
+        }
+    }
+
+    /// Returns an iterator over the `(index, value)` pairs of the non-zero
+    /// buckets, in order.
+    pub fn iter_nonzero(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.iter().enumerate().filter(|(_, value)| *value != 0)
     }
 
     /// Reset restores the Buckets to the original state.
     /// Returns itself to allow for chaining.
     pub fn reset(&mut self) -> &Self {
-        self.data = vec![0; (self.count * usize::from(self.bucket_size) + 7) / 8];
+        self.data.fill(0);
         self
     }
 
+    /// Panics if the bucket is out of range. Buckets past the end may still
+    /// fall into the last byte, whose unused bits must stay zero.
+    #[inline]
+    fn check_bucket(&self, bucket: usize) {
+        assert!(
+            bucket < self.count,
+            "bucket index {} out of range for {} buckets",
+            bucket,
+            self.count
+        );
+    }
+
     /// Returns the bits at the specified offset and length.
     #[inline]
     fn get_bits(&self, offset: usize, length: u8) -> u32 {
-        let byte_index = offset / 8;
-        let byte_offset = offset % 8;
-        if byte_offset as u8 + length > 8 {
-            let rem = 8 - byte_offset as u8;
-            return self.get_bits(offset, rem)
-                | (self.get_bits(offset + rem as usize, length - rem) << rem);
-        }
-
-        let bit_mask = (1 << length) - 1;
-        (u32::from(self.data[byte_index as usize]) & (bit_mask << byte_offset) as u32)
-            >> byte_offset
+        get_bits(&self.data, offset, length)
     }
 
     /// setBits sets bits at the specified offset and length.
     #[inline]
-    fn set_bits(&mut self, offset: u32, length: u8, bits: u8) {
+    fn set_bits(&mut self, offset: usize, length: u8, bits: u8) {
         let byte_index = offset / 8;
         let byte_offset = offset % 8;
         if byte_offset as u8 + length > 8 {
             let rem = 8 - byte_offset as u8;
             self.set_bits(offset, rem, bits);
-            self.set_bits(offset + u32::from(rem), length - rem, bits >> rem);
+            self.set_bits(offset + usize::from(rem), length - rem, bits >> rem);
             return;
         }
 
         let bit_mask: u32 = (1 << length) - 1;
-        self.data[byte_index as usize] =
-            (u32::from(self.data[byte_index as usize]) & !(bit_mask << byte_offset)) as u8;
-        self.data[byte_index as usize] = (u32::from(self.data[byte_index as usize])
-            | ((u32::from(bits) & bit_mask) << byte_offset))
-            as u8;
+        self.data[byte_index] =
+            (u32::from(self.data[byte_index]) & !(bit_mask << byte_offset)) as u8;
+        self.data[byte_index] = (u32::from(self.data[byte_index])
+            | ((u32::from(bits) & bit_mask) << byte_offset)) as u8;
+    }
+}
+
+/// Clones are always held on the heap, also when cloning memory-mapped
+/// buckets.
+impl Clone for Buckets {
+    fn clone(&self) -> Self {
+        Buckets {
+            data: Storage::Heap(self.data.to_vec()),
+            bucket_size: self.bucket_size,
+            max: self.max,
+            count: self.count,
+        }
+    }
+}
+
+/// Buckets are equal if they have the same number and size of buckets and
+/// the same values, regardless of where they are stored.
+impl PartialEq for Buckets {
+    fn eq(&self, other: &Self) -> bool {
+        self.count == other.count
+            && self.bucket_size == other.bucket_size
+            && self.data[..] == other.data[..]
+    }
+}
+
+// Implemented by hand to leave out the bucket data, which may be large.
+impl fmt::Debug for Buckets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Buckets")
+            .field("count", &self.count)
+            .field("bucket_size", &self.bucket_size)
+            .field("max", &self.max)
+            .finish()
+    }
+}
+
+impl<'a> IntoIterator for &'a Buckets {
+    type Item = u8;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Iter yields the values of Buckets in order. It advances a bit offset
+/// rather than computing it from the bucket index for every value.
+pub struct Iter<'a> {
+    buckets: &'a Buckets,
+    /// bit offset of the next bucket
+    offset: usize,
+    /// bit offset past the last bucket
+    end: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let bucket_size = self.buckets.bucket_size;
+        let value = self.buckets.get_bits(self.offset, bucket_size) as u8;
+        self.offset += usize::from(bucket_size);
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.end - self.offset) / usize::from(self.buckets.bucket_size.max(1));
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+/// WideBuckets is a packed array of buckets like `Buckets`, whose buckets can
+/// be up to 32 bits wide, so that counters don't saturate at 255.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "WideBucketsState"))]
+pub struct WideBuckets {
+    data: Vec<u8>,
+    bucket_size: u8,
+    max: u32,
+    count: usize,
+}
+
+impl WideBuckets {
+    /// Creates a new WideBuckets with the provided number of buckets where
+    /// each bucket is the specified number of bits, between 1 and 32.
+    pub fn new(count: usize, bucket_size: u8) -> Self {
+        if bucket_size == 0 || bucket_size > 32 {
+            panic!("bucket_size must be between 1 and 32");
+        }
+        WideBuckets {
+            count,
+            bucket_size,
+            data: vec![0; expected_byte_len(count, bucket_size)],
+            max: ((1u64 << bucket_size) - 1) as u32,
+        }
+    }
+
+    /// Creates a new WideBuckets like `new`, but returns an error instead of
+    /// panicking if the bucket size isn't between 1 and 32 bits.
+    pub fn try_new(count: usize, bucket_size: u8) -> Result<Self, SbfError> {
+        if bucket_size == 0 || bucket_size > 32 {
+            return Err(SbfError::InvalidBucketSize(bucket_size));
+        }
+        Ok(Self::new(count, bucket_size))
+    }
+
+    /// Returns the maximum value that can be stored in a bucket.
+    pub fn max_bucket_value(&self) -> u32 {
+        self.max
+    }
+
+    /// Returns the number of buckets.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of bits per bucket.
+    pub fn bucket_size(&self) -> u8 {
+        self.bucket_size
+    }
+
+    /// Returns the value in the specified bucket. Panics if the bucket is
+    /// out of range.
+    #[inline]
+    pub fn get(&self, bucket: usize) -> u32 {
+        self.check_bucket(bucket);
+        let (byte_index, shift) = self.position(bucket);
+        ((self.read_word(byte_index) >> shift) & u64::from(self.max)) as u32
+    }
+
+    /// Set the bucket value. The value is clamped to the maximum bucket
+    /// value. Returns itself to allow for chaining. Panics if the bucket is
+    /// out of range.
+    #[inline]
+    pub fn set(&mut self, bucket: usize, value: u32) -> &Self {
+        self.check_bucket(bucket);
+        let (byte_index, shift) = self.position(bucket);
+        let mask = u64::from(self.max) << shift;
+        let word = self.read_word(byte_index);
+        let value = u64::from(value.min(self.max)) << shift;
+        self.write_word(byte_index, (word & !mask) | value);
+        self
+    }
+
+    /// Increment the value in the specified bucket by the provided delta.
+    /// The value is clamped to the maximum bucket value. Returns itself to
+    /// allow for chaining.
+    #[inline]
+    pub fn increment(&mut self, bucket: usize, delta: u32) -> &Self {
+        let value = self.get(bucket).saturating_add(delta);
+        self.set(bucket, value)
+    }
+
+    /// Decrease the value in the specified bucket by the provided delta.
+    /// The value is clamped to zero. Returns itself to allow for chaining.
+    #[inline]
+    pub fn decrease(&mut self, bucket: usize, delta: u32) -> &Self {
+        let value = self.get(bucket).saturating_sub(delta);
+        self.set(bucket, value)
+    }
+
+    /// Reset restores the WideBuckets to the original state.
+    /// Returns itself to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.data.fill(0);
+        self
+    }
+
+    /// Panics if the bucket is out of range.
+    #[inline]
+    fn check_bucket(&self, bucket: usize) {
+        assert!(
+            bucket < self.count,
+            "bucket index {} out of range for {} buckets",
+            bucket,
+            self.count
+        );
+    }
+
+    /// Returns the index of the first byte holding the bucket and the
+    /// offset of the bucket within that byte.
+    #[inline]
+    fn position(&self, bucket: usize) -> (usize, usize) {
+        let offset = bucket * usize::from(self.bucket_size);
+        (offset / 8, offset % 8)
+    }
+
+    /// Returns the up to eight bytes starting at byte_index as a
+    /// little-endian word. A bucket of at most 32 bits starting at any bit
+    /// of the first byte lies within the first five.
+    #[inline]
+    fn read_word(&self, byte_index: usize) -> u64 {
+        let end = (byte_index + 8).min(self.data.len());
+        let mut bytes = [0u8; 8];
+        bytes[..end - byte_index].copy_from_slice(&self.data[byte_index..end]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Writes back the bytes read by `read_word`.
+    #[inline]
+    fn write_word(&mut self, byte_index: usize, word: u64) {
+        let end = (byte_index + 8).min(self.data.len());
+        self.data[byte_index..end].copy_from_slice(&word.to_le_bytes()[..end - byte_index]);
+    }
+}
+
+/// Returns the value of the bucket in data laid out as returned by
+/// `Buckets::as_bytes`, with buckets of bucket_size bits.
+#[inline]
+pub(crate) fn bucket_value(data: &[u8], bucket: usize, bucket_size: u8) -> u8 {
+    // Byte-aligned and single-bit buckets are loaded directly.
+    match bucket_size {
+        8 => data[bucket],
+        1 => (data[bucket / 8] >> (bucket % 8)) & 1,
+        _ => get_bits(data, bucket * usize::from(bucket_size), bucket_size) as u8,
+    }
+}
+
+/// Returns the bits of data at the specified offset and length.
+#[inline]
+fn get_bits(data: &[u8], offset: usize, length: u8) -> u32 {
+    let byte_index = offset / 8;
+    let byte_offset = offset % 8;
+    if byte_offset as u8 + length > 8 {
+        let rem = 8 - byte_offset as u8;
+        return get_bits(data, offset, rem)
+            | (get_bits(data, offset + rem as usize, length - rem) << rem);
+    }
+
+    let bit_mask = (1 << length) - 1;
+    (u32::from(data[byte_index]) & (bit_mask << byte_offset) as u32) >> byte_offset
+}
+
+/// Memory backing the buckets.
+enum Storage {
+    /// buckets held on the heap
+    Heap(Vec<u8>),
+    /// buckets held in a memory-mapped file
+    #[cfg(feature = "mmap")]
+    Mmap(MmapMut),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Heap(data) => data,
+            #[cfg(feature = "mmap")]
+            Storage::Mmap(map) => map,
+        }
+    }
+}
+
+impl DerefMut for Storage {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Storage::Heap(data) => data,
+            #[cfg(feature = "mmap")]
+            Storage::Mmap(map) => map,
+        }
+    }
+}
+
+// Memory-mapped buckets are serialized like buckets held in memory, and are
+// always deserialized into memory.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Storage {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Serialized form of `Buckets`, which is validated before the buckets are
+/// restored from it.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct BucketsState {
+    data: Vec<u8>,
+    bucket_size: u8,
+    max: u8,
+    count: usize,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<BucketsState> for Buckets {
+    type Error = SbfError;
+
+    fn try_from(state: BucketsState) -> Result<Self, SbfError> {
+        let b = Buckets::from_raw_parts(state.data, state.count, state.bucket_size)?;
+        if state.max != b.max {
+            return Err(SbfError::InconsistentState(
+                "maximum value doesn't match the bucket size",
+            ));
+        }
+        Ok(b)
+    }
+}
+
+/// Serialized form of `WideBuckets`, which is validated before the buckets
+/// are restored from it.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct WideBucketsState {
+    data: Vec<u8>,
+    bucket_size: u8,
+    max: u32,
+    count: usize,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<WideBucketsState> for WideBuckets {
+    type Error = SbfError;
+
+    fn try_from(state: WideBucketsState) -> Result<Self, SbfError> {
+        if state.bucket_size == 0 || state.bucket_size > 32 {
+            return Err(SbfError::InvalidBucketSize(state.bucket_size));
+        }
+        if state
+            .count
+            .checked_mul(usize::from(state.bucket_size))
+            .is_none()
+        {
+            return Err(SbfError::InconsistentState("too many buckets"));
+        }
+        let expected = expected_byte_len(state.count, state.bucket_size);
+        if state.data.len() != expected {
+            return Err(SbfError::DataLength {
+                expected,
+                actual: state.data.len(),
+            });
+        }
+        let max = ((1u64 << state.bucket_size) - 1) as u32;
+        if state.max != max {
+            return Err(SbfError::InconsistentState(
+                "maximum value doesn't match the bucket size",
+            ));
+        }
+        Ok(WideBuckets {
+            data: state.data,
+            bucket_size: state.bucket_size,
+            max,
+            count: state.count,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Buckets;
+    use super::{expected_byte_len, Buckets, WideBuckets};
+    use crate::error::SbfError;
 
     // Ensures that MaxBucketValue returns the correct maximum based on the bucket
     // size.
@@ -212,4 +759,275 @@ mod tests {
             assert_eq!(b.get(i), 0);
         }
     }
+
+    // Ensures that SetBitsBulk produces the same state as setting every bucket
+    // to the maximum value one at a time.
+    #[test]
+    fn test_buckets_set_bits_bulk() {
+        for &bucket_size in [1, 3].iter() {
+            let indices = [0, 1, 7, 8, 9, 3, 63, 64, 2, 99];
+            let mut bulk = Buckets::new(100, bucket_size);
+            let mut single = Buckets::new(100, bucket_size);
+
+            bulk.set_bits_bulk(&indices);
+            for i in indices.iter() {
+                single.set(*i, single.max_bucket_value());
+            }
+
+            for i in 0..100 {
+                assert_eq!(bulk.get(i), single.get(i));
+            }
+        }
+    }
+
+    // Ensures that SetBitsBulk rejects indices past the last bucket which
+    // still fall into the last byte.
+    #[test]
+    #[should_panic(expected = "bucket index 12 out of range")]
+    fn test_buckets_set_bits_bulk_out_of_range() {
+        let mut b = Buckets::new(10, 1);
+        b.set_bits_bulk(&[0, 9, 12]);
+    }
+
+    // Ensures that Set rejects indices past the last bucket.
+    #[test]
+    #[should_panic(expected = "bucket index 10 out of range")]
+    fn test_buckets_set_out_of_range() {
+        let mut b = Buckets::new(10, 3);
+        b.set(10, 1);
+    }
+
+    // Ensures that expected_byte_len returns the number of bytes needed to
+    // store the buckets.
+    #[test]
+    fn test_expected_byte_len() {
+        assert_eq!(expected_byte_len(0, 1), 0);
+        assert_eq!(expected_byte_len(8, 1), 1);
+        assert_eq!(expected_byte_len(9, 1), 2);
+        assert_eq!(expected_byte_len(10, 3), 4);
+        assert_eq!(expected_byte_len(100, 8), 100);
+        assert_eq!(expected_byte_len(100, 3), Buckets::new(100, 3).data.len());
+    }
+
+    // Ensures that FromRawParts restores the buckets from their data and
+    // rejects data of the wrong length.
+    #[test]
+    fn test_buckets_from_raw_parts() {
+        let mut b = Buckets::new(10, 3);
+        b.set(0, 5);
+        b.set(9, 7);
+
+        assert_eq!(b.as_bytes(), &[0b0000_0101, 0, 0, 0b0011_1000][..]);
+        let restored = Buckets::from_raw_parts(b.as_bytes().to_vec(), 10, 3).unwrap();
+        for i in 0..10 {
+            assert_eq!(restored.get(i), b.get(i));
+        }
+        assert_eq!(restored.count(), 10);
+
+        assert_eq!(
+            Buckets::from_raw_parts(vec![0; 3], 10, 3).err(),
+            Some(SbfError::DataLength {
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert!(Buckets::from_raw_parts(vec![0; 5], 10, 3).is_err());
+        assert_eq!(
+            Buckets::from_raw_parts(vec![0; 10], 10, 9).err(),
+            Some(SbfError::InvalidBucketSize(9))
+        );
+    }
+
+    // Ensures that TryNew returns an error for invalid bucket sizes instead
+    // of panicking.
+    #[test]
+    fn test_try_new() {
+        assert_eq!(Buckets::try_new(10, 8).unwrap().max_bucket_value(), 255);
+        assert_eq!(
+            Buckets::try_new(10, 0).err(),
+            Some(SbfError::InvalidBucketSize(0))
+        );
+        assert_eq!(
+            Buckets::try_new(10, 9).err(),
+            Some(SbfError::InvalidBucketSize(9))
+        );
+    }
+
+    // Ensures that memory-mapped buckets keep their values across reopening
+    // the file and reject files of the wrong length.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_buckets_open_mmap() {
+        let path = std::env::temp_dir().join(format!("sbf-buckets-{}.mmap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut b = Buckets::open_mmap(&path, 100, 3).unwrap();
+            assert_eq!(b.count(), 100);
+            assert_eq!(b.get(42), 0);
+            b.set(42, 5);
+            b.set(99, 7);
+            b.flush().unwrap();
+        }
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            expected_byte_len(100, 3) as u64
+        );
+
+        {
+            let mut b = Buckets::open_mmap(&path, 100, 3).unwrap();
+            assert_eq!(b.get(42), 5);
+            assert_eq!(b.get(99), 7);
+            b.reset();
+            assert_eq!(b.get(42), 0);
+        }
+
+        assert!(Buckets::open_mmap(&path, 200, 3).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Ensures that buckets past 2^32 bits of a memory-mapped file are written
+    // where they're read, without wrapping onto the start of the file.
+    #[cfg(all(feature = "mmap", target_pointer_width = "64"))]
+    #[test]
+    fn test_buckets_open_mmap_large() {
+        let path =
+            std::env::temp_dir().join(format!("sbf-buckets-large-{}.mmap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let count = (1usize << 32) / 3 + 100;
+        let last = count - 1;
+        let wrapped = (last * 3 - (1usize << 32)) / 3;
+        {
+            let mut b = Buckets::open_mmap(&path, count, 3).unwrap();
+            b.set(last, 5);
+            b.increment(last - 1, 3);
+            b.set(last - 2, 7);
+            b.decrease(last - 2, 1);
+            assert_eq!(b.get(last), 5);
+            assert_eq!(b.get(last - 1), 3);
+            assert_eq!(b.get(last - 2), 6);
+            assert_eq!(b.get(wrapped), 0);
+            b.flush().unwrap();
+        }
+        {
+            let b = Buckets::open_mmap(&path, count, 3).unwrap();
+            assert_eq!(b.get(last), 5);
+            assert_eq!(b.get(wrapped), 0);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Ensures that wide buckets store values of up to 32 bits, including
+    // buckets straddling several bytes and at the end of the data.
+    #[test]
+    fn test_wide_buckets() {
+        for &bucket_size in [1u8, 5, 8, 13, 16, 27, 32].iter() {
+            let mut b = WideBuckets::new(50, bucket_size);
+            let max = b.max_bucket_value();
+            assert_eq!(u64::from(max), (1u64 << bucket_size) - 1);
+
+            for i in 0..50 {
+                b.set(i, (i as u32).wrapping_mul(2_654_435_761));
+            }
+            for i in 0..50 {
+                assert_eq!(b.get(i), (i as u32).wrapping_mul(2_654_435_761).min(max));
+            }
+
+            b.reset();
+            b.increment(49, 300);
+            assert_eq!(b.get(49), 300u32.min(max));
+            assert_eq!(b.get(48), 0);
+            b.increment(49, u32::MAX);
+            assert_eq!(b.get(49), max);
+            b.decrease(49, u32::MAX);
+            assert_eq!(b.get(49), 0);
+        }
+
+        assert_eq!(
+            WideBuckets::try_new(10, 33).err(),
+            Some(SbfError::InvalidBucketSize(33))
+        );
+    }
+
+    // Ensures that wide buckets reject buckets past the end, also when they
+    // fall into the last bytes of the data.
+    #[test]
+    #[should_panic(expected = "bucket index 10 out of range for 10 buckets")]
+    fn test_wide_buckets_out_of_range() {
+        let mut b = WideBuckets::new(10, 13);
+        b.set(10, 1);
+    }
+
+    // Ensures that serialized wide buckets whose fields contradict each other
+    // are rejected instead of being restored.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_wide_buckets_serde_invalid_state() {
+        let mut b = WideBuckets::new(10, 13);
+        b.set(9, 1000);
+        let state = serde_json::to_value(&b).unwrap();
+
+        let cases: Vec<(&str, serde_json::Value)> = vec![
+            ("bucket_size", 33.into()),
+            ("bucket_size", 0.into()),
+            ("max", 255.into()),
+            ("count", 11.into()),
+            ("count", usize::MAX.into()),
+            ("data", vec![0u8; 16].into()),
+        ];
+        for (field, value) in cases {
+            let mut bad = state.clone();
+            bad[field] = value;
+            let restored: Result<WideBuckets, _> = serde_json::from_value(bad);
+            assert!(restored.is_err(), "{} wasn't validated", field);
+        }
+
+        let restored: WideBuckets = serde_json::from_value(state).unwrap();
+        assert_eq!(restored, b);
+        assert_eq!(restored.get(9), 1000);
+    }
+
+    // Ensures that the iterators yield the bucket values in order.
+    #[test]
+    fn test_iter() {
+        for &bucket_size in [1u8, 3, 8].iter() {
+            let mut b = Buckets::new(20, bucket_size);
+            b.set(0, 1);
+            b.set(7, 2);
+            b.set(19, 255);
+
+            let values: Vec<u8> = b.iter().collect();
+            assert_eq!(values.len(), 20);
+            assert_eq!(b.iter().len(), 20);
+            for (i, value) in values.iter().enumerate() {
+                assert_eq!(*value, b.get(i));
+            }
+            assert_eq!((&b).into_iter().count(), 20);
+
+            let nonzero: Vec<(usize, u8)> = b.iter_nonzero().collect();
+            let expected: Vec<(usize, u8)> = [0, 7, 19]
+                .iter()
+                .map(|i| (*i, b.get(*i)))
+                .filter(|(_, value)| *value != 0)
+                .collect();
+            assert_eq!(nonzero, expected);
+        }
+    }
+
+    // Ensures that cloned buckets are equal and independent.
+    #[test]
+    fn test_clone_and_eq() {
+        let mut b = Buckets::new(10, 3);
+        b.set(4, 6);
+        let mut c = b.clone();
+        assert_eq!(c, b);
+
+        c.set(4, 5);
+        assert_ne!(c, b);
+        assert_eq!(b.get(4), 6);
+        assert_ne!(Buckets::new(10, 3), Buckets::new(10, 4));
+        assert_ne!(Buckets::new(10, 3), Buckets::new(11, 3));
+    }
 }
@@ -0,0 +1,241 @@
+use crate::buckets::WideBuckets;
+use crate::fnv::FnvHasher;
+use crate::stable::split_hash;
+use crate::{optimal_k, optimal_m, Filter};
+use std::hash::Hasher;
+
+/// CountingBloomFilter implements a Counting Bloom Filter as described by Fan,
+/// Cao, Almeida, and Broder in Summary Cache: A Scalable Wide-Area Web Cache
+/// Sharing Protocol:
+///
+/// http://pages.cs.wisc.edu/~jussara/papers/00ton.pdf
+///
+/// A Counting Bloom Filter (CBF) provides a way to remove elements by using an
+/// array of n-bit buckets. When an element is added, the respective buckets
+/// are incremented. To remove an element, the respective buckets are
+/// decremented. A query checks that each of the respective buckets are
+/// non-zero. Because CBFs allow elements to be removed, they introduce a
+/// non-zero probability of false negatives in addition to the possibility of
+/// false positives.
+///
+/// Counters saturate at their maximum value. Removing an element whose
+/// counters saturated may cause false negatives for other elements, so the
+/// bucket size, of up to 32 bits, should leave room for the expected number
+/// of collisions and duplicate adds; 4 bits is enough for most uses.
+pub struct CountingBloomFilter {
+    /// filter data
+    buckets: WideBuckets,
+    /// hash function (kernel for all k functions)
+    hash: FnvHasher,
+    /// filter size
+    m: usize,
+    /// number of hash functions
+    k: usize,
+    /// number of items in the filter
+    count: usize,
+    /// buffer used to cache indices
+    index_buffer: Vec<usize>,
+}
+
+impl CountingBloomFilter {
+    /// Creates a new Counting Bloom Filter optimized to store n items with a
+    /// specified target false-positive rate and bucket size. If you don't know
+    /// how many bits to use for buckets, use `new_default`.
+    pub fn new(n: usize, b: u8, fp_rate: f64) -> Self {
+        let m = optimal_m(n, fp_rate).max(1);
+        let k = optimal_k(fp_rate).max(1);
+
+        CountingBloomFilter {
+            buckets: WideBuckets::new(m, b),
+            hash: FnvHasher::default(),
+            m,
+            k,
+            count: 0,
+            index_buffer: vec![0; k],
+        }
+    }
+
+    /// Creates a new Counting Bloom Filter optimized to store n items with a
+    /// specified target false-positive rate. Buckets are allocated four bits.
+    pub fn new_default(n: usize, fp_rate: f64) -> Self {
+        Self::new(n, 4, fp_rate)
+    }
+
+    /// Returns the Bloom filter capacity, m.
+    pub fn capacity(&self) -> usize {
+        self.m
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of items in the filter.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Will test for membership of the data and remove it from the filter if
+    /// it exists. Returns true if the data was a member, false if not.
+    pub fn test_and_remove(&mut self, data: &[u8]) -> bool {
+        self.fill_index_buffer(data);
+        let member = self.index_buffer.iter().all(|i| self.buckets.get(*i) != 0);
+
+        if member {
+            for i in self.index_buffer.iter() {
+                self.buckets.decrease(*i, 1);
+            }
+            self.count -= 1;
+        }
+        member
+    }
+
+    /// Restores the Counting Bloom Filter to its original state. It returns
+    /// the filter to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.buckets.reset();
+        self.count = 0;
+        self
+    }
+
+    /// Caches the k bucket indices of the data.
+    fn fill_index_buffer(&mut self, data: &[u8]) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        let (lower, upper) = split_hash(hasher.finish());
+
+        for i in 0..(self.k) {
+            self.index_buffer[i] = (lower as usize + upper as usize * i) % self.m;
+        }
+    }
+}
+
+impl Filter for CountingBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives and false negatives.
+    fn test(&self, data: &[u8]) -> bool {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        let (lower, upper) = split_hash(hasher.finish());
+
+        (0..(self.k)).all(|i| {
+            self.buckets
+                .get((lower as usize + upper as usize * i) % self.m)
+                != 0
+        })
+    }
+
+    /// Will add the data to the Counting Bloom Filter. It returns the filter
+    /// to allow for chaining.
+    fn add(&mut self, data: &[u8]) {
+        self.fill_index_buffer(data);
+        for i in self.index_buffer.iter() {
+            self.buckets.increment(*i, 1);
+        }
+        self.count += 1;
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.fill_index_buffer(data);
+        let member = self.index_buffer.iter().all(|i| self.buckets.get(*i) != 0);
+
+        for i in self.index_buffer.iter() {
+            self.buckets.increment(*i, 1);
+        }
+        self.count += 1;
+        member
+    }
+
+    /// Will remove the data from the Counting Bloom Filter if it is a member.
+    fn remove(&mut self, data: &[u8]) {
+        self.test_and_remove(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountingBloomFilter;
+    use crate::Filter;
+
+    // Ensures that Capacity returns the number of bits, m, in the Bloom
+    // filter.
+    #[test]
+    fn test_counting_capacity() {
+        let f = CountingBloomFilter::new(100, 4, 0.1);
+        assert_eq!(f.capacity(), 480);
+    }
+
+    // Ensures that K returns the number of hash functions in the Bloom Filter.
+    #[test]
+    fn test_counting_k() {
+        let f = CountingBloomFilter::new(100, 4, 0.1);
+        assert_eq!(f.k(), 4);
+    }
+
+    // Ensures that Count returns the number of items added to the filter.
+    #[test]
+    fn test_counting_count() {
+        let mut f = CountingBloomFilter::new(100, 4, 0.1);
+        for i in 0..10 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert_eq!(f.count(), 10);
+
+        f.remove(b"0");
+        assert_eq!(f.count(), 9);
+        f.remove(b"missing");
+        assert_eq!(f.count(), 9);
+    }
+
+    // Ensures that removed elements are no longer members while other
+    // elements are unaffected.
+    #[test]
+    fn test_counting_test_and_remove() {
+        let mut f = CountingBloomFilter::new_default(100, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        f.add(b"b");
+
+        // a was added twice, so it takes two removals.
+        assert!(f.test_and_remove(b"a"));
+        assert!(f.test(b"a"));
+        assert!(f.test_and_remove(b"a"));
+        assert!(!f.test(b"a"));
+        assert!(!f.test_and_remove(b"a"));
+        assert!(f.test(b"b"));
+    }
+
+    // Ensures that Reset restores the filter to its original state.
+    #[test]
+    fn test_counting_reset() {
+        let mut f = CountingBloomFilter::new_default(100, 0.1);
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        f.reset();
+        assert_eq!(f.count(), 0);
+        for i in 0..1000 {
+            assert!(!f.test(i.to_string().as_bytes()));
+        }
+    }
+
+    // Ensures that wide counters count duplicate adds beyond 255.
+    #[test]
+    fn test_counting_wide_counters() {
+        let mut f = CountingBloomFilter::new(100, 16, 0.01);
+        for _ in 0..300 {
+            f.add(b"a");
+        }
+        for _ in 0..299 {
+            assert!(f.test_and_remove(b"a"));
+        }
+        assert!(f.test(b"a"));
+        assert!(f.test_and_remove(b"a"));
+        assert!(!f.test(b"a"));
+    }
+}
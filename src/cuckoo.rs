@@ -0,0 +1,336 @@
+use crate::fnv::FnvHasher;
+use crate::Filter;
+use rand::{thread_rng, Rng};
+use std::hash::Hasher;
+
+/// Number of fingerprints per bucket.
+const BUCKET_SIZE: usize = 4;
+
+/// Maximum number of relocations attempted on insert.
+const MAX_KICKS: usize = 500;
+
+/// CuckooFilter implements a Cuckoo Bloom filter as described by Andersen,
+/// Kaminsky, and Mitzenmacher in Cuckoo Filter: Practically Better Than
+/// Bloom:
+///
+/// http://www.pdl.cmu.edu/PDL-FTP/FS/cuckoo-conext2014.pdf
+///
+/// A Cuckoo Filter is a Bloom filter variation which provides support for
+/// removing elements without significantly degrading space and performance.
+/// It works by using a cuckoo hashing scheme for inserting items. Instead of
+/// storing the elements themselves, it stores their fingerprints, which also
+/// allows for item removal without false negatives (if you don't attempt to
+/// remove an item not contained in the filter).
+///
+/// For applications that store many items and target moderately low
+/// false-positive rates, cuckoo filters have lower space overhead than
+/// space-optimized Bloom filters.
+pub struct CuckooFilter {
+    /// buckets of fingerprints, zero marking an empty entry
+    buckets: Vec<[u32; BUCKET_SIZE]>,
+    /// fingerprint evicted by an insert which ran out of relocations
+    victim: Option<(usize, u32)>,
+    /// hash function
+    hash: FnvHasher,
+    /// number of bits per fingerprint
+    fingerprint_bits: u8,
+    /// number of items in the filter
+    count: usize,
+}
+
+impl CuckooFilter {
+    /// Creates a new Cuckoo Bloom filter optimized to store n items with a
+    /// specified target false-positive rate.
+    pub fn new(n: usize, fp_rate: f64) -> Self {
+        let bits = (2.0 * (BUCKET_SIZE as f64) / fp_rate).log2().ceil();
+        Self::with_fingerprint_bits(n, bits.clamp(1.0, 32.0) as u8)
+    }
+
+    /// Creates a new Cuckoo Bloom filter able to store n items with
+    /// fingerprints of the specified number of bits. Longer fingerprints
+    /// lower the false-positive rate at the cost of memory. Panics if the
+    /// fingerprint size isn't between 1 and 32 bits.
+    pub fn with_fingerprint_bits(n: usize, fingerprint_bits: u8) -> Self {
+        if fingerprint_bits == 0 || fingerprint_bits > 32 {
+            panic!("fingerprint size must be between 1 and 32 bits");
+        }
+        let buckets = n.div_ceil(BUCKET_SIZE).next_power_of_two();
+
+        CuckooFilter {
+            buckets: vec![[0; BUCKET_SIZE]; buckets],
+            victim: None,
+            hash: FnvHasher::default(),
+            fingerprint_bits,
+            count: 0,
+        }
+    }
+
+    /// Returns the number of items the filter can hold.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+
+    /// Returns the number of items in the filter.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of bits per fingerprint.
+    pub fn fingerprint_bits(&self) -> u8 {
+        self.fingerprint_bits
+    }
+
+    /// Returns the fraction of entries holding a fingerprint.
+    pub fn load_factor(&self) -> f64 {
+        (self.count as f64) / (self.capacity() as f64)
+    }
+
+    /// Returns the upper bound on false positives when the filter is full.
+    pub fn false_positive_rate(&self) -> f64 {
+        2.0 * (BUCKET_SIZE as f64) / 2f64.powi(i32::from(self.fingerprint_bits))
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives.
+    pub fn lookup(&self, data: &[u8]) -> bool {
+        let (i1, fp) = self.components(data);
+        let i2 = self.alternate_index(i1, fp);
+
+        self.buckets[i1].contains(&fp)
+            || self.buckets[i2].contains(&fp)
+            || self
+                .victim
+                .is_some_and(|v| v.1 == fp && (v.0 == i1 || v.0 == i2))
+    }
+
+    /// Will add the data to the filter. It returns false if the filter is
+    /// full and the data couldn't be added.
+    pub fn insert(&mut self, data: &[u8]) -> bool {
+        if self.victim.is_some() {
+            return false;
+        }
+
+        let (i1, fp) = self.components(data);
+        let i2 = self.alternate_index(i1, fp);
+        if self.put(i1, fp) || self.put(i2, fp) {
+            self.count += 1;
+            return true;
+        }
+
+        // Relocate existing fingerprints to their alternate buckets.
+        let mut rng = thread_rng();
+        let mut index = if rng.gen::<bool>() { i1 } else { i2 };
+        let mut fp = fp;
+        for _ in 0..MAX_KICKS {
+            let entry = rng.gen_range(0, BUCKET_SIZE);
+            std::mem::swap(&mut fp, &mut self.buckets[index][entry]);
+            index = self.alternate_index(index, fp);
+            if self.put(index, fp) {
+                self.count += 1;
+                return true;
+            }
+        }
+
+        // The data is stored, but the fingerprint kicked out last is kept
+        // aside, which marks the filter as full.
+        self.victim = Some((index, fp));
+        self.count += 1;
+        true
+    }
+
+    /// Will remove the data from the filter. It returns true if the data was
+    /// a member, false if not. Removing data which was never added may remove
+    /// another element sharing its fingerprint.
+    pub fn delete(&mut self, data: &[u8]) -> bool {
+        let (i1, fp) = self.components(data);
+        let i2 = self.alternate_index(i1, fp);
+
+        let removed = self.take(i1, fp)
+            || self.take(i2, fp)
+            || match self.victim {
+                Some((index, victim)) if victim == fp && (index == i1 || index == i2) => {
+                    self.victim = None;
+                    true
+                }
+                _ => false,
+            };
+        if !removed {
+            return false;
+        }
+        self.count -= 1;
+
+        // Room was made, so the victim can go back into the table.
+        if let Some((index, victim)) = self.victim.take() {
+            let alternate = self.alternate_index(index, victim);
+            if !self.put(index, victim) && !self.put(alternate, victim) {
+                self.victim = Some((index, victim));
+            }
+        }
+        true
+    }
+
+    /// Restores the filter to its original state. It returns the filter to
+    /// allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        for bucket in self.buckets.iter_mut() {
+            *bucket = [0; BUCKET_SIZE];
+        }
+        self.victim = None;
+        self.count = 0;
+        self
+    }
+
+    /// Returns the primary bucket index and the fingerprint of the data. The
+    /// fingerprint is never zero, which marks empty entries.
+    fn components(&self, data: &[u8]) -> (usize, u32) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        let hash = hasher.finish();
+
+        let index = (hash as usize) & (self.buckets.len() - 1);
+        let mixed = hash.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let fp = (mixed >> (64 - u32::from(self.fingerprint_bits))) as u32;
+        (index, fp.max(1))
+    }
+
+    /// Returns the other bucket index of the fingerprint stored in the bucket
+    /// at index. Applying it twice yields index again.
+    #[inline]
+    fn alternate_index(&self, index: usize, fp: u32) -> usize {
+        let hash = u64::from(fp).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        (index ^ (hash as usize)) & (self.buckets.len() - 1)
+    }
+
+    /// Stores the fingerprint in the bucket at index if it has an empty entry.
+    fn put(&mut self, index: usize, fp: u32) -> bool {
+        match self.buckets[index].iter_mut().find(|entry| **entry == 0) {
+            Some(entry) => {
+                *entry = fp;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes one occurrence of the fingerprint from the bucket at index.
+    fn take(&mut self, index: usize, fp: u32) -> bool {
+        match self.buckets[index].iter_mut().find(|entry| **entry == fp) {
+            Some(entry) => {
+                *entry = 0;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Filter for CuckooFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    fn test(&self, data: &[u8]) -> bool {
+        self.lookup(data)
+    }
+
+    /// Will add the data to the filter unless it is full. It returns the
+    /// filter to allow for chaining.
+    fn add(&mut self, data: &[u8]) {
+        self.insert(data);
+    }
+
+    /// Will test for membership of the data and add it if it isn't a member.
+    /// It returns true if the data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        if self.lookup(data) {
+            return true;
+        }
+        self.insert(data);
+        false
+    }
+
+    /// Will remove the data from the filter if it is a member.
+    fn remove(&mut self, data: &[u8]) {
+        self.delete(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CuckooFilter;
+    use crate::Filter;
+
+    // Ensures that the fingerprint size is derived from the target
+    // false-positive rate and that the capacity is rounded up to a power of
+    // two number of buckets.
+    #[test]
+    fn test_cuckoo_parameters() {
+        let f = CuckooFilter::new(100, 0.001);
+        assert_eq!(f.fingerprint_bits(), 13);
+        assert_eq!(f.capacity(), 128);
+        assert!(f.false_positive_rate() <= 0.001);
+
+        let f = CuckooFilter::with_fingerprint_bits(1000, 8);
+        assert_eq!(f.fingerprint_bits(), 8);
+        assert_eq!(f.capacity(), 1024);
+    }
+
+    // Ensures that inserted elements are members until they are deleted.
+    #[test]
+    fn test_cuckoo_insert_lookup_delete() {
+        let mut f = CuckooFilter::new(1000, 0.001);
+        for i in 0..900 {
+            assert!(f.insert(i.to_string().as_bytes()));
+        }
+        assert_eq!(f.count(), 900);
+        for i in 0..900 {
+            assert!(f.lookup(i.to_string().as_bytes()));
+        }
+
+        for i in 0..450 {
+            assert!(f.delete(i.to_string().as_bytes()));
+        }
+        assert_eq!(f.count(), 450);
+        for i in 450..900 {
+            assert!(f.lookup(i.to_string().as_bytes()));
+        }
+        let remaining = (0..450)
+            .filter(|i| f.lookup(i.to_string().as_bytes()))
+            .count();
+        assert!(remaining < 5);
+    }
+
+    // Ensures that inserts fail once the filter is full without losing the
+    // elements already stored.
+    #[test]
+    fn test_cuckoo_full() {
+        let mut f = CuckooFilter::with_fingerprint_bits(16, 16);
+        let inserted: Vec<String> = (0..100)
+            .map(|i| i.to_string())
+            .take_while(|data| f.insert(data.as_bytes()))
+            .collect();
+        assert!(inserted.len() < 100);
+        assert!(f.load_factor() > 0.5);
+        assert!(!f.insert(b"full"));
+        for data in inserted.iter() {
+            assert!(f.lookup(data.as_bytes()));
+        }
+
+        f.reset();
+        assert_eq!(f.count(), 0);
+        assert!(f.insert(b"full"));
+    }
+
+    // Ensures that the filter can be used through the Filter trait, including
+    // removal.
+    #[test]
+    fn test_cuckoo_filter_trait() {
+        let mut f = CuckooFilter::new(100, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        assert_eq!(f.count(), 1);
+
+        f.remove(b"a");
+        assert!(!f.test(b"a"));
+        assert_eq!(f.count(), 0);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::fnv::FnvBuildHasher;
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+/// TimeDecayingFilter is a Stable Bloom Filter whose cells decay with
+/// wall-clock time rather than with the number of adds. It is configured
+/// with the lifetime of the information in a cell: the expected time for a
+/// cell set on add to be decremented back to zero. The decay is applied
+/// lazily by every mutating operation, according to the time elapsed since
+/// the previous one, so bursts of adds don't evict elements any faster than
+/// idle periods do.
+///
+/// Elements are forgotten once any of their k cells reaches zero, which on
+/// average happens somewhat before the configured lifetime.
+pub struct TimeDecayingFilter<S = FnvBuildHasher> {
+    /// inner filter, with eviction on add disabled
+    filter: StableBloomFilter<S>,
+    /// decrement iterations per second
+    rate_per_sec: f64,
+    /// time up to which the decay was applied
+    last: Instant,
+}
+
+impl TimeDecayingFilter {
+    /// Creates a new time-decaying filter with m cells and d bits allocated
+    /// per cell optimized for the target false-positive rate, whose cells
+    /// fade after about the provided lifetime.
+    pub fn new(m: usize, d: u8, fp_rate: f64, lifetime: Duration) -> Self {
+        Self::from_filter(StableBloomFilter::new(m, d, fp_rate), lifetime)
+    }
+}
+
+impl<S: BuildHasher> TimeDecayingFilter<S> {
+    /// Wraps the filter so that its cells fade after about the provided
+    /// lifetime. Eviction on add is disabled, as cells are decremented with
+    /// time instead. The filter must decrement at least one cell per
+    /// iteration, i.e. p must be non-zero, and the lifetime must be non-zero.
+    pub fn from_filter(mut filter: StableBloomFilter<S>, lifetime: Duration) -> Self {
+        assert!(
+            filter.p() > 0,
+            "a time-decaying filter must decrement cells"
+        );
+        assert!(
+            lifetime > Duration::from_secs(0),
+            "a time-decaying filter needs a non-zero lifetime"
+        );
+        filter.set_eviction_enabled(false);
+
+        // Every iteration decrements a given cell with probability p/m, and a
+        // cell needs max decrements to go from max to zero.
+        let iterations = f64::from(filter.max()) * (filter.cells() as f64) / (filter.p() as f64);
+
+        TimeDecayingFilter {
+            rate_per_sec: iterations / lifetime.as_secs_f64(),
+            filter,
+            last: Instant::now(),
+        }
+    }
+
+    /// Returns the inner filter.
+    pub fn filter(&self) -> &StableBloomFilter<S> {
+        &self.filter
+    }
+
+    /// Returns the number of decrement iterations applied per second.
+    pub fn rate_per_sec(&self) -> f64 {
+        self.rate_per_sec
+    }
+
+    /// Applies the decay for the time elapsed between the last operation and
+    /// now. Returns the number of decrement iterations that were run.
+    pub fn decay(&mut self) -> usize {
+        self.decay_to(Instant::now())
+    }
+
+    /// Applies the decay for the time elapsed between the last operation and
+    /// the provided instant. Instants before the last operation apply no
+    /// decay. Returns the number of decrement iterations that were run.
+    pub fn decay_to(&mut self, now: Instant) -> usize {
+        if now <= self.last {
+            return 0;
+        }
+        let elapsed = now - self.last;
+        self.last = now;
+        self.filter.apply_time_decay(elapsed, self.rate_per_sec)
+    }
+
+    /// Will test for membership of the data at the provided instant, after
+    /// applying the decay up to then.
+    pub fn test_at(&mut self, data: &[u8], now: Instant) -> bool {
+        self.decay_to(now);
+        self.filter.test(data)
+    }
+
+    /// Will add the data at the provided instant, after applying the decay up
+    /// to then. It returns the filter to allow for chaining.
+    pub fn add_at(&mut self, data: &[u8], now: Instant) -> &Self {
+        self.decay_to(now);
+        self.filter.add(data);
+        self
+    }
+
+    /// Is equivalent to calling test_at followed by add_at. It returns true
+    /// if the data is a member, false if not.
+    pub fn test_and_add_at(&mut self, data: &[u8], now: Instant) -> bool {
+        self.decay_to(now);
+        self.filter.test_and_add(data)
+    }
+}
+
+impl<S: BuildHasher> Filter for TimeDecayingFilter<S> {
+    /// Will test for membership of the data. As testing doesn't modify the
+    /// filter, it reflects the decay applied by the last mutating operation;
+    /// call `decay` first to account for the time elapsed since then.
+    fn test(&self, data: &[u8]) -> bool {
+        self.filter.test(data)
+    }
+
+    /// Will add the data to the filter after applying the decay up to now.
+    fn add(&mut self, data: &[u8]) {
+        self.add_at(data, Instant::now());
+    }
+
+    /// Is equivalent to calling decay, test and add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.test_and_add_at(data, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeDecayingFilter;
+    use crate::stable::StableBloomFilter;
+    use std::time::{Duration, Instant};
+
+    // Ensures that the decay rate decrements every cell max times per
+    // lifetime on average.
+    #[test]
+    fn test_rate_per_sec() {
+        let f = TimeDecayingFilter::new(1000, 2, 0.01, Duration::from_secs(10));
+        let inner = f.filter();
+        let expected = 3.0 * 1000.0 / (inner.p() as f64) / 10.0;
+        assert!((f.rate_per_sec() - expected).abs() < 1e-9);
+        assert!(!inner.is_eviction_enabled());
+    }
+
+    // Ensures that elements fade with elapsed time rather than with the
+    // number of adds.
+    #[test]
+    fn test_decay_with_time() {
+        let lifetime = Duration::from_secs(600);
+        let filter = StableBloomFilter::new_with_seed(10_000, 1, 0.01, 5);
+        let mut f = TimeDecayingFilter::from_filter(filter, lifetime);
+        let start = Instant::now();
+
+        f.add_at(b"a", start);
+        // A burst of adds within a second doesn't evict a.
+        for i in 0..10_000 {
+            f.add_at(
+                i.to_string().as_bytes(),
+                start + Duration::from_millis(i / 10),
+            );
+        }
+        assert!(f.test_at(b"a", start + Duration::from_secs(1)));
+
+        // Going back in time applies no decay.
+        assert_eq!(f.decay_to(start), 0);
+
+        assert!(!f.test_at(b"a", start + lifetime * 3));
+        assert!(f.filter().zeros_fraction() > 0.9);
+    }
+
+    // Ensures that a filter with a zero lifetime is rejected instead of
+    // decaying at an infinite rate.
+    #[test]
+    #[should_panic(expected = "a time-decaying filter needs a non-zero lifetime")]
+    fn test_zero_lifetime() {
+        TimeDecayingFilter::new(1000, 2, 0.01, Duration::from_secs(0));
+    }
+}
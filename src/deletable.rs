@@ -0,0 +1,276 @@
+use crate::buckets::Buckets;
+use crate::fnv::FnvHasher;
+use crate::stable::split_hash;
+use crate::{optimal_k, optimal_m, Filter};
+use std::hash::Hasher;
+
+/// DeletableBloomFilter implements a Deletable Bloom Filter as described by
+/// Rothenberg, Macapuna, Verdi, and Magalhaes in The Deletable Bloom filter -
+/// A new member of the Bloom family:
+///
+/// http://arxiv.org/pdf/1005.0352.pdf
+///
+/// A Deletable Bloom Filter compactly stores information on collisions when
+/// inserting elements. This information is used to determine if elements are
+/// deletable. This design enables false-negative-free deletions at a fraction
+/// of the cost in memory consumption of a counting filter.
+///
+/// The bit array is divided into r regions, each tracked by a single bit
+/// recording whether two elements ever set the same bit within it. Bits in
+/// collision-free regions can be reset on removal, while bits in regions
+/// which saw a collision are kept, so that an element can only be removed if
+/// at least one of its bits lies in a collision-free region.
+pub struct DeletableBloomFilter {
+    /// filter data
+    buckets: Buckets,
+    /// collision bitmap, one bit per region
+    collisions: Buckets,
+    /// hash function (kernel for all k functions)
+    hash: FnvHasher,
+    /// filter size
+    m: usize,
+    /// number of regions
+    r: usize,
+    /// number of hash functions
+    k: usize,
+    /// number of items in the filter
+    count: usize,
+    /// buffer used to cache indices
+    index_buffer: Vec<usize>,
+}
+
+impl DeletableBloomFilter {
+    /// Creates a new Deletable Bloom Filter optimized to store n items with a
+    /// specified target false-positive rate. The r value determines the
+    /// number of bits to use to store collision information. This controls
+    /// the deletability of an element. Refer to the paper for selecting an
+    /// optimal value.
+    pub fn new(n: usize, r: usize, fp_rate: f64) -> Self {
+        let m = optimal_m(n, fp_rate);
+        let k = optimal_k(fp_rate).max(1);
+        let r = r.clamp(1, m.max(2) - 1);
+        // The collision bitmap is taken from the filter's bits.
+        let m = (m - r).max(r);
+
+        DeletableBloomFilter {
+            buckets: Buckets::new(m, 1),
+            collisions: Buckets::new(r, 1),
+            hash: FnvHasher::default(),
+            m,
+            r,
+            k,
+            count: 0,
+            index_buffer: vec![0; k],
+        }
+    }
+
+    /// Returns the number of bits used to store elements, m.
+    pub fn capacity(&self) -> usize {
+        self.m
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of collision regions, r.
+    pub fn regions(&self) -> usize {
+        self.r
+    }
+
+    /// Returns the number of items in the filter.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the fraction of regions without collisions, i.e. whose bits
+    /// can be reset on removal.
+    pub fn deletable_ratio(&self) -> f64 {
+        let free = (0..(self.r))
+            .filter(|i| self.collisions.get(*i) == 0)
+            .count();
+        (free as f64) / (self.r as f64)
+    }
+
+    /// Will test for membership of the data and remove it from the filter if
+    /// it exists. Returns true if the data was a member, false if not. A
+    /// member whose bits all lie in regions with collisions stays in the
+    /// filter.
+    pub fn test_and_remove(&mut self, data: &[u8]) -> bool {
+        self.fill_index_buffer(data);
+        let member = self.index_buffer.iter().all(|i| self.buckets.get(*i) != 0);
+        if !member {
+            return false;
+        }
+
+        for i in 0..(self.k) {
+            let index = self.index_buffer[i];
+            // Only bits in collision-free regions can be safely reset.
+            if self.collisions.get(self.region(index)) == 0 {
+                self.buckets.set(index, 0);
+            }
+        }
+        // A member whose bits all collided stays in the filter and can be
+        // removed again, more often than it was added.
+        self.count = self.count.saturating_sub(1);
+        true
+    }
+
+    /// Restores the Deletable Bloom Filter to its original state. It returns
+    /// the filter to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.buckets.reset();
+        self.collisions.reset();
+        self.count = 0;
+        self
+    }
+
+    /// Caches the k bit indices of the data.
+    fn fill_index_buffer(&mut self, data: &[u8]) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        let (lower, upper) = split_hash(hasher.finish());
+
+        for i in 0..(self.k) {
+            self.index_buffer[i] = (lower as usize + upper as usize * i) % self.m;
+        }
+    }
+
+    /// Returns the region of the bit at the specified index.
+    #[inline]
+    fn region(&self, index: usize) -> usize {
+        index * self.r / self.m
+    }
+
+    /// Sets the cached bits, recording a collision for every bit that was
+    /// already set.
+    fn set_indices(&mut self) {
+        for i in 0..(self.k) {
+            let index = self.index_buffer[i];
+            if self.buckets.get(index) != 0 {
+                let region = self.region(index);
+                self.collisions.set(region, 1);
+            } else {
+                self.buckets.set(index, 1);
+            }
+        }
+        self.count += 1;
+    }
+}
+
+impl Filter for DeletableBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives but a zero probability of
+    /// false negatives.
+    fn test(&self, data: &[u8]) -> bool {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        let (lower, upper) = split_hash(hasher.finish());
+
+        (0..(self.k)).all(|i| {
+            self.buckets
+                .get((lower as usize + upper as usize * i) % self.m)
+                != 0
+        })
+    }
+
+    /// Will add the data to the Bloom filter.
+    fn add(&mut self, data: &[u8]) {
+        self.fill_index_buffer(data);
+        self.set_indices();
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.fill_index_buffer(data);
+        let member = self.index_buffer.iter().all(|i| self.buckets.get(*i) != 0);
+        self.set_indices();
+        member
+    }
+
+    /// Will remove the data from the filter if it is a member and at least
+    /// one of its bits lies in a collision-free region.
+    fn remove(&mut self, data: &[u8]) {
+        self.test_and_remove(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeletableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that the collision bitmap is taken from the optimal number of
+    // bits.
+    #[test]
+    fn test_deletable_capacity() {
+        let f = DeletableBloomFilter::new(100, 10, 0.1);
+        assert_eq!(f.capacity(), 470);
+        assert_eq!(f.regions(), 10);
+        assert_eq!(f.k(), 4);
+        assert_eq!(f.deletable_ratio(), 1.0);
+    }
+
+    // Ensures that elements can be removed without false negatives for the
+    // remaining elements.
+    #[test]
+    fn test_deletable_remove() {
+        let mut f = DeletableBloomFilter::new(1000, 100, 0.01);
+        for i in 0..500 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert_eq!(f.count(), 500);
+        assert!(f.deletable_ratio() < 1.0);
+
+        let mut removed = 0;
+        for i in 0..250 {
+            let data = i.to_string();
+            assert!(f.test_and_remove(data.as_bytes()));
+            if !f.test(data.as_bytes()) {
+                removed += 1;
+            }
+        }
+        assert_eq!(f.count(), 250);
+        assert!(removed > 0);
+        for i in 250..500 {
+            assert!(f.test(i.to_string().as_bytes()));
+        }
+        assert!(!f.test_and_remove(b"missing"));
+    }
+
+    // Ensures that an element without collisions is fully removed and that
+    // Reset clears the collision bitmap.
+    #[test]
+    fn test_deletable_test_and_add_and_reset() {
+        let mut f = DeletableBloomFilter::new(100, 10, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        f.remove(b"a");
+        assert!(!f.test(b"a"));
+        assert_eq!(f.count(), 0);
+
+        f.add(b"a");
+        assert!(f.test_and_add(b"a"));
+        assert!(f.deletable_ratio() < 1.0);
+
+        f.reset();
+        assert_eq!(f.count(), 0);
+        assert_eq!(f.deletable_ratio(), 1.0);
+        assert!(!f.test(b"a"));
+    }
+
+    // Ensures that removing a member whose bits all collided more often than
+    // it was added doesn't underflow the count.
+    #[test]
+    fn test_deletable_remove_collided() {
+        let mut f = DeletableBloomFilter::new(100, 10, 0.01);
+        f.add(b"a");
+        f.add(b"a");
+        for _ in 0..3 {
+            assert!(f.test_and_remove(b"a"));
+        }
+        assert_eq!(f.count(), 0);
+    }
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Returned when two filters can't be combined or compared because their
+/// parameters differ.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeError {
+    /// The filters have a different number of cells.
+    CellsMismatch { left: usize, right: usize },
+    /// The filters use a different number of bits per cell.
+    BucketSizeMismatch { left: u8, right: u8 },
+    /// The filters use a different number of hash functions.
+    HashFunctionsMismatch { left: usize, right: usize },
+    /// The filters hash data differently, e.g. because they use different
+    /// seeds.
+    HashMismatch,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::CellsMismatch { left, right } => {
+                write!(f, "cell count mismatch: {} != {}", left, right)
+            }
+            MergeError::BucketSizeMismatch { left, right } => {
+                write!(f, "bucket size mismatch: {} != {}", left, right)
+            }
+            MergeError::HashFunctionsMismatch { left, right } => {
+                write!(f, "hash function count mismatch: {} != {}", left, right)
+            }
+            MergeError::HashMismatch => write!(f, "hash mismatch"),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// Returned when a filter can't be created from the provided data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SbfError {
+    /// The data length doesn't match the number of bytes needed for the
+    /// filter's cells.
+    DataLength { expected: usize, actual: usize },
+    /// The filter would have zero cells.
+    ZeroCells,
+    /// The number of bits per cell is outside of 1 to 8.
+    InvalidBucketSize(u8),
+    /// The false-positive rate isn't strictly between 0 and 1.
+    InvalidFpRate(f64),
+    /// Neither a number of cells nor an expected window was provided.
+    MissingSize,
+    /// Both a number of cells and an expected window were provided.
+    ConflictingSize,
+    /// The fill value isn't between 1 and the maximum cell value.
+    InvalidFillValue(u8),
+    /// A cell index isn't below the number of cells.
+    CellIndexOutOfRange { index: usize, cells: usize },
+    /// A cell value exceeds the maximum value of a cell.
+    CellValueOutOfRange { value: u8, max: u8 },
+    /// The fields of a deserialized filter contradict each other.
+    InconsistentState(&'static str),
+}
+
+impl fmt::Display for SbfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SbfError::DataLength { expected, actual } => write!(
+                f,
+                "data length mismatch: expected {} bytes, got {}",
+                expected, actual
+            ),
+            SbfError::ZeroCells => write!(f, "a filter needs at least one cell"),
+            SbfError::InvalidBucketSize(d) => {
+                write!(f, "bucket size must be between 1 and 8, got {}", d)
+            }
+            SbfError::InvalidFpRate(fp_rate) => write!(
+                f,
+                "false-positive rate must be between 0 and 1, got {}",
+                fp_rate
+            ),
+            SbfError::MissingSize => write!(f, "either cells or an expected window is needed"),
+            SbfError::ConflictingSize => {
+                write!(f, "cells and an expected window are mutually exclusive")
+            }
+            SbfError::InvalidFillValue(fill_value) => write!(
+                f,
+                "fill value must be between 1 and the maximum cell value, got {}",
+                fill_value
+            ),
+            SbfError::CellIndexOutOfRange { index, cells } => {
+                write!(f, "cell index {} out of range for {} cells", index, cells)
+            }
+            SbfError::CellValueOutOfRange { value, max } => {
+                write!(f, "cell value {} exceeds the maximum of {}", value, max)
+            }
+            SbfError::InconsistentState(reason) => {
+                write!(f, "inconsistent filter state: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for SbfError {}
+
+/// Returned when a snapshot can't be read.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading from the underlying reader failed, e.g. because the snapshot
+    /// is truncated.
+    Io(io::Error),
+    /// The snapshot doesn't start with the expected magic bytes.
+    BadMagic,
+    /// The snapshot was written in a format version this crate can't read.
+    UnsupportedVersion(u8),
+    /// The snapshot has a number of bits per cell outside of 1 to 8.
+    InvalidBucketSize(u8),
+    /// The snapshot has zero hash functions.
+    InvalidHashFunctions,
+    /// The snapshot's fields contradict each other, e.g. a cell count which
+    /// doesn't match the length of the cell data.
+    InconsistentHeader,
+    /// The snapshot uses features this crate can't represent, e.g. a scaling
+    /// RedisBloom filter with several sub-filters.
+    UnsupportedLayout,
+    /// The snapshot holds a filter whose hash scheme this reader can't
+    /// derive cell indices for.
+    UnsupportedHashScheme(u8),
+    /// The snapshot is corrupted: its checksum doesn't match its contents,
+    /// e.g. because it was only partially written, or its sparse cell data
+    /// or rkyv archive is malformed.
+    Corrupted,
+    /// The snapshot holds more cells than the caller allows.
+    TooManyCells { cells: usize, max: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot i/o error: {}", err),
+            SnapshotError::BadMagic => write!(f, "not a stable bloom filter snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version: {}", version)
+            }
+            SnapshotError::InvalidBucketSize(d) => write!(f, "invalid bucket size: {}", d),
+            SnapshotError::InvalidHashFunctions => write!(f, "invalid hash function count: 0"),
+            SnapshotError::InconsistentHeader => write!(f, "inconsistent snapshot header"),
+            SnapshotError::UnsupportedLayout => write!(f, "unsupported snapshot layout"),
+            SnapshotError::UnsupportedHashScheme(id) => {
+                write!(f, "unsupported hash scheme: {}", id)
+            }
+            SnapshotError::Corrupted => write!(f, "corrupted snapshot"),
+            SnapshotError::TooManyCells { cells, max } => write!(
+                f,
+                "snapshot holds {} cells, more than the maximum of {}",
+                cells, max
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
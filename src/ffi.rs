@@ -0,0 +1,177 @@
+//! C bindings, enabled by the `ffi` feature, so that C and C++ services can
+//! embed the Stable Bloom Filter through the crate's cdylib. The functions
+//! are declared in `include/sbf.h`.
+//!
+//! The cdylib is built whether or not this feature is enabled, as it also
+//! serves the Python and WebAssembly bindings. Rust dependents only use the
+//! rlib, but still pay for linking the cdylib.
+//!
+//! Filters are opaque pointers created by `sbf_new` or `sbf_deserialize` and
+//! released by `sbf_free`. Buffers returned by `sbf_serialize` are released
+//! by `sbf_buffer_free`. Filters aren't synchronized, so a filter must not be
+//! used from several threads at once.
+
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use std::{ptr, slice};
+
+/// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+/// cell optimized for the target false-positive rate. Returns null if the
+/// parameters are invalid.
+#[no_mangle]
+pub extern "C" fn sbf_new(m: usize, d: u8, fp_rate: f64) -> *mut StableBloomFilter {
+    match StableBloomFilter::try_new(m, d, fp_rate) {
+        Ok(filter) => Box::into_raw(Box::new(filter)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Releases a filter. Does nothing if filter is null.
+///
+/// # Safety
+///
+/// filter must be null or a pointer returned by `sbf_new` or
+/// `sbf_deserialize` which wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_free(filter: *mut StableBloomFilter) {
+    if !filter.is_null() {
+        drop(Box::from_raw(filter));
+    }
+}
+
+/// Will add the len bytes at data to the filter.
+///
+/// # Safety
+///
+/// filter must be a live filter and data must point to len readable bytes,
+/// or be null if len is zero.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_add(filter: *mut StableBloomFilter, data: *const u8, len: usize) {
+    (*filter).add(bytes(data, len));
+}
+
+/// Will test for membership of the len bytes at data and returns true if
+/// they are a member, false if not.
+///
+/// # Safety
+///
+/// filter must be a live filter and data must point to len readable bytes,
+/// or be null if len is zero.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_test(
+    filter: *const StableBloomFilter,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    (*filter).test(bytes(data, len))
+}
+
+/// Is equivalent to calling sbf_test followed by sbf_add. It returns true if
+/// the data is a member, false if not.
+///
+/// # Safety
+///
+/// filter must be a live filter and data must point to len readable bytes,
+/// or be null if len is zero.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_test_and_add(
+    filter: *mut StableBloomFilter,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    (*filter).test_and_add(bytes(data, len))
+}
+
+/// Returns a snapshot of the filter's parameters and cells, in the format of
+/// the `serialization` module, and stores its length in out_len. The buffer
+/// must be released with `sbf_buffer_free`.
+///
+/// # Safety
+///
+/// filter must be a live filter and out_len must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_serialize(
+    filter: *const StableBloomFilter,
+    out_len: *mut usize,
+) -> *mut u8 {
+    let mut buf = Vec::new();
+    (*filter)
+        .write_to(&mut buf)
+        .expect("writing to a Vec never fails");
+    let buf = buf.into_boxed_slice();
+    *out_len = buf.len();
+    Box::into_raw(buf) as *mut u8
+}
+
+/// Releases a buffer returned by `sbf_serialize`. Does nothing if buf is
+/// null.
+///
+/// # Safety
+///
+/// buf must be null or a buffer returned by `sbf_serialize` which wasn't
+/// released yet, and len the length it was returned with.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_buffer_free(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+/// Reads a filter from the len bytes of a snapshot written by
+/// `sbf_serialize`. Returns null if the snapshot is invalid.
+///
+/// # Safety
+///
+/// data must point to len readable bytes, or be null if len is zero.
+#[no_mangle]
+pub unsafe extern "C" fn sbf_deserialize(data: *const u8, len: usize) -> *mut StableBloomFilter {
+    match StableBloomFilter::read_from(bytes(data, len)) {
+        Ok(filter) => Box::into_raw(Box::new(filter)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Returns the len bytes at data as a slice, which is empty if len is zero.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        sbf_add, sbf_buffer_free, sbf_deserialize, sbf_free, sbf_new, sbf_serialize, sbf_test,
+        sbf_test_and_add,
+    };
+    use std::ptr;
+
+    // Ensures that a filter created, filled, serialized and freed through the
+    // C functions behaves like the Rust filter.
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let f = sbf_new(1000, 2, 0.01);
+            assert!(!f.is_null());
+            assert!(!sbf_test_and_add(f, b"a".as_ptr(), 1));
+            sbf_add(f, b"b".as_ptr(), 1);
+            assert!(sbf_test(f, b"a".as_ptr(), 1));
+            assert!(!sbf_test(f, ptr::null(), 0));
+
+            let mut len = 0;
+            let buf = sbf_serialize(f, &mut len);
+            // The six cells set are written as sparse cell data.
+            assert_eq!(len, 44 + 15 + 4);
+            let g = sbf_deserialize(buf, len);
+            assert!(!g.is_null());
+            assert!(sbf_test(g, b"b".as_ptr(), 1));
+
+            sbf_buffer_free(buf, len);
+            sbf_free(f);
+            sbf_free(g);
+        }
+        assert!(sbf_new(0, 2, 0.01).is_null());
+    }
+}
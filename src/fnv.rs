@@ -1,12 +1,16 @@
-use std::hash::Hasher;
+use std::hash::{BuildHasher, Hasher};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FnvHasher(u64);
 
+/// FNV-1a offset basis.
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
 impl Default for FnvHasher {
     #[inline]
     fn default() -> FnvHasher {
-        FnvHasher(0xcbf2_9ce4_8422_2325)
+        FnvHasher(OFFSET_BASIS)
     }
 }
 
@@ -37,3 +41,42 @@ impl Hasher for FnvHasher {
         *self = FnvHasher(hash);
     }
 }
+
+/// FnvBuildHasher creates `FnvHasher`s which all start with the same key. It's
+/// the default hasher of a Stable Bloom Filter.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FnvBuildHasher {
+    key: u64,
+}
+
+impl Default for FnvBuildHasher {
+    #[inline]
+    fn default() -> FnvBuildHasher {
+        FnvBuildHasher { key: OFFSET_BASIS }
+    }
+}
+
+impl FnvBuildHasher {
+    /// Create an FNV build hasher whose hashers start with a state
+    /// corresponding to the hash `key`.
+    #[inline]
+    pub fn with_key(key: u64) -> FnvBuildHasher {
+        FnvBuildHasher { key }
+    }
+
+    /// Returns the key the hashers start with.
+    #[inline]
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+}
+
+impl BuildHasher for FnvBuildHasher {
+    type Hasher = FnvHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> FnvHasher {
+        FnvHasher::with_key(self.key)
+    }
+}
@@ -0,0 +1,552 @@
+//! Alternative hash kernels for Stable Bloom Filters. Each is a
+//! `BuildHasher` which can be passed to `StableBloomFilter::with_hasher`.
+//! The default kernel is FNV-1a, see `fnv::FnvBuildHasher`.
+
+use crate::fnv::FnvBuildHasher;
+use crate::interop::GoFnvBuildHasher;
+use crate::stable::ProbeScheme;
+use std::any::TypeId;
+use std::hash::{BuildHasher, Hasher};
+
+#[cfg(feature = "ahash")]
+pub use self::ahash::AHashBuilder;
+#[cfg(feature = "siphash")]
+pub use self::siphash::SipHashBuilder;
+#[cfg(feature = "xxhash")]
+pub use self::xxhash::XxHashBuilder;
+
+/// HashScheme identifies how a filter derives the cell indices of data: the
+/// hash kernel and the probe scheme. The derivation of a scheme never changes
+/// across crate versions; a changed derivation gets a new scheme, so that a
+/// persisted filter is never read back with different cell indices. Kernel
+/// parameters such as seeds and keys are part of the hash builder, not the
+/// scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashScheme {
+    /// FNV-1a and plain double hashing, used by filters created before
+    /// enhanced double hashing.
+    FnvV1,
+    /// FNV-1a and enhanced double hashing, the default.
+    FnvV2,
+    /// SipHash-2-4 and enhanced double hashing.
+    Sip24V1,
+    /// 64-bit xxHash and enhanced double hashing.
+    Xx64V1,
+    /// The first half of MurmurHash3 x64_128 and enhanced double hashing.
+    Murmur3V1,
+    /// FNV-1 and plain double hashing, as in BoomFilters.
+    GoFnvV1,
+    /// Any other hash kernel or combination, e.g. aHash, whose derivation
+    /// isn't covered by the guarantee.
+    Custom,
+}
+
+impl HashScheme {
+    /// Returns the scheme of filters which hash data with S and derive cell
+    /// indices with the probe scheme.
+    pub(crate) fn of<S: 'static>(probe: ProbeScheme) -> HashScheme {
+        let kernel = TypeId::of::<S>();
+        match probe {
+            ProbeScheme::DoubleHashing if kernel == TypeId::of::<FnvBuildHasher>() => {
+                HashScheme::FnvV1
+            }
+            ProbeScheme::DoubleHashing if kernel == TypeId::of::<GoFnvBuildHasher>() => {
+                HashScheme::GoFnvV1
+            }
+            ProbeScheme::EnhancedDoubleHashing if kernel == TypeId::of::<FnvBuildHasher>() => {
+                HashScheme::FnvV2
+            }
+            #[cfg(feature = "siphash")]
+            ProbeScheme::EnhancedDoubleHashing if kernel == TypeId::of::<SipHashBuilder>() => {
+                HashScheme::Sip24V1
+            }
+            #[cfg(feature = "xxhash")]
+            ProbeScheme::EnhancedDoubleHashing if kernel == TypeId::of::<XxHashBuilder>() => {
+                HashScheme::Xx64V1
+            }
+            ProbeScheme::EnhancedDoubleHashing if kernel == TypeId::of::<Murmur3Builder>() => {
+                HashScheme::Murmur3V1
+            }
+            _ => HashScheme::Custom,
+        }
+    }
+
+    /// Returns the probe scheme of the scheme, or None for custom schemes.
+    pub fn probe_scheme(self) -> Option<ProbeScheme> {
+        match self {
+            HashScheme::FnvV1 | HashScheme::GoFnvV1 => Some(ProbeScheme::DoubleHashing),
+            HashScheme::FnvV2
+            | HashScheme::Sip24V1
+            | HashScheme::Xx64V1
+            | HashScheme::Murmur3V1 => Some(ProbeScheme::EnhancedDoubleHashing),
+            HashScheme::Custom => None,
+        }
+    }
+
+    /// Returns the identifier recorded for the scheme in snapshots.
+    pub fn id(self) -> u8 {
+        match self {
+            HashScheme::Custom => 0,
+            HashScheme::FnvV1 => 1,
+            HashScheme::FnvV2 => 2,
+            HashScheme::Sip24V1 => 3,
+            HashScheme::Xx64V1 => 4,
+            HashScheme::Murmur3V1 => 5,
+            HashScheme::GoFnvV1 => 6,
+        }
+    }
+
+    /// Returns the scheme recorded as id in a snapshot, or None if the id is
+    /// unknown.
+    pub fn from_id(id: u8) -> Option<HashScheme> {
+        match id {
+            0 => Some(HashScheme::Custom),
+            1 => Some(HashScheme::FnvV1),
+            2 => Some(HashScheme::FnvV2),
+            3 => Some(HashScheme::Sip24V1),
+            4 => Some(HashScheme::Xx64V1),
+            5 => Some(HashScheme::Murmur3V1),
+            6 => Some(HashScheme::GoFnvV1),
+            _ => None,
+        }
+    }
+}
+
+/// Murmur3Builder creates MurmurHash3 x64_128 hashers which all use the same
+/// seed. The 64-bit hash of the data is the first half of the 128-bit digest,
+/// which matches `Hashing.murmur3_128(seed).hashBytes(data).asLong()` in
+/// Guava, so that a filter derives the same cell indices as a JVM filter
+/// which splits that hash the same way.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Murmur3Builder {
+    seed: u32,
+}
+
+impl Murmur3Builder {
+    /// Creates a MurmurHash3 build hasher whose hashers use the provided
+    /// seed.
+    #[inline]
+    pub fn with_seed(seed: u32) -> Murmur3Builder {
+        Murmur3Builder { seed }
+    }
+
+    /// Returns the seed the hashers use.
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+}
+
+impl BuildHasher for Murmur3Builder {
+    type Hasher = Murmur3Hasher;
+
+    #[inline]
+    fn build_hasher(&self) -> Murmur3Hasher {
+        Murmur3Hasher {
+            seed: self.seed,
+            data: Vec::new(),
+        }
+    }
+}
+
+/// Murmur3Hasher computes the MurmurHash3 x64_128 digest of the data written
+/// to it. MurmurHash3 mixes the length of the data into the digest, so the
+/// data is buffered until the hash is finished.
+#[derive(Clone, Debug)]
+pub struct Murmur3Hasher {
+    seed: u32,
+    data: Vec<u8>,
+}
+
+impl Murmur3Hasher {
+    /// Returns both 64-bit halves of the 128-bit digest of the data written
+    /// so far, in the order MurmurHash3 outputs them.
+    #[inline]
+    pub fn finish128(&self) -> (u64, u64) {
+        murmur3_x64_128(&self.data, self.seed)
+    }
+}
+
+impl Hasher for Murmur3Hasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.finish128().0
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+/// Returns the two 64-bit halves of the MurmurHash3 x64_128 digest of the
+/// data with the provided seed.
+fn murmur3_x64_128(data: &[u8], seed: u32) -> (u64, u64) {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mix_k1 = |k1: u64| k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    let mix_k2 = |k2: u64| k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    let read_u64 = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .enumerate()
+            .fold(0u64, |k, (i, byte)| k | (u64::from(*byte) << (8 * i)))
+    };
+
+    let (mut h1, mut h2) = (u64::from(seed), u64::from(seed));
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        h1 ^= mix_k1(read_u64(&block[..8]));
+        h1 = h1.rotate_left(27).wrapping_add(h2);
+        h1 = h1.wrapping_mul(5).wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(read_u64(&block[8..]));
+        h2 = h2.rotate_left(31).wrapping_add(h1);
+        h2 = h2.wrapping_mul(5).wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    if tail.len() > 8 {
+        h2 ^= mix_k2(read_u64(&tail[8..]));
+    }
+    if !tail.is_empty() {
+        h1 ^= mix_k1(read_u64(&tail[..tail.len().min(8)]));
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    (h1, h2)
+}
+
+/// Returns the MurmurHash3 finalization mix of k, which makes every bit of
+/// the result depend on every bit of k.
+#[inline]
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
+
+#[cfg(feature = "ahash")]
+mod ahash {
+    use ahash::{AHasher, RandomState};
+    use std::hash::BuildHasher;
+
+    /// AHashBuilder creates aHash hashers which all use the same seed. aHash
+    /// hashes short keys in a few instructions, which makes it about twice as
+    /// fast as FNV for keys of 8 to 32 bytes. Its output depends on the CPU
+    /// features the crate is compiled for and may change between aHash
+    /// versions, so filters using it shouldn't be persisted or shared with
+    /// other machines.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct AHashBuilder {
+        seed: u64,
+    }
+
+    impl AHashBuilder {
+        /// Creates an aHash build hasher whose hashers use the provided seed.
+        #[inline]
+        pub fn with_seed(seed: u64) -> AHashBuilder {
+            AHashBuilder { seed }
+        }
+
+        /// Returns the seed the hashers use.
+        #[inline]
+        pub fn seed(&self) -> u64 {
+            self.seed
+        }
+    }
+
+    impl BuildHasher for AHashBuilder {
+        type Hasher = AHasher;
+
+        #[inline]
+        fn build_hasher(&self) -> AHasher {
+            RandomState::with_seeds(self.seed, 0, 0, 0).build_hasher()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::AHashBuilder;
+        use crate::stable::StableBloomFilter;
+        use crate::Filter;
+
+        // Ensures that hashes are deterministic for a seed and depend on it.
+        #[test]
+        fn test_seed() {
+            let f = StableBloomFilter::with_hasher(1000, 1, 0.01, AHashBuilder::default());
+            let same = StableBloomFilter::with_hasher(1000, 1, 0.01, AHashBuilder::default());
+            assert_eq!(f.hash64(b"a"), same.hash64(b"a"));
+
+            let seeded = StableBloomFilter::with_hasher(1000, 1, 0.01, AHashBuilder::with_seed(1));
+            assert_eq!(seeded.hasher().seed(), 1);
+            assert_ne!(seeded.hash64(b"a"), f.hash64(b"a"));
+        }
+
+        // Ensures that a filter using the aHash kernel tests positive for
+        // added data.
+        #[test]
+        fn test_filter() {
+            let mut f = StableBloomFilter::with_hasher(10_000, 2, 0.01, AHashBuilder::default());
+            f.add(b"a");
+            f.add(b"0123456789abcdef0123");
+            assert!(f.test(b"a"));
+            assert!(f.test(b"0123456789abcdef0123"));
+            assert!(!f.test(b"b"));
+        }
+    }
+}
+
+#[cfg(feature = "siphash")]
+mod siphash {
+    use siphasher::sip::SipHasher24;
+    use std::fmt;
+    use std::hash::BuildHasher;
+
+    /// SipHashBuilder creates SipHash-2-4 hashers keyed with a secret 128-bit
+    /// key. Unlike FNV, the cell indices can't be predicted without the key,
+    /// so an adversary feeding the filter can't craft colliding keys to
+    /// inflate the false-positive rate.
+    ///
+    /// The key is secret, so it isn't serializable. A keyed filter is
+    /// persisted as its `to_sparse` cells, which are restored with
+    /// `apply_delta` into a filter created with the same key.
+    #[derive(Clone, PartialEq)]
+    pub struct SipHashBuilder {
+        key: [u8; 16],
+    }
+
+    impl SipHashBuilder {
+        /// Creates a SipHash build hasher whose hashers use the provided key.
+        #[inline]
+        pub fn new_with_key(key: [u8; 16]) -> SipHashBuilder {
+            SipHashBuilder { key }
+        }
+    }
+
+    // The key is secret, so it's left out of the debug output.
+    impl fmt::Debug for SipHashBuilder {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("SipHashBuilder").finish()
+        }
+    }
+
+    impl BuildHasher for SipHashBuilder {
+        type Hasher = SipHasher24;
+
+        #[inline]
+        fn build_hasher(&self) -> SipHasher24 {
+            SipHasher24::new_with_key(&self.key)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::SipHashBuilder;
+        use crate::stable::StableBloomFilter;
+        use crate::Filter;
+
+        // Ensures that the keyed kernel produces the reference SipHash-2-4
+        // value and depends on the key.
+        #[test]
+        fn test_reference_value() {
+            let mut key = [0; 16];
+            for (i, b) in key.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+
+            let f = StableBloomFilter::new_keyed(1000, 1, 0.01, key);
+            assert_eq!(f.hash64(b""), 0x726f_db47_dd0e_0e31);
+
+            let other = StableBloomFilter::new_keyed(1000, 1, 0.01, [0; 16]);
+            assert_ne!(other.hash64(b"a"), f.hash64(b"a"));
+            assert_eq!(format!("{:?}", f.hasher()), "SipHashBuilder");
+        }
+
+        // Ensures that a keyed filter tests positive for added data.
+        #[test]
+        fn test_filter() {
+            let builder = SipHashBuilder::new_with_key([42; 16]);
+            let mut f = StableBloomFilter::with_hasher(10_000, 2, 0.01, builder);
+            f.add(b"a");
+            assert!(f.test(b"a"));
+            assert!(!f.test(b"b"));
+        }
+    }
+}
+
+#[cfg(feature = "xxhash")]
+mod xxhash {
+    use std::hash::BuildHasher;
+    use twox_hash::XxHash64;
+
+    /// XxHashBuilder creates 64-bit xxHash hashers which all use the same
+    /// seed. xxHash processes 32 bytes per round instead of one, which makes
+    /// it much faster than FNV for keys longer than a few dozen bytes.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct XxHashBuilder {
+        seed: u64,
+    }
+
+    impl XxHashBuilder {
+        /// Creates an xxHash build hasher whose hashers use the provided
+        /// seed.
+        #[inline]
+        pub fn with_seed(seed: u64) -> XxHashBuilder {
+            XxHashBuilder { seed }
+        }
+
+        /// Returns the seed the hashers use.
+        #[inline]
+        pub fn seed(&self) -> u64 {
+            self.seed
+        }
+    }
+
+    impl BuildHasher for XxHashBuilder {
+        type Hasher = XxHash64;
+
+        #[inline]
+        fn build_hasher(&self) -> XxHash64 {
+            XxHash64::with_seed(self.seed)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::XxHashBuilder;
+        use crate::stable::StableBloomFilter;
+        use crate::Filter;
+
+        // Ensures that the kernel produces the reference xxHash64 values.
+        #[test]
+        fn test_reference_values() {
+            let f = StableBloomFilter::with_hasher(1000, 1, 0.01, XxHashBuilder::default());
+            assert_eq!(f.hash64(b""), 0xef46_db37_51d8_e999);
+            assert_eq!(f.hash64(b"a"), 0xd24e_c4f1_a98c_6e5b);
+
+            let seeded = StableBloomFilter::with_hasher(1000, 1, 0.01, XxHashBuilder::with_seed(1));
+            assert_eq!(seeded.hasher().seed(), 1);
+            assert_ne!(seeded.hash64(b"a"), f.hash64(b"a"));
+        }
+
+        // Ensures that a filter using the xxHash kernel tests positive for
+        // added data, including large payloads.
+        #[test]
+        fn test_filter() {
+            let mut f = StableBloomFilter::with_hasher(10_000, 2, 0.01, XxHashBuilder::default());
+            let payload = vec![7u8; 4096];
+
+            f.add(&payload);
+            f.add(b"a");
+            assert!(f.test(&payload));
+            assert!(f.test(b"a"));
+            assert!(!f.test(b"b"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HashScheme, Murmur3Builder};
+    use crate::stable::ProbeScheme;
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+    use std::hash::{BuildHasher, Hasher};
+
+    // Ensures that the Murmur3 kernel produces the reference MurmurHash3
+    // x64_128 digests, covering inputs with and without a partial block.
+    #[test]
+    fn test_murmur3_reference_values() {
+        let digest = |seed: u32, data: &[u8]| {
+            let mut hasher = Murmur3Builder::with_seed(seed).build_hasher();
+            hasher.write(data);
+            hasher.finish128()
+        };
+        assert_eq!(digest(0, b""), (0, 0));
+        assert_eq!(
+            digest(0, b"hell"),
+            (0x6299_4269_3e10_f867, 0x92db_0b82_baeb_5347)
+        );
+        assert_eq!(
+            digest(0, b"The quick brown fox jumps over the lazy dog"),
+            (0xe34b_bc7b_bc07_1b6c, 0x7a43_3ca9_c49a_9347)
+        );
+
+        let f = StableBloomFilter::with_hasher(1000, 1, 0.01, Murmur3Builder::default());
+        assert_eq!(f.hash64(b"hell"), 0x6299_4269_3e10_f867);
+        let seeded = StableBloomFilter::with_hasher(1000, 1, 0.01, Murmur3Builder::with_seed(1));
+        assert_eq!(seeded.hasher().seed(), 1);
+        assert_ne!(seeded.hash64(b"hell"), f.hash64(b"hell"));
+    }
+
+    // Ensures that a filter using the Murmur3 kernel tests positive for added
+    // data.
+    #[test]
+    fn test_murmur3_filter() {
+        let mut f = StableBloomFilter::with_hasher(10_000, 2, 0.01, Murmur3Builder::default());
+        f.add(b"a");
+        assert!(f.test(b"a"));
+        assert!(!f.test(b"b"));
+    }
+
+    // Ensures that the cell indices of every scheme stay the same across
+    // crate versions, and that filters report the scheme of their kernel.
+    #[test]
+    fn test_hash_scheme_indices() {
+        fn indices<S: BuildHasher + 'static>(
+            mut f: StableBloomFilter<S>,
+            scheme: HashScheme,
+        ) -> Vec<usize> {
+            if scheme.probe_scheme() == Some(ProbeScheme::DoubleHashing) {
+                f.set_probe_scheme(ProbeScheme::DoubleHashing);
+            }
+            assert_eq!(f.hash_scheme(), scheme);
+            f.hash_indices(b"a").collect()
+        }
+
+        let fnv = || StableBloomFilter::new(1000, 1, 0.01);
+        assert_eq!(indices(fnv(), HashScheme::FnvV1), vec![36, 296, 556]);
+        assert_eq!(indices(fnv(), HashScheme::FnvV2), vec![36, 297, 558]);
+        #[cfg(feature = "siphash")]
+        {
+            let sip = StableBloomFilter::new_keyed(1000, 1, 0.01, [0; 16]);
+            assert_eq!(indices(sip, HashScheme::Sip24V1), vec![617, 146, 675]);
+        }
+        let murmur = StableBloomFilter::with_hasher(1000, 1, 0.01, Murmur3Builder::default());
+        assert_eq!(indices(murmur, HashScheme::Murmur3V1), vec![697, 846, 995]);
+        let go_fnv = StableBloomFilter::new_boomfilters(1000, 1, 0.01);
+        assert_eq!(indices(go_fnv, HashScheme::GoFnvV1), vec![518, 842, 166]);
+        #[cfg(feature = "xxhash")]
+        {
+            let xx = StableBloomFilter::with_hasher(1000, 1, 0.01, super::XxHashBuilder::default());
+            assert_eq!(indices(xx, HashScheme::Xx64V1), vec![795, 380, 965]);
+        }
+
+        let custom = StableBloomFilter::with_hasher(
+            1000,
+            1,
+            0.01,
+            std::collections::hash_map::RandomState::new(),
+        );
+        assert_eq!(custom.hash_scheme(), HashScheme::Custom);
+        for id in 0..7 {
+            assert_eq!(HashScheme::from_id(id).unwrap().id(), id);
+        }
+        assert_eq!(HashScheme::from_id(7), None);
+    }
+}
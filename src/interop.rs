@@ -0,0 +1,671 @@
+//! Interoperability with the binary formats of other Bloom filter
+//! implementations, so that live filter state can be migrated without a cold
+//! start.
+//!
+//! # Go BoomFilters
+//!
+//! `write_boomfilters_to` and `read_boomfilters_from` match the layout of
+//! `StableBloomFilter.WriteTo` and `ReadFrom` in tylertreat/BoomFilters. All
+//! integers are big-endian:
+//!
+//! | size  | field                                       |
+//! |-------|---------------------------------------------|
+//! | 8     | number of cells, m                          |
+//! | 8     | number of cells decremented per add, p      |
+//! | 8     | number of hash functions, k                 |
+//! | 8     | maximum cell value                          |
+//! | 8     | length of the index buffer, n               |
+//! | 8 * n | index buffer, scratch space with no meaning |
+//! | 1     | bits per cell, d                            |
+//! | 1     | maximum cell value                          |
+//! | 8     | number of cells, m                          |
+//! | 8     | length of the cell data                     |
+//! | ...   | cell data, packed like `Buckets`            |
+//!
+//! BoomFilters hashes data with Go's `hash/fnv` FNV-1 rather than FNV-1a, so
+//! migrated filters use `GoFnvBuildHasher` to keep finding the elements the Go
+//! service added.
+//!
+//! # RedisBloom
+//!
+//! `RedisBloomFilter::to_redisbloom_chunks` and `from_redisbloom_chunks`
+//! convert a classic, non-scaling filter to and from the chunks returned by
+//! `BF.SCANDUMP` and accepted by `BF.LOADCHUNK`. The first chunk, with
+//! iterator 1, is a little-endian packed header:
+//!
+//! | size | field                                        |
+//! |------|----------------------------------------------|
+//! | 8    | number of items added                        |
+//! | 4    | number of sub-filters, always 1              |
+//! | 4    | creation options                             |
+//! | 4    | growth factor of a scaling filter            |
+//! | 8    | sub-filter size in bytes                     |
+//! | 8    | sub-filter size in bits                      |
+//! | 8    | number of items added to the sub-filter      |
+//! | 8    | target false-positive rate, IEEE 754         |
+//! | 8    | bits per element, IEEE 754                   |
+//! | 4    | number of hash functions                     |
+//! | 8    | capacity of the sub-filter                   |
+//! | 1    | log2 of the size in bits if it was rounded   |
+//!
+//! Every following chunk holds a slice of the bit array, whose iterator is
+//! one past the offset of the chunk's end. Bits are packed least significant
+//! bit first, like 1-bit `Buckets`. Elements are hashed with 64-bit
+//! MurmurHash64A, which RedisBloom uses for every filter created since 64-bit
+//! hashing became its default.
+
+use crate::buckets::{expected_byte_len, Buckets};
+use crate::error::SnapshotError;
+use crate::stable::{stable_zeros, ProbeScheme, StableBloomFilter};
+use crate::Filter;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+
+/// FNV-1 offset basis.
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1 prime.
+const PRIME: u64 = 0x0100_0000_01b3;
+
+/// GoFnvHasher implements FNV-1, which multiplies before XOR-ing in every
+/// byte, as returned by Go's `fnv.New64`.
+#[derive(Clone, Debug)]
+pub struct GoFnvHasher(u64);
+
+impl Default for GoFnvHasher {
+    #[inline]
+    fn default() -> GoFnvHasher {
+        GoFnvHasher(OFFSET_BASIS)
+    }
+}
+
+impl Hasher for GoFnvHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        let GoFnvHasher(mut hash) = *self;
+
+        for byte in bytes.iter() {
+            hash = hash.wrapping_mul(PRIME);
+            hash ^= u64::from(*byte);
+        }
+
+        *self = GoFnvHasher(hash);
+    }
+}
+
+/// GoFnvBuildHasher creates `GoFnvHasher`s, so that a filter derives the same
+/// cell indices as a BoomFilters filter for the same data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoFnvBuildHasher;
+
+impl BuildHasher for GoFnvBuildHasher {
+    type Hasher = GoFnvHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> GoFnvHasher {
+        GoFnvHasher::default()
+    }
+}
+
+impl StableBloomFilter<GoFnvBuildHasher> {
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, which hashes data
+    /// like BoomFilters' `NewStableBloomFilter`. BoomFilters derives cell
+    /// indices by plain double hashing, so the filter does too.
+    pub fn new_boomfilters(m: usize, d: u8, fp_rate: f64) -> Self {
+        let mut f = Self::with_hasher(m, d, fp_rate, GoFnvBuildHasher);
+        f.set_probe_scheme(ProbeScheme::DoubleHashing);
+        f
+    }
+
+    /// Writes the filter to w in the layout of BoomFilters'
+    /// `StableBloomFilter.WriteTo`. Fails with `InvalidInput` if the filter
+    /// doesn't derive cell indices by plain double hashing, as BoomFilters
+    /// would look for its elements in other cells.
+    pub fn write_boomfilters_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        if self.probe_scheme() != ProbeScheme::DoubleHashing {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "BoomFilters only supports plain double hashing",
+            ));
+        }
+        let buckets = self.buckets();
+        for value in [self.cells(), self.p(), self.k(), usize::from(self.max())].iter() {
+            w.write_all(&(*value as u64).to_be_bytes())?;
+        }
+        // The index buffer is scratch space, written as k zeros.
+        w.write_all(&(self.k() as u64).to_be_bytes())?;
+        for _ in 0..(self.k()) {
+            w.write_all(&0u64.to_be_bytes())?;
+        }
+
+        w.write_all(&[buckets.bucket_size(), buckets.max_bucket_value()])?;
+        w.write_all(&(buckets.count() as u64).to_be_bytes())?;
+        w.write_all(&(buckets.as_bytes().len() as u64).to_be_bytes())?;
+        w.write_all(buckets.as_bytes())
+    }
+
+    /// Reads a filter written by BoomFilters' `StableBloomFilter.WriteTo`.
+    /// The Go format doesn't record the target false-positive rate, so it is
+    /// derived from the filter's stable point.
+    pub fn read_boomfilters_from<R: Read>(mut r: R) -> Result<Self, SnapshotError> {
+        let m = to_usize(read_u64(&mut r)?)?;
+        let p = to_usize(read_u64(&mut r)?)?;
+        let k = to_usize(read_u64(&mut r)?)?;
+        let max = read_u64(&mut r)?;
+        if k == 0 {
+            return Err(SnapshotError::InvalidHashFunctions);
+        }
+        if m == 0 || k > m || read_u64(&mut r)? != k as u64 {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+        for _ in 0..k {
+            read_u64(&mut r)?;
+        }
+
+        let mut header = [0; 2];
+        r.read_exact(&mut header)?;
+        let d = header[0];
+        if d == 0 || d > 8 {
+            return Err(SnapshotError::InvalidBucketSize(d));
+        }
+        let count = to_usize(read_u64(&mut r)?)?;
+        let len = to_usize(read_u64(&mut r)?)?;
+        if m.checked_mul(usize::from(d)).is_none() {
+            return Err(SnapshotError::UnsupportedLayout);
+        }
+        let expected_max = (1u64 << d) - 1;
+        if count != m
+            || len != expected_byte_len(m, d)
+            || max != expected_max
+            || u64::from(header[1]) != expected_max
+        {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+
+        // The data is read as it arrives, so that truncated input doesn't
+        // allocate the cells it claims to hold.
+        let mut data = Vec::new();
+        r.take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let cells =
+            Buckets::from_raw_parts(data, m, d).expect("data is sized by expected_byte_len");
+        let fp_rate = (1.0 - stable_zeros(m, k, p, max as u8)).powf(k as f64);
+
+        let mut f =
+            StableBloomFilter::from_parts_with_hasher(cells, GoFnvBuildHasher, k, p, fp_rate);
+        f.set_probe_scheme(ProbeScheme::DoubleHashing);
+        Ok(f)
+    }
+}
+
+/// `BF.RESERVE` option which keeps the bit array size unrounded.
+const REDISBLOOM_NOROUND: u32 = 1;
+
+/// `BF.RESERVE` option which selects 64-bit hashing.
+const REDISBLOOM_FORCE64: u32 = 4;
+
+/// `BF.RESERVE` option which disables scaling.
+const REDISBLOOM_NO_SCALING: u32 = 8;
+
+/// Seed of the first MurmurHash64A hash.
+const REDISBLOOM_SEED: u64 = 0xc6a4_a793_5bd1_e995;
+
+/// Size of the packed RedisBloom header of a filter with one sub-filter.
+const REDISBLOOM_HEADER_LEN: usize = 20 + 53;
+
+/// Maximum size of the bit array chunks written by `to_redisbloom_chunks`.
+pub const REDISBLOOM_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// RedisBloomFilter is a classic Bloom filter laid out and hashed like a
+/// non-scaling RedisBloom filter, so that its state can be moved to and from
+/// a Redis deployment with `BF.SCANDUMP` and `BF.LOADCHUNK`.
+pub struct RedisBloomFilter {
+    /// filter data
+    bits: Buckets,
+    /// number of hash functions
+    hashes: u32,
+    /// expected number of items
+    capacity: u64,
+    /// target false-positive rate
+    error: f64,
+    /// bits per element
+    bpe: f64,
+    /// number of items added
+    count: u64,
+    /// log2 of the number of bits if it was rounded to a power of two
+    n2: u8,
+}
+
+impl RedisBloomFilter {
+    /// Creates a new filter sized like `BF.RESERVE key error capacity
+    /// NONSCALING`.
+    pub fn new(capacity: usize, error: f64) -> Self {
+        let bpe = -error.ln() / std::f64::consts::LN_2.powi(2);
+        let bits = ((capacity as f64) * bpe) as u64;
+        // RedisBloom rounds the bit array up to whole 64-bit words.
+        let bytes = bits.div_ceil(64).max(1) * 8;
+
+        RedisBloomFilter {
+            bits: Buckets::new((bytes * 8) as usize, 1),
+            hashes: (std::f64::consts::LN_2 * bpe).ceil() as u32,
+            capacity: capacity as u64,
+            error,
+            bpe,
+            count: 0,
+            n2: 0,
+        }
+    }
+
+    /// Returns the number of bits in the filter.
+    pub fn capacity(&self) -> usize {
+        self.bits.count()
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.hashes as usize
+    }
+
+    /// Returns the number of items added to the filter.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the chunks `BF.SCANDUMP` would return for the filter, as
+    /// pairs of iterator and data to pass to `BF.LOADCHUNK` in order.
+    pub fn to_redisbloom_chunks(&self) -> Vec<(i64, Vec<u8>)> {
+        let data = self.bits.as_bytes();
+        let mut header = Vec::with_capacity(REDISBLOOM_HEADER_LEN);
+        header.extend_from_slice(&self.count.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        let options = REDISBLOOM_NOROUND | REDISBLOOM_FORCE64 | REDISBLOOM_NO_SCALING;
+        header.extend_from_slice(&options.to_le_bytes());
+        header.extend_from_slice(&2u32.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        header.extend_from_slice(&(self.bits.count() as u64).to_le_bytes());
+        header.extend_from_slice(&self.count.to_le_bytes());
+        header.extend_from_slice(&self.error.to_bits().to_le_bytes());
+        header.extend_from_slice(&self.bpe.to_bits().to_le_bytes());
+        header.extend_from_slice(&self.hashes.to_le_bytes());
+        header.extend_from_slice(&self.capacity.to_le_bytes());
+        header.push(self.n2);
+
+        let mut chunks = vec![(1, header)];
+        let mut offset = 0;
+        for chunk in data.chunks(REDISBLOOM_CHUNK_SIZE) {
+            offset += chunk.len();
+            chunks.push(((offset + 1) as i64, chunk.to_vec()));
+        }
+        chunks
+    }
+
+    /// Creates a filter from the chunks returned by `BF.SCANDUMP`, starting
+    /// with the header and followed by the bit array chunks in order. Scaling
+    /// filters with more than one sub-filter and filters using the legacy
+    /// 32-bit hashing aren't supported.
+    pub fn from_redisbloom_chunks<I, B>(chunks: I) -> Result<Self, SnapshotError>
+    where
+        I: IntoIterator<Item = (i64, B)>,
+        B: AsRef<[u8]>,
+    {
+        let mut chunks = chunks.into_iter();
+        let header = match chunks.next() {
+            Some((1, header)) => header,
+            _ => return Err(SnapshotError::InconsistentHeader),
+        };
+        let mut r = header.as_ref();
+        if r.len() != REDISBLOOM_HEADER_LEN {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+
+        let count = read_le_u64(&mut r)?;
+        let filters = read_le_u32(&mut r)?;
+        let options = read_le_u32(&mut r)?;
+        let _growth = read_le_u32(&mut r)?;
+        if filters != 1 || options & REDISBLOOM_FORCE64 == 0 {
+            return Err(SnapshotError::UnsupportedLayout);
+        }
+        let bytes = to_usize(read_le_u64(&mut r)?)?;
+        let bits = to_usize(read_le_u64(&mut r)?)?;
+        let _link_count = read_le_u64(&mut r)?;
+        let error = f64::from_bits(read_le_u64(&mut r)?);
+        let bpe = f64::from_bits(read_le_u64(&mut r)?);
+        let hashes = read_le_u32(&mut r)?;
+        let capacity = read_le_u64(&mut r)?;
+        let n2 = r[0];
+        if hashes == 0 {
+            return Err(SnapshotError::InvalidHashFunctions);
+        }
+        if bytes.checked_mul(8) != Some(bits) || bits == 0 || hashes as usize > bits {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+
+        // Every chunk must continue where the previous one ended, so that the
+        // data only grows with the chunks actually received.
+        let mut data = Vec::new();
+        for (iter, chunk) in chunks {
+            let chunk = chunk.as_ref();
+            let end = data.len() + chunk.len();
+            if iter < 1 || end > bytes || iter as u64 != end as u64 + 1 {
+                return Err(SnapshotError::InconsistentHeader);
+            }
+            data.extend_from_slice(chunk);
+        }
+        if data.len() != bytes {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+
+        Ok(RedisBloomFilter {
+            bits: Buckets::from_raw_parts(data, bits, 1).expect("data is sized from the header"),
+            hashes,
+            capacity,
+            error,
+            bpe,
+            count,
+            n2,
+        })
+    }
+
+    /// Returns the two 64-bit hashes of the data from which the bit indices
+    /// are derived.
+    fn hash_kernel(&self, data: &[u8]) -> (u64, u64) {
+        let a = murmur_hash64a(data, REDISBLOOM_SEED);
+        (a, murmur_hash64a(data, a))
+    }
+
+    /// Returns the bit index of the i-th hash function.
+    #[inline]
+    fn location(&self, a: u64, b: u64, i: u32) -> usize {
+        (a.wrapping_add(u64::from(i).wrapping_mul(b)) % (self.bits.count() as u64)) as usize
+    }
+}
+
+impl Filter for RedisBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    fn test(&self, data: &[u8]) -> bool {
+        let (a, b) = self.hash_kernel(data);
+        (0..(self.hashes)).all(|i| self.bits.get(self.location(a, b, i)) != 0)
+    }
+
+    /// Will add the data to the filter. It returns the filter to allow for
+    /// chaining.
+    fn add(&mut self, data: &[u8]) {
+        let (a, b) = self.hash_kernel(data);
+        for i in 0..(self.hashes) {
+            let location = self.location(a, b, i);
+            self.bits.set(location, 1);
+        }
+        self.count += 1;
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        let member = self.test(data);
+        self.add(data);
+        member
+    }
+}
+
+/// Returns the MurmurHash64A hash of the data with the provided seed.
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes([
+            block[0], block[1], block[2], block[3], block[4], block[5], block[6], block[7],
+        ]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= u64::from(*byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Reads a little-endian u32.
+fn read_le_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Reads a little-endian u64.
+fn read_le_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads a big-endian u64.
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Converts a size read from the input, which must fit in a usize.
+fn to_usize(value: u64) -> Result<usize, SnapshotError> {
+    usize::try_from(value).map_err(|_| SnapshotError::UnsupportedLayout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{murmur_hash64a, GoFnvHasher, RedisBloomFilter, REDISBLOOM_HEADER_LEN};
+    use crate::error::SnapshotError;
+    use crate::stable::{ProbeScheme, StableBloomFilter};
+    use crate::Filter;
+    use std::hash::Hasher;
+
+    // Ensures that the hasher matches the FNV-1 reference values.
+    #[test]
+    fn test_go_fnv() {
+        let hash = |data: &[u8]| {
+            let mut hasher = GoFnvHasher::default();
+            hasher.write(data);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_bd4c_8601_b7be);
+        assert_eq!(hash(b"foobar"), 0x340d_8765_a4dd_a9c2);
+    }
+
+    // Ensures that a filter is written in the BoomFilters layout and read back
+    // with its parameters, cells and memberships.
+    #[test]
+    fn test_boomfilters_round_trip() {
+        let mut f = StableBloomFilter::new_boomfilters(1000, 2, 0.01);
+        f.set_eviction_enabled(false);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let mut buf = Vec::new();
+        f.write_boomfilters_to(&mut buf).unwrap();
+        let header = 8 * (5 + f.k());
+        assert_eq!(buf.len(), header + 18 + 250);
+        assert_eq!(&buf[0..8], &1000u64.to_be_bytes());
+        assert_eq!(&buf[24..32], &3u64.to_be_bytes());
+        assert_eq!(&buf[header..header + 2], &[2, 3]);
+
+        let restored = StableBloomFilter::read_boomfilters_from(buf.as_slice()).unwrap();
+        assert_eq!(restored.cells(), 1000);
+        assert_eq!(restored.k(), f.k());
+        assert_eq!(restored.p(), f.p());
+        assert!((restored.target_fp_rate() - f.false_positive_rate()).abs() < 1e-12);
+        for i in 0..1000 {
+            assert_eq!(restored.cell_value(i), f.cell_value(i));
+        }
+        for i in 0..50 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+    }
+
+    // Ensures that truncated or inconsistent input is rejected.
+    #[test]
+    fn test_boomfilters_invalid() {
+        let f = StableBloomFilter::new_boomfilters(100, 1, 0.01);
+        let mut buf = Vec::new();
+        f.write_boomfilters_to(&mut buf).unwrap();
+
+        let truncated = &buf[..buf.len() - 1];
+        assert!(matches!(
+            StableBloomFilter::read_boomfilters_from(truncated),
+            Err(SnapshotError::Io(_))
+        ));
+
+        let mut inconsistent = buf.clone();
+        inconsistent[7] = 99;
+        assert!(matches!(
+            StableBloomFilter::read_boomfilters_from(inconsistent.as_slice()),
+            Err(SnapshotError::InconsistentHeader)
+        ));
+
+        // Zero cells, more hash functions than cells and an index buffer
+        // whose length isn't k.
+        for (offset, value) in [(0, 0), (16, 101), (32, u64::MAX)].iter() {
+            let mut inconsistent = buf.clone();
+            inconsistent[*offset..*offset + 8].copy_from_slice(&value.to_be_bytes());
+            assert!(matches!(
+                StableBloomFilter::read_boomfilters_from(inconsistent.as_slice()),
+                Err(SnapshotError::InconsistentHeader)
+            ));
+        }
+
+        // A filter claiming far more cells than the input holds is rejected
+        // without allocating them.
+        let mut oversized = buf.clone();
+        let header = 8 * (5 + f.k());
+        oversized[0..8].copy_from_slice(&(1u64 << 50).to_be_bytes());
+        oversized[header + 2..header + 10].copy_from_slice(&(1u64 << 50).to_be_bytes());
+        oversized[header + 10..header + 18].copy_from_slice(&(1u64 << 47).to_be_bytes());
+        assert!(matches!(
+            StableBloomFilter::read_boomfilters_from(oversized.as_slice()),
+            Err(SnapshotError::Io(_))
+        ));
+    }
+
+    // Ensures that filters which use enhanced double hashing aren't written
+    // in a layout BoomFilters would misread.
+    #[test]
+    fn test_boomfilters_probe_scheme() {
+        let f = StableBloomFilter::new_boomfilters(100, 1, 0.01);
+        assert_eq!(f.probe_scheme(), ProbeScheme::DoubleHashing);
+
+        let mut f = f;
+        f.set_probe_scheme(ProbeScheme::EnhancedDoubleHashing);
+        let err = f.write_boomfilters_to(Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    // Ensures that the MurmurHash64A tail handling mixes every byte.
+    #[test]
+    fn test_murmur_hash64a() {
+        assert_eq!(murmur_hash64a(b"", 0), 0);
+        let hashes: Vec<u64> = (0..=9)
+            .map(|n| murmur_hash64a(&b"abcdefghi"[..n], 1))
+            .collect();
+        for (i, a) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|b| a != b));
+        }
+    }
+
+    // Ensures that a filter is sized like BF.RESERVE and survives a round
+    // trip through its SCANDUMP chunks.
+    #[test]
+    fn test_redisbloom_round_trip() {
+        let mut f = RedisBloomFilter::new(1000, 0.01);
+        assert_eq!(f.capacity(), 9600);
+        assert_eq!(f.k(), 7);
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let chunks = f.to_redisbloom_chunks();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].0, 1);
+        assert_eq!(chunks[0].1.len(), REDISBLOOM_HEADER_LEN);
+        assert_eq!(chunks[1].0, 1201);
+
+        let restored = RedisBloomFilter::from_redisbloom_chunks(chunks).unwrap();
+        assert_eq!(restored.count(), 1000);
+        assert_eq!(restored.capacity(), f.capacity());
+        for i in 0..1000 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+        let false_positives = (1000..11_000)
+            .filter(|i| restored.test(i.to_string().as_bytes()))
+            .count();
+        assert!(false_positives < 200);
+    }
+
+    // Ensures that chunks out of bounds and unsupported headers are rejected.
+    #[test]
+    fn test_redisbloom_invalid() {
+        let f = RedisBloomFilter::new(100, 0.01);
+        let mut chunks = f.to_redisbloom_chunks();
+        chunks[1].0 += 1;
+        assert!(matches!(
+            RedisBloomFilter::from_redisbloom_chunks(chunks.clone()),
+            Err(SnapshotError::InconsistentHeader)
+        ));
+
+        // A missing chunk, and a header claiming far more bits than the
+        // chunks hold, more hash functions than bits or bits which aren't
+        // the bytes times 8.
+        let chunks = f.to_redisbloom_chunks();
+        assert!(matches!(
+            RedisBloomFilter::from_redisbloom_chunks(chunks[..1].to_vec()),
+            Err(SnapshotError::InconsistentHeader)
+        ));
+        let headers: Vec<(usize, Vec<u8>)> = vec![
+            (20, (1u64 << 50).to_le_bytes().to_vec()),
+            (60, u32::MAX.to_le_bytes().to_vec()),
+            (28, u64::MAX.to_le_bytes().to_vec()),
+        ];
+        for (offset, field) in headers {
+            let mut chunks = chunks.clone();
+            if offset == 20 {
+                chunks[0].1[28..36].copy_from_slice(&(1u64 << 53).to_le_bytes());
+            }
+            chunks[0].1[offset..offset + field.len()].copy_from_slice(&field);
+            assert!(matches!(
+                RedisBloomFilter::from_redisbloom_chunks(chunks),
+                Err(SnapshotError::InconsistentHeader)
+            ));
+        }
+
+        let mut chunks = f.to_redisbloom_chunks();
+        chunks[0].1[8] = 2;
+        assert!(matches!(
+            RedisBloomFilter::from_redisbloom_chunks(chunks),
+            Err(SnapshotError::UnsupportedLayout)
+        ));
+    }
+}
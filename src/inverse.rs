@@ -0,0 +1,151 @@
+use crate::fnv::FnvHasher;
+use crate::sharded::lock;
+use crate::Filter;
+use std::hash::Hasher;
+use std::sync::Mutex;
+
+/// InverseBloomFilter is a concurrent "inverse" Bloom filter, which is
+/// effectively the opposite of a classic Bloom filter. This was originally
+/// described and written by Jeff Hodges:
+///
+/// http://www.somethingsimilar.com/2012/05/21/the-opposite-of-a-bloom-filter/
+///
+/// The InverseBloomFilter may report a false negative but can never report a
+/// false positive. That is, it may report that an item has not been seen when
+/// it actually has, but it will never report an item as seen which it hasn't
+/// come across. This behaves in a similar manner to a fixed-size hashmap which
+/// does not handle conflicts.
+///
+/// Every slot holds the last element hashed to it, behind its own lock, so
+/// that the filter can be shared between threads. Recently observed elements
+/// are reported until another element hashed to the same slot replaces them.
+pub struct InverseBloomFilter {
+    /// the most recently observed element of every slot
+    array: Vec<Mutex<Option<Box<[u8]>>>>,
+    /// hash function
+    hash: FnvHasher,
+}
+
+impl InverseBloomFilter {
+    /// Creates and returns a new InverseBloomFilter with the specified
+    /// capacity. Panics if capacity is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "an inverse filter needs at least one slot");
+
+        InverseBloomFilter {
+            array: (0..capacity).map(|_| Mutex::new(None)).collect(),
+            hash: FnvHasher::default(),
+        }
+    }
+
+    /// Returns the filter capacity.
+    pub fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false negatives but a zero probability of
+    /// false positives. That is, it may return false even though the data was
+    /// added, but it will never return true for data that hasn't been added.
+    pub fn test(&self, data: &[u8]) -> bool {
+        let slot = lock(&self.array[self.index(data)]);
+        slot.as_deref() == Some(data)
+    }
+
+    /// Will add the data to the filter, replacing the element previously held
+    /// by its slot.
+    pub fn add(&self, data: &[u8]) {
+        *lock(&self.array[self.index(data)]) = Some(data.into());
+    }
+
+    /// Is equivalent to calling Test followed by Add atomically. It returns
+    /// true if the data is a member, false if not.
+    pub fn test_and_add(&self, data: &[u8]) -> bool {
+        let mut slot = lock(&self.array[self.index(data)]);
+        if slot.as_deref() == Some(data) {
+            return true;
+        }
+        *slot = Some(data.into());
+        false
+    }
+
+    /// Returns the slot index of the data.
+    #[inline]
+    fn index(&self, data: &[u8]) -> usize {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        (hasher.finish() % (self.array.len() as u64)) as usize
+    }
+}
+
+impl Filter for InverseBloomFilter {
+    fn test(&self, data: &[u8]) -> bool {
+        InverseBloomFilter::test(self, data)
+    }
+
+    fn add(&mut self, data: &[u8]) {
+        InverseBloomFilter::add(self, data);
+    }
+
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        InverseBloomFilter::test_and_add(self, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InverseBloomFilter;
+    use std::thread;
+
+    // Ensures that TestAndAdd reports recently added elements as members and
+    // never reports elements which weren't added.
+    #[test]
+    fn test_inverse_test_and_add() {
+        let f = InverseBloomFilter::new(2);
+        assert_eq!(f.capacity(), 2);
+        assert!(!f.test(b"a"));
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test(b"a"));
+        assert!(f.test_and_add(b"a"));
+        assert!(!f.test(b"b"));
+    }
+
+    // Ensures that an element is forgotten once another element replaces it
+    // in its slot.
+    #[test]
+    fn test_inverse_false_negative() {
+        let f = InverseBloomFilter::new(1);
+        f.add(b"a");
+        f.add(b"b");
+        assert!(!f.test(b"a"));
+        assert!(f.test(b"b"));
+    }
+
+    // Ensures that the filter never reports false positives while shared
+    // between threads.
+    #[test]
+    fn test_inverse_concurrent() {
+        let f = InverseBloomFilter::new(100);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let f = &f;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        f.test_and_add(format!("{}-{}", t, i).as_bytes());
+                    }
+                });
+            }
+        });
+
+        let members = (0..100)
+            .filter(|i| f.test(format!("4-{}", i).as_bytes()))
+            .count();
+        assert_eq!(members, 0);
+        let recent = (0..4)
+            .flat_map(|t| (990..1000).map(move |i| format!("{}-{}", t, i)))
+            .filter(|data| f.test(data.as_bytes()))
+            .count();
+        assert!(recent > 0);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::Filter;
+
+/// SeenTagger wraps an iterator and a filter, yielding every item together
+/// with whether the filter has seen it before. Unlike deduplication, every
+/// item is passed through.
+pub struct SeenTagger<'a, I, F> {
+    iter: I,
+    filter: &'a mut F,
+}
+
+impl<'a, I, F> SeenTagger<'a, I, F> {
+    /// Creates a new SeenTagger which tags the items of the iterator using
+    /// the provided filter.
+    pub fn new(iter: I, filter: &'a mut F) -> Self {
+        SeenTagger { iter, filter }
+    }
+}
+
+impl<'a, I, F> Iterator for SeenTagger<'a, I, F>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+    F: Filter,
+{
+    type Item = (I::Item, bool);
+
+    /// Returns the next item and true if it was seen before, false if it is
+    /// seen for the first time. The item is added to the filter either way.
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        let seen = self.filter.test_and_add(item.as_ref());
+        Some((item, seen))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// DedupStable wraps an iterator and a filter, lazily dropping the items
+/// the filter has probably seen before. With a Stable Bloom Filter, a small
+/// fraction of new items may be dropped as false positives and old
+/// duplicates may pass once they have been evicted.
+pub struct DedupStable<I, F> {
+    iter: I,
+    filter: F,
+}
+
+impl<I, F> DedupStable<I, F> {
+    /// Creates a new DedupStable which drops the items of the iterator the
+    /// provided filter has seen.
+    pub fn new(iter: I, filter: F) -> Self {
+        DedupStable { iter, filter }
+    }
+
+    /// Returns the filter.
+    pub fn inner_filter(&self) -> &F {
+        &self.filter
+    }
+
+    /// Returns the wrapped iterator and the filter.
+    pub fn into_inner(self) -> (I, F) {
+        (self.iter, self.filter)
+    }
+}
+
+impl<I, F> Iterator for DedupStable<I, F>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+    F: Filter,
+{
+    type Item = I::Item;
+
+    /// Returns the next item the filter hasn't seen, adding every item
+    /// consumed to the filter.
+    fn next(&mut self) -> Option<Self::Item> {
+        let filter = &mut self.filter;
+        self.iter.find(|item| !filter.test_and_add(item.as_ref()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+/// DedupStableExt adds `dedup_stable` to every iterator of byte-like items.
+pub trait DedupStableExt: Iterator + Sized
+where
+    Self::Item: AsRef<[u8]>,
+{
+    /// Wraps the iterator so that it drops the items the filter has probably
+    /// seen before. Pass `&mut filter` to keep using the filter afterwards.
+    fn dedup_stable<F: Filter>(self, filter: F) -> DedupStable<Self, F> {
+        DedupStable::new(self, filter)
+    }
+}
+
+impl<I> DedupStableExt for I
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DedupStableExt, SeenTagger};
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that SeenTagger passes every item through and marks repeated
+    // items as seen.
+    #[test]
+    fn test_seen_tagger() {
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        let stream = vec!["a", "b", "a", "c", "b", "a"];
+
+        let tags: Vec<(&str, bool)> = SeenTagger::new(stream.into_iter(), &mut f).collect();
+
+        assert_eq!(
+            tags,
+            vec![
+                ("a", false),
+                ("b", false),
+                ("a", true),
+                ("c", false),
+                ("b", true),
+                ("a", true),
+            ]
+        );
+    }
+
+    // Ensures that dedup_stable drops repeated items lazily, with both an
+    // owned and a borrowed filter.
+    #[test]
+    fn test_dedup_stable() {
+        let stream = vec!["a", "b", "a", "c", "b", "a"];
+        let unique: Vec<&str> = stream
+            .into_iter()
+            .dedup_stable(StableBloomFilter::new_unstable(1000, 0.01))
+            .collect();
+        assert_eq!(unique, vec!["a", "b", "c"]);
+
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        let mut dedup = (0..)
+            .map(|i: u32| (i % 3).to_le_bytes())
+            .dedup_stable(&mut f);
+        assert_eq!(dedup.next(), Some(0u32.to_le_bytes()));
+        assert_eq!(dedup.next(), Some(1u32.to_le_bytes()));
+        assert_eq!(dedup.next(), Some(2u32.to_le_bytes()));
+        assert!(f.test(&1u32.to_le_bytes()));
+    }
+}
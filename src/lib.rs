@@ -21,16 +21,107 @@
 // and memory is bounded.  For example, an SBF can be used to deduplicate
 // events from an unbounded event stream with a specified upper bound on false
 // positives and minimal false negatives.
+pub mod age_partitioned;
+#[cfg(feature = "rkyv")]
+pub mod archive;
 pub mod buckets;
+pub mod counting;
+pub mod cuckoo;
+pub mod decay;
+pub mod deletable;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod fnv;
+pub mod hash;
+pub mod interop;
+pub mod inverse;
+pub mod iter;
+pub mod partitioned;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rebuild;
+pub mod scalable;
+pub mod serialization;
+pub mod sharded;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd;
+pub mod similarity;
+pub mod sketch;
 pub mod stable;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod topk;
+pub mod view;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod window;
+#[cfg(any(feature = "postcard", feature = "bincode"))]
+pub mod wire;
 
+use std::hash::Hash;
+
+/// Filter is implemented by the membership filters of this crate. It is
+/// object safe, so that a filter type picked at runtime can be held as a
+/// `Box<dyn Filter>`.
 pub trait Filter {
     fn test(&self, _data: &[u8]) -> bool;
 
-    fn add(&mut self, _data: &[u8]) -> &Self;
+    fn add(&mut self, _data: &[u8]);
 
     fn test_and_add(&mut self, _data: &[u8]) -> bool;
+
+    /// Removes the data from the filter, if the filter supports it. Does
+    /// nothing by default.
+    fn remove(&mut self, _data: &[u8]) {}
+}
+
+impl<F: Filter + ?Sized> Filter for &mut F {
+    fn test(&self, data: &[u8]) -> bool {
+        (**self).test(data)
+    }
+
+    fn add(&mut self, data: &[u8]) {
+        (**self).add(data)
+    }
+
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        (**self).test_and_add(data)
+    }
+
+    fn remove(&mut self, data: &[u8]) {
+        (**self).remove(data)
+    }
+}
+
+impl<F: Filter + ?Sized> Filter for Box<F> {
+    fn test(&self, data: &[u8]) -> bool {
+        (**self).test(data)
+    }
+
+    fn add(&mut self, data: &[u8]) {
+        (**self).add(data)
+    }
+
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        (**self).test_and_add(data)
+    }
+
+    fn remove(&mut self, data: &[u8]) {
+        (**self).remove(data)
+    }
+}
+
+/// HashFilter is a filter of any values implementing `Hash`, which spares
+/// callers from serializing values to bytes first.
+pub trait HashFilter {
+    /// Will test for membership of the item and returns true if it is a
+    /// member, false if not.
+    fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool;
+
+    /// Will add the item to the filter. It returns the filter to allow for
+    /// chaining.
+    fn insert<T: Hash + ?Sized>(&mut self, item: &T) -> &Self;
 }
 
 /// Calculates the optimal number of hash functions to use for a Bloom
@@ -39,6 +130,13 @@ pub(crate) fn optimal_k(fp_rate: f64) -> usize {
     (1.0 / fp_rate).log2().ceil() as usize
 }
 
+/// Calculates the optimal size of a Bloom filter in bits given the expected
+/// number of elements and the desired rate of false positives.
+pub(crate) fn optimal_m(n: usize, fp_rate: f64) -> usize {
+    let ln2 = 2f64.ln();
+    ((n as f64) / ((ln2 * ln2) / fp_rate.ln().abs())).ceil() as usize
+}
+
 /// Returns the optimal number of cells to decrement, p, per
 /// iteration for the provided parameters of an SBF.
 pub(crate) fn optimal_stable_p(m: usize, k: usize, d: u8, fp_rate: f64) -> usize {
@@ -54,3 +152,69 @@ pub(crate) fn optimal_stable_p(m: usize, k: usize, d: u8, fp_rate: f64) -> usize
 
     p
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::counting::CountingBloomFilter;
+    use crate::cuckoo::CuckooFilter;
+    use crate::sharded::ShardedStableBloomFilter;
+    use crate::stable::{ConcurrentStableBloomFilter, StableBloomFilter};
+    use std::collections::HashSet;
+
+    /// Exact set that relies on the default remove.
+    struct SetFilter {
+        items: HashSet<Vec<u8>>,
+    }
+
+    impl Filter for SetFilter {
+        fn test(&self, data: &[u8]) -> bool {
+            self.items.contains(data)
+        }
+
+        fn add(&mut self, data: &[u8]) {
+            self.items.insert(data.to_vec());
+        }
+
+        fn test_and_add(&mut self, data: &[u8]) -> bool {
+            !self.items.insert(data.to_vec())
+        }
+    }
+
+    // Ensures that the default remove leaves the filter unchanged.
+    #[test]
+    fn test_default_remove() {
+        let mut f = SetFilter {
+            items: HashSet::new(),
+        };
+        f.add(b"a");
+
+        f.remove(b"a");
+        assert!(f.test(b"a"));
+        f.remove(b"b");
+        assert!(!f.test(b"b"));
+    }
+
+    // Ensures that filters picked at runtime can be used through trait
+    // objects.
+    #[test]
+    fn test_dyn_filter() {
+        let mut filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(StableBloomFilter::new(1000, 2, 0.01)),
+            Box::new(CountingBloomFilter::new_default(100, 0.01)),
+            Box::new(CuckooFilter::new(100, 0.01)),
+            Box::new(ShardedStableBloomFilter::new(2, 1000, 2, 0.01)),
+            Box::new(ConcurrentStableBloomFilter::new(1000, 2, 0.01)),
+            Box::new(SetFilter {
+                items: HashSet::new(),
+            }),
+        ];
+
+        for f in filters.iter_mut() {
+            assert!(!f.test_and_add(b"a"));
+            f.add(b"b");
+            assert!(f.test(b"a"));
+            assert!(f.test(b"b"));
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::buckets::Buckets;
+use crate::fnv::FnvHasher;
+use crate::stable::split_hash;
+use crate::{optimal_k, optimal_m, Filter};
+use std::hash::Hasher;
+
+/// PartitionedBloomFilter implements a variation of a classic Bloom filter as
+/// described by Almeida, Baquero, Preguica, and Hutchison in Scalable Bloom
+/// Filters:
+///
+/// http://gsd.di.uminho.pt/members/cbm/ps/dbloom.pdf
+///
+/// This filter works by partitioning the M-sized bit array into k slices of
+/// size m = M/k bits. Each hash function produces an index over m for its
+/// respective slice. Thus, each element is described by exactly k bits,
+/// meaning the distribution of false positives is uniform across all
+/// elements and no hash function's bit can be masked by another's.
+pub struct PartitionedBloomFilter {
+    /// filter data, k consecutive partitions
+    partitions: Buckets,
+    /// hash function (kernel for all k functions)
+    hash: FnvHasher,
+    /// filter size (divided into k partitions)
+    m: usize,
+    /// number of hash functions (and partitions)
+    k: usize,
+    /// partition size (m / k)
+    s: usize,
+    /// number of items added
+    count: usize,
+}
+
+impl PartitionedBloomFilter {
+    /// Creates a new partitioned Bloom filter optimized to store n items with
+    /// a specified target false-positive rate.
+    pub fn new(n: usize, fp_rate: f64) -> Self {
+        let k = optimal_k(fp_rate).max(1);
+        let s = optimal_m(n, fp_rate).div_ceil(k).max(1);
+
+        PartitionedBloomFilter {
+            partitions: Buckets::new(k * s, 1),
+            hash: FnvHasher::default(),
+            m: k * s,
+            k,
+            s,
+            count: 0,
+        }
+    }
+
+    /// Returns the Bloom filter capacity, m.
+    pub fn capacity(&self) -> usize {
+        self.m
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of items added to the filter.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the current estimated ratio of set bits, derived from the
+    /// number of items added.
+    pub fn estimated_fill_ratio(&self) -> f64 {
+        1.0 - (-(self.count as f64) / (self.s as f64)).exp()
+    }
+
+    /// Returns the average ratio of set bits across all partitions.
+    pub fn fill_ratio(&self) -> f64 {
+        let set = (0..(self.m))
+            .filter(|i| self.partitions.get(*i) != 0)
+            .count();
+        (set as f64) / (self.m as f64)
+    }
+
+    /// Restores the partitioned Bloom filter to its original state. It
+    /// returns the filter to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.partitions.reset();
+        self.count = 0;
+        self
+    }
+
+    /// Returns the 32-bit lower and upper halves of the hash of the data.
+    fn hash_kernel(&self, data: &[u8]) -> (u32, u32) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        split_hash(hasher.finish())
+    }
+
+    /// Returns the bit index of the i-th hash function, which lies in the i-th
+    /// partition.
+    #[inline]
+    fn location(&self, lower: u32, upper: u32, i: usize) -> usize {
+        i * self.s + (lower as usize + upper as usize * i) % self.s
+    }
+}
+
+impl Filter for PartitionedBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives but a zero probability of
+    /// false negatives. Due to the way the filter is partitioned, the
+    /// probability of false positives is uniformly distributed across all
+    /// elements.
+    fn test(&self, data: &[u8]) -> bool {
+        let (lower, upper) = self.hash_kernel(data);
+
+        // If any of the K partition bits are not set, then it's not a member.
+        (0..(self.k)).all(|i| self.partitions.get(self.location(lower, upper, i)) != 0)
+    }
+
+    /// Will add the data to the Bloom filter.
+    fn add(&mut self, data: &[u8]) {
+        let (lower, upper) = self.hash_kernel(data);
+
+        // Set the K partition bits.
+        for i in 0..(self.k) {
+            let location = self.location(lower, upper, i);
+            self.partitions.set(location, 1);
+        }
+        self.count += 1;
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        let (lower, upper) = self.hash_kernel(data);
+        let mut member = true;
+
+        // If any of the K partition bits are not set, then it's not a member.
+        for i in 0..(self.k) {
+            let location = self.location(lower, upper, i);
+            if self.partitions.get(location) == 0 {
+                member = false;
+            }
+            self.partitions.set(location, 1);
+        }
+        self.count += 1;
+        member
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartitionedBloomFilter;
+    use crate::Filter;
+
+    // Ensures that Capacity returns the number of bits, m, in the Bloom
+    // filter, rounded up to a multiple of the number of partitions.
+    #[test]
+    fn test_partitioned_capacity() {
+        let f = PartitionedBloomFilter::new(100, 0.1);
+        assert_eq!(f.capacity(), 480);
+        assert_eq!(f.k(), 4);
+    }
+
+    // Ensures that every hash function sets exactly one bit in its own
+    // partition.
+    #[test]
+    fn test_partitioned_one_bit_per_partition() {
+        let mut f = PartitionedBloomFilter::new(100, 0.01);
+        let s = f.capacity() / f.k();
+        f.add(b"a");
+
+        for i in 0..(f.k()) {
+            let set = (i * s..(i + 1) * s)
+                .filter(|j| f.partitions.get(*j) != 0)
+                .count();
+            assert_eq!(set, 1);
+        }
+    }
+
+    // Ensures that TestAndAdd reports membership before adding, that added
+    // elements are members and that Reset clears the filter.
+    #[test]
+    fn test_partitioned_test_and_add() {
+        let mut f = PartitionedBloomFilter::new(1000, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+        for i in 0..1000 {
+            assert!(f.test(i.to_string().as_bytes()));
+        }
+        assert_eq!(f.count(), 1002);
+
+        f.reset();
+        assert_eq!(f.count(), 0);
+        assert_eq!(f.fill_ratio(), 0.0);
+        assert!(!f.test(b"a"));
+    }
+
+    // Ensures that the estimated fill ratio tracks the actual ratio of set
+    // bits.
+    #[test]
+    fn test_partitioned_estimated_fill_ratio() {
+        let mut f = PartitionedBloomFilter::new(1000, 0.01);
+        assert_eq!(f.estimated_fill_ratio(), 0.0);
+        // Short decimal keys collide more often under FNV than the estimate
+        // assumes, so use keys whose bytes are well spread.
+        for i in 0..1000u64 {
+            f.add(&i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes());
+        }
+        assert!((f.estimated_fill_ratio() - 0.5).abs() < 0.05);
+        assert!((f.fill_ratio() - f.estimated_fill_ratio()).abs() < 0.05);
+    }
+}
@@ -0,0 +1,128 @@
+//! PyO3 bindings, enabled by the `python` feature, which expose the Stable
+//! Bloom Filter to Python as `stable_bloom_filter.StableBloomFilter`. Data is
+//! passed as `bytes`, and filters pickle through the binary snapshot format
+//! of the `serialization` module, so that a filter prototyped in Python can be
+//! loaded by a Rust service and vice versa.
+//!
+//! Build the extension module with maturin, which enables the `python` and
+//! `pyo3/extension-module` features through `pyproject.toml`.
+
+// The conversions of PyResult return values generated by pymethods trip up
+// clippy.
+#![allow(clippy::useless_conversion)]
+
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+/// PyStableBloomFilter wraps a Stable Bloom Filter hashing data with FNV.
+#[pyclass(name = "StableBloomFilter", module = "stable_bloom_filter")]
+pub struct PyStableBloomFilter {
+    inner: StableBloomFilter,
+}
+
+#[pymethods]
+impl PyStableBloomFilter {
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated
+    /// per cell optimized for the target false-positive rate.
+    #[new]
+    #[pyo3(signature = (m, d = 1, fp_rate = 0.01))]
+    fn new(m: usize, d: u8, fp_rate: f64) -> PyResult<Self> {
+        StableBloomFilter::try_new(m, d, fp_rate)
+            .map(|inner| PyStableBloomFilter { inner })
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    /// Reads a filter from a snapshot written by `to_bytes`.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        StableBloomFilter::read_from(data)
+            .map(|inner| PyStableBloomFilter { inner })
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    /// Returns a snapshot of the filter's parameters and cells.
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let mut buf = Vec::new();
+        self.inner
+            .write_to(&mut buf)
+            .expect("writing to a Vec never fails");
+        PyBytes::new_bound(py, &buf)
+    }
+
+    /// Will add the data to the filter.
+    fn add(&mut self, data: &[u8]) {
+        self.inner.add(data);
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    fn test(&self, data: &[u8]) -> bool {
+        self.inner.test(data)
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.inner.test_and_add(data)
+    }
+
+    /// Supports the `in` operator, equivalent to test.
+    fn __contains__(&self, data: &[u8]) -> bool {
+        self.inner.test(data)
+    }
+
+    /// Pickles the filter as a call to `from_bytes` with its snapshot.
+    #[allow(clippy::type_complexity)]
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, PyAny>, (Bound<'py, PyBytes>,))> {
+        let from_bytes = slf.get_type().getattr("from_bytes")?;
+        Ok((from_bytes, (slf.borrow().to_bytes(slf.py()),)))
+    }
+}
+
+/// Initializes the `stable_bloom_filter` Python module.
+#[pymodule]
+fn stable_bloom_filter(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyStableBloomFilter>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stable_bloom_filter;
+    use pyo3::prelude::*;
+
+    // Ensures that the filter is usable from Python and survives a pickle
+    // round trip.
+    #[test]
+    fn test_bindings() {
+        pyo3::append_to_inittab!(stable_bloom_filter);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            py.run_bound(
+                r#"
+import pickle
+from stable_bloom_filter import StableBloomFilter
+f = StableBloomFilter(1000, 2, 0.01)
+assert not f.test_and_add(b"a")
+f.add(b"b")
+assert b"a" in f and f.test(b"b")
+g = pickle.loads(pickle.dumps(f))
+assert g.test(b"a") and g.test(b"b")
+assert StableBloomFilter.from_bytes(f.to_bytes()).test(b"a")
+try:
+    StableBloomFilter(0)
+    raise AssertionError("expected a ValueError")
+except ValueError:
+    pass
+"#,
+                None,
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
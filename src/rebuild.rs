@@ -0,0 +1,110 @@
+use crate::error::MergeError;
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+
+/// Rebuilder populates a shadow Stable Bloom Filter in the background and
+/// promotes it into a live filter once it is ready. Unlike resetting the live
+/// filter, this doesn't cause every element to be forgotten at once.
+pub struct Rebuilder {
+    shadow: StableBloomFilter,
+}
+
+impl Rebuilder {
+    /// Creates a new Rebuilder whose shadow filter has m cells and d bits
+    /// allocated per cell optimized for the target false-positive rate. The
+    /// parameters must match the filter it will be promoted into.
+    pub fn new(m: usize, d: u8, fp_rate: f64) -> Self {
+        Rebuilder {
+            shadow: StableBloomFilter::new(m, d, fp_rate),
+        }
+    }
+
+    /// Returns the shadow filter being populated.
+    pub fn shadow(&self) -> &StableBloomFilter {
+        &self.shadow
+    }
+
+    /// Will add the data to the shadow filter. It returns the rebuilder to
+    /// allow for chaining.
+    pub fn add(&mut self, data: &[u8]) -> &Self {
+        self.shadow.add(data);
+        self
+    }
+
+    /// Swaps the cells of the shadow filter into the primary filter. The
+    /// shadow is reset afterwards so that the rebuilder can be reused for the
+    /// next rebuild. Returns an error and leaves both filters untouched if the
+    /// primary filter isn't compatible with the shadow or hashes data
+    /// differently.
+    pub fn promote(&mut self, primary: &mut StableBloomFilter) -> Result<(), MergeError> {
+        primary.swap_cells(&mut self.shadow)?;
+        self.shadow.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rebuilder;
+    use crate::error::MergeError;
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that promoting a shadow filter makes the primary reflect the
+    // shadow's contents and leaves the shadow empty.
+    #[test]
+    fn test_promote() {
+        let mut primary = StableBloomFilter::new_default(100_000, 0.01);
+        primary.add(b"stale");
+
+        let mut rebuilder = Rebuilder::new(100_000, 1, 0.01);
+        rebuilder.add(b"a");
+        rebuilder.add(b"b");
+        assert!(rebuilder.shadow().test(b"a"));
+        assert!(!primary.test(b"a"));
+
+        rebuilder.promote(&mut primary).unwrap();
+
+        assert!(primary.test(b"a"));
+        assert!(primary.test(b"b"));
+        assert!(!primary.test(b"stale"));
+        for i in 0..(rebuilder.shadow().cells()) {
+            assert_eq!(rebuilder.shadow().cell_value(i), 0);
+        }
+    }
+
+    // Ensures that promoting into a filter with a different geometry, number
+    // of hash functions or hash is rejected and leaves both filters intact.
+    #[test]
+    fn test_promote_mismatch() {
+        let mut rebuilder = Rebuilder::new(1000, 1, 0.01);
+        rebuilder.add(b"a");
+
+        let mut primary = StableBloomFilter::new_default(100, 0.01);
+        assert_eq!(
+            rebuilder.promote(&mut primary),
+            Err(MergeError::CellsMismatch {
+                left: 100,
+                right: 1000
+            })
+        );
+
+        let mut primary = StableBloomFilter::new_default(1000, 0.0001);
+        match rebuilder.promote(&mut primary) {
+            Err(MergeError::HashFunctionsMismatch { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut primary = StableBloomFilter::builder()
+            .cells(1000)
+            .seed(7)
+            .build()
+            .unwrap();
+        assert_eq!(
+            rebuilder.promote(&mut primary),
+            Err(MergeError::HashMismatch)
+        );
+        assert!(rebuilder.shadow().test(b"a"));
+        assert!(!primary.test(b"a"));
+    }
+}
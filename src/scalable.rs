@@ -0,0 +1,203 @@
+use crate::stable::StableBloomFilter;
+use crate::{optimal_m, Filter};
+
+/// ScalableBloomFilter implements a Scalable Bloom Filter as described by
+/// Almeida, Baquero, Preguica, and Hutchison in Scalable Bloom Filters:
+///
+/// http://gsd.di.uminho.pt/members/cbm/ps/dbloom.pdf
+///
+/// A Scalable Bloom Filter (SBF) dynamically adapts to the number of elements
+/// in the data set while enforcing a tight upper bound on the false-positive
+/// rate. It chains classic Bloom filters, adding a new one with twice the
+/// expected capacity of its predecessor once the current filter fills up, so
+/// that the number of filters grows logarithmically with the number of
+/// elements. Each new filter is created with a tighter error probability,
+/// the first one's being the target rate times 1 - r and every following
+/// one's r times its predecessor's, so that the compounded false-positive
+/// rate never exceeds the target rate. Unlike a Stable Bloom Filter, data is
+/// never evicted, which suits data sets whose cardinality is unknown but
+/// which aren't streams.
+pub struct ScalableBloomFilter {
+    /// filters with geometrically decreasing error rates
+    filters: Vec<StableBloomFilter>,
+    /// tightening ratio
+    r: f64,
+    /// target false-positive rate
+    fp: f64,
+    /// fill ratio at which a new filter is added
+    p: f64,
+    /// expected number of elements of the first filter
+    hint: usize,
+}
+
+impl ScalableBloomFilter {
+    /// Creates a new Scalable Bloom Filter with the specified target
+    /// false-positive rate and tightening ratio. Use `new_default` if you
+    /// don't want to calculate these parameters.
+    pub fn new(hint: usize, fp_rate: f64, r: f64) -> Self {
+        let mut s = ScalableBloomFilter {
+            filters: Vec::new(),
+            r,
+            fp: fp_rate,
+            p: 0.5,
+            hint: hint.max(1),
+        };
+
+        s.add_filter();
+        s
+    }
+
+    /// Creates a new Scalable Bloom Filter with the specified target
+    /// false-positive rate and an optimal tightening ratio.
+    pub fn new_default(hint: usize, fp_rate: f64) -> Self {
+        Self::new(hint, fp_rate, 0.8)
+    }
+
+    /// Returns the current Scalable Bloom Filter capacity, which is the sum
+    /// of the capacities for the contained series of Bloom filters.
+    pub fn capacity(&self) -> usize {
+        self.filters.iter().map(|f| f.cells()).sum()
+    }
+
+    /// Returns the number of hash functions used in each Bloom filter.
+    pub fn k(&self) -> usize {
+        self.filters[self.filters.len() - 1].k()
+    }
+
+    /// Returns the number of Bloom filters in the chain.
+    pub fn filters(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Returns the average ratio of set bits across every filter.
+    pub fn fill_ratio(&self) -> f64 {
+        let sum: f64 = self.filters.iter().map(|f| f.fill_ratio()).sum();
+        sum / (self.filters.len() as f64)
+    }
+
+    /// Restores the Scalable Bloom Filter to its original state. It returns
+    /// the filter to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.filters.clear();
+        self.add_filter();
+        self
+    }
+
+    /// Returns the estimated fill ratio of the newest filter, derived from the
+    /// number of elements added to it rather than by counting set bits.
+    fn estimated_fill_ratio(&self) -> f64 {
+        let f = &self.filters[self.filters.len() - 1];
+        let bits = (f.k() as f64) * (f.insert_count() as f64);
+        1.0 - (-bits / (f.cells() as f64)).exp()
+    }
+
+    /// Adds a new Bloom filter with a restricted false-positive rate to the
+    /// Scalable Bloom Filter.
+    fn add_filter(&mut self) {
+        let fp_rate = self.fp * (1.0 - self.r) * self.r.powi(self.filters.len() as i32);
+        let n = self.hint.saturating_mul(1 << self.filters.len().min(32));
+        let m = optimal_m(n, fp_rate).max(1);
+        self.filters
+            .push(StableBloomFilter::new_unstable(m, fp_rate));
+    }
+}
+
+impl Filter for ScalableBloomFilter {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives but a zero probability of
+    /// false negatives.
+    fn test(&self, data: &[u8]) -> bool {
+        // Querying is made by testing for the presence in each filter.
+        self.filters.iter().any(|f| f.test(data))
+    }
+
+    /// Will add the data to the Scalable Bloom Filter. It returns the filter
+    /// to allow for chaining.
+    fn add(&mut self, data: &[u8]) {
+        if self.estimated_fill_ratio() >= self.p {
+            self.add_filter();
+        }
+
+        let idx = self.filters.len() - 1;
+        self.filters[idx].add(data);
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        let member = self.test(data);
+        self.add(data);
+        member
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScalableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that the filter grows as elements are added and that its
+    // capacity is the sum of the capacities of its filters.
+    #[test]
+    fn test_scalable_capacity() {
+        let mut f = ScalableBloomFilter::new(1, 0.2, 0.5);
+        assert_eq!(f.filters(), 1);
+        assert_eq!(f.capacity(), 5);
+        assert_eq!(f.k(), 4);
+
+        f.add(b"a");
+        f.add(b"b");
+        assert_eq!(f.filters(), 2);
+        assert_eq!(f.capacity(), 5 + 13);
+        assert_eq!(f.k(), 5);
+    }
+
+    // Ensures that the filter has no false negatives regardless of how many
+    // elements are added.
+    #[test]
+    fn test_scalable_no_false_negatives() {
+        let mut f = ScalableBloomFilter::new_default(100, 0.01);
+        for i in 0..10_000 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert!(f.filters() > 1);
+        for i in 0..10_000 {
+            assert!(f.test(i.to_string().as_bytes()));
+        }
+    }
+
+    // Ensures that the observed false-positive rate stays below the target
+    // rate as the filter grows.
+    #[test]
+    fn test_scalable_false_positive_rate() {
+        // Keys are spread over all 64 bits, as FNV poorly mixes the upper
+        // half of its hash for short decimal strings.
+        let key = |i: u64| i.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_le_bytes();
+        let mut f = ScalableBloomFilter::new_default(1000, 0.01);
+        for i in 0..50_000 {
+            f.add(&key(i));
+        }
+        assert!(f.filters() > 1);
+
+        let false_positives = (50_000..150_000).filter(|i| f.test(&key(*i))).count();
+        assert!((false_positives as f64) / 100_000.0 < 0.01);
+    }
+
+    // Ensures that TestAndAdd reports membership before adding and that
+    // Reset restores the filter to a single empty filter.
+    #[test]
+    fn test_scalable_test_and_add_and_reset() {
+        let mut f = ScalableBloomFilter::new_default(10, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        f.reset();
+        assert_eq!(f.filters(), 1);
+        assert_eq!(f.fill_ratio(), 0.0);
+        assert!(!f.test(b"a"));
+    }
+}
@@ -0,0 +1,1013 @@
+//! A compact, versioned binary snapshot format for Stable Bloom Filters which
+//! doesn't depend on serde, so that snapshots can be exchanged with services
+//! written in other languages. Snapshots of large filters can be streamed in
+//! parts with `write_chunks` and read back incrementally with
+//! `ChunkedReader`.
+//!
+//! All integers are little-endian. A snapshot is laid out as follows:
+//!
+//! | offset | size | field                                   |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | magic bytes `SBF\0`                     |
+//! | 4      | 1    | format version, currently 5             |
+//! | 5      | 1    | hash scheme, see `HashScheme::id`       |
+//! | 6      | 1    | cell encoding, 0 for dense, 1 for sparse|
+//! | 7      | 1    | bits per cell, d                        |
+//! | 8      | 4    | number of hash functions, k             |
+//! | 12     | 8    | number of cells decremented per add, p  |
+//! | 20     | 8    | number of cells, m                      |
+//! | 28     | 8    | target false-positive rate, IEEE 754    |
+//! | 36     | 8    | FNV-1a hash kernel offset basis         |
+//! | 44     | n    | cell data                               |
+//! | 44 + n | 4    | CRC-32C of all preceding bytes          |
+//!
+//! Dense cell data holds all cells in `expected_byte_len(m, d)` bytes.
+//! Cells are packed back to back with d bits each, least significant bit
+//! first: bit j of cell i is bit (i * d + j) % 8 of byte (i * d + j) / 8, and
+//! the unused high bits of the last byte are zero. The layout doesn't depend
+//! on the byte order or word size of the platform, and cell indices are
+//! derived with 64-bit arithmetic everywhere, so a snapshot written on one
+//! target tests the same on every other. A snapshot whose m or p doesn't fit
+//! in a usize is rejected with `UnsupportedLayout`, and one with zero cells,
+//! more hash functions than cells or a false-positive rate which isn't a
+//! finite number with `InconsistentHeader`.
+//!
+//! Sparse cell data holds only the non-zero cells: their number, followed by
+//! the index and value of each cell in ascending order of index. An index is
+//! stored as its distance from the index following the previous non-zero
+//! cell, and numbers and distances as unsigned LEB128 varints. A value takes
+//! one byte. `write_to` picks whichever encoding is smaller, so freshly
+//! created or lightly loaded filters are written as sparse cell data.
+//!
+//! Snapshots whose CRC-32C (Castagnoli) checksum doesn't match, e.g. files
+//! only partially flushed to disk, are rejected with `Corrupted`, and
+//! truncated ones with an `Io` error. Snapshots of version 4 have no cell
+//! encoding byte and dense cell data, versions 1 to 3 also have no checksum,
+//! and versions 1 and 2 also lack the hash scheme byte; they are read as
+//! holding `FnvV1` and `FnvV2` filters respectively.
+//!
+//! With the `compress` feature, `write_to_compressed` and
+//! `read_from_compressed` wrap a snapshot in a zstd frame.
+//! Runtime settings such as probe ordering, overload protection or
+//! suppressed keys aren't part of a snapshot.
+
+use crate::buckets::{expected_byte_len, Buckets};
+use crate::error::SnapshotError;
+use crate::fnv::FnvBuildHasher;
+use crate::hash::HashScheme;
+use crate::stable::StableBloomFilter;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+mod chunked;
+
+pub use self::chunked::ChunkedReader;
+
+/// Magic bytes every snapshot starts with.
+pub const MAGIC: [u8; 4] = *b"SBF\0";
+
+/// Version of the snapshot format written by `write_to`.
+pub const VERSION: u8 = 5;
+
+/// Cell encoding byte of dense cell data.
+const DENSE: u8 = 0;
+
+/// Cell encoding byte of sparse cell data.
+const SPARSE: u8 = 1;
+
+/// Number of bytes of sparse cell data buffered before they are written.
+const WRITE_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reflected CRC-32C polynomial.
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+/// CRC-32C lookup table of the remainders of all byte values.
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+impl StableBloomFilter {
+    /// Writes a snapshot of the filter's parameters and cells to w.
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        let cells = self.buckets();
+        let sparse = sparse_len(cells, cells.as_bytes().len());
+        let encoding = if sparse.is_some() { SPARSE } else { DENSE };
+
+        let mut w = Checksummed::new(w);
+        w.write_all(&MAGIC)?;
+        w.write_all(&[VERSION, self.hash_scheme().id(), encoding, self.d()])?;
+        w.write_all(&(self.k() as u32).to_le_bytes())?;
+        w.write_all(&(self.p() as u64).to_le_bytes())?;
+        w.write_all(&(self.cells() as u64).to_le_bytes())?;
+        w.write_all(&self.target_fp_rate().to_bits().to_le_bytes())?;
+        w.write_all(&self.hasher().key().to_le_bytes())?;
+        match sparse {
+            Some((_, nonzero)) => write_sparse(&mut w, cells, nonzero)?,
+            None => w.write_all(cells.as_bytes())?,
+        }
+
+        let crc = w.crc();
+        w.inner.write_all(&crc.to_le_bytes())
+    }
+
+    /// Writes the snapshot written by `write_to` to w in chunks of
+    /// chunk_size bytes, the last one possibly shorter, and flushes w after
+    /// each chunk. Writers which upload every flushed chunk as one part, e.g.
+    /// to object storage, thereby stream a filter of any size while buffering
+    /// at most one chunk. Panics if chunk_size is zero.
+    pub fn write_chunks<W: Write>(&self, w: W, chunk_size: usize) -> io::Result<()> {
+        assert!(chunk_size > 0, "chunks must hold at least one byte");
+        let mut w = ChunkWriter {
+            inner: w,
+            chunk_size,
+            filled: 0,
+        };
+        self.write_to(&mut w)?;
+        if w.filled > 0 {
+            w.inner.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the snapshot written by `write_to` to w as a zstd frame
+    /// compressed at the given level, 0 selecting zstd's default. Filters
+    /// near their stable point compress far better than their cell data
+    /// suggests, as runs of zero and saturated cells are common.
+    #[cfg(feature = "compress")]
+    pub fn write_to_compressed<W: Write>(&self, w: W, level: i32) -> io::Result<()> {
+        let mut w = zstd::Encoder::new(w, level)?;
+        self.write_to(&mut w)?;
+        w.finish()?;
+        Ok(())
+    }
+
+    /// Reads a filter from a snapshot written by `write_to`. The insert count
+    /// starts at zero and runtime settings are left at their defaults.
+    pub fn read_from<R: Read>(r: R) -> Result<Self, SnapshotError> {
+        Self::read_from_with_limit(r, usize::MAX)
+    }
+
+    /// Reads a filter like `read_from`, but rejects a snapshot of more than
+    /// max_cells cells with `TooManyCells` before reading its cells, so that
+    /// untrusted snapshots can't make the reader allocate arbitrary amounts
+    /// of memory. This matters most for sparse cell data, for which all
+    /// cells are allocated however few are set.
+    pub fn read_from_with_limit<R: Read>(r: R, max_cells: usize) -> Result<Self, SnapshotError> {
+        let mut r = Checksummed::new(r);
+        let header = Header::read(&mut r)?;
+
+        let cells = if header.sparse {
+            let mut cells = header.sparse_cells(max_cells)?;
+            let nonzero = read_sparse_count(&mut r, &header)?;
+            let mut next = 0;
+            for _ in 0..nonzero {
+                let gap = read_varint(&mut r)?;
+                let value = read_u8(&mut r)?;
+                next = set_sparse_cell(&mut cells, next, gap, value)?;
+            }
+            cells
+        } else {
+            header.check_cells(max_cells)?;
+            // The data is read as it arrives, so that a truncated snapshot
+            // doesn't allocate the cells it claims to hold.
+            let len = header.data_len();
+            let mut data = Vec::new();
+            r.by_ref().take(len as u64).read_to_end(&mut data)?;
+            if data.len() != len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            header.dense_cells(data)
+        };
+        if header.has_checksum() {
+            let crc = r.crc();
+            if read_u32(&mut r.inner)? != crc {
+                return Err(SnapshotError::Corrupted);
+            }
+        }
+        Ok(header.into_filter(cells))
+    }
+
+    /// Reads a filter from a snapshot written by `write_to_compressed`. Data
+    /// which isn't a zstd frame is rejected with an `Io` error.
+    #[cfg(feature = "compress")]
+    pub fn read_from_compressed<R: Read>(r: R) -> Result<Self, SnapshotError> {
+        Self::read_from(zstd::Decoder::new(r)?)
+    }
+}
+
+/// Header holds the parameters read from the header of a snapshot.
+struct Header {
+    version: u8,
+    scheme: HashScheme,
+    sparse: bool,
+    d: u8,
+    k: usize,
+    p: usize,
+    m: usize,
+    fp_rate: f64,
+    key: u64,
+}
+
+impl Header {
+    /// Reads and validates the header of a snapshot, from the magic bytes up
+    /// to the cell data.
+    fn read<R: Read>(r: &mut R) -> Result<Header, SnapshotError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = read_u8(r)?;
+        let scheme = match version {
+            1 => HashScheme::FnvV1,
+            2 => HashScheme::FnvV2,
+            3..=VERSION => {
+                let id = read_u8(r)?;
+                match HashScheme::from_id(id) {
+                    Some(scheme @ HashScheme::FnvV1) | Some(scheme @ HashScheme::FnvV2) => scheme,
+                    _ => return Err(SnapshotError::UnsupportedHashScheme(id)),
+                }
+            }
+            _ => return Err(SnapshotError::UnsupportedVersion(version)),
+        };
+        let sparse = match version {
+            1..=4 => false,
+            _ => match read_u8(r)? {
+                DENSE => false,
+                SPARSE => true,
+                _ => return Err(SnapshotError::UnsupportedLayout),
+            },
+        };
+        let d = read_u8(r)?;
+        if d == 0 || d > 8 {
+            return Err(SnapshotError::InvalidBucketSize(d));
+        }
+
+        let k = read_u32(r)? as usize;
+        if k == 0 {
+            return Err(SnapshotError::InvalidHashFunctions);
+        }
+        let p = read_usize(r)?;
+        let m = read_usize(r)?;
+        let fp_rate = f64::from_bits(read_u64(r)?);
+        let key = read_u64(r)?;
+        if m.checked_mul(usize::from(d)).is_none() {
+            return Err(SnapshotError::UnsupportedLayout);
+        }
+        if m == 0 || k > m || !fp_rate.is_finite() {
+            return Err(SnapshotError::InconsistentHeader);
+        }
+
+        Ok(Header {
+            version,
+            scheme,
+            sparse,
+            d,
+            k,
+            p,
+            m,
+            fp_rate,
+            key,
+        })
+    }
+
+    /// Returns the length of the header of a snapshot of the version, in
+    /// bytes.
+    fn len(version: u8) -> usize {
+        match version {
+            1 | 2 => 42,
+            3 | 4 => 43,
+            _ => 44,
+        }
+    }
+
+    /// Returns the length of dense cell data, in bytes.
+    fn data_len(&self) -> usize {
+        expected_byte_len(self.m, self.d)
+    }
+
+    /// Returns the zeroed cells sparse cell data is restored into, or an error
+    /// if the snapshot holds more than max_cells cells. Unlike dense cell
+    /// data, all cells are allocated up front, however short the data, so an
+    /// allocation failure is returned as an `OutOfMemory` error rather than
+    /// aborting.
+    fn sparse_cells(&self, max_cells: usize) -> Result<Buckets, SnapshotError> {
+        self.check_cells(max_cells)?;
+        let len = self.data_len();
+        let mut data = Vec::new();
+        data.try_reserve_exact(len)
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        data.resize(len, 0);
+        Ok(self.dense_cells(data))
+    }
+
+    /// Returns an error if the snapshot holds more than max_cells cells.
+    fn check_cells(&self, max_cells: usize) -> Result<(), SnapshotError> {
+        if self.m > max_cells {
+            return Err(SnapshotError::TooManyCells {
+                cells: self.m,
+                max: max_cells,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns true if the cell data is followed by a checksum.
+    fn has_checksum(&self) -> bool {
+        self.version >= 4
+    }
+
+    /// Returns the cells held in dense cell data.
+    fn dense_cells(&self, data: Vec<u8>) -> Buckets {
+        Buckets::from_raw_parts(data, self.m, self.d).expect("data is sized by expected_byte_len")
+    }
+
+    /// Returns the filter with the header's parameters and the cells.
+    fn into_filter(self, cells: Buckets) -> StableBloomFilter {
+        let mut f = StableBloomFilter::from_parts(
+            cells,
+            FnvBuildHasher::with_key(self.key),
+            self.k,
+            self.p,
+            self.fp_rate,
+        );
+        f.set_probe_scheme(
+            self.scheme
+                .probe_scheme()
+                .expect("FNV schemes have a probe scheme"),
+        );
+        f
+    }
+}
+
+/// ChunkWriter flushes the wrapped writer after every chunk_size bytes.
+struct ChunkWriter<W> {
+    inner: W,
+    chunk_size: usize,
+    filled: usize,
+}
+
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.filled);
+        let n = self.inner.write(&buf[..len])?;
+        self.filled += n;
+        if self.filled == self.chunk_size {
+            self.inner.flush()?;
+            self.filled = 0;
+        }
+        Ok(n)
+    }
+
+    // Chunks are flushed as soon as they are full.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Checksummed computes the CRC-32C of the bytes read from or written to the
+/// wrapped reader or writer.
+struct Checksummed<T> {
+    inner: T,
+    crc: u32,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Checksummed { inner, crc: !0 }
+    }
+
+    /// Returns the checksum of the bytes so far.
+    fn crc(&self) -> u32 {
+        !self.crc
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.crc =
+                CRC32C_TABLE[((self.crc ^ u32::from(*byte)) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the CRC-32C lookup table.
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the length of the sparse cell data of the cells and the number of
+/// non-zero cells, or None if the sparse cell data isn't shorter than limit.
+fn sparse_len(cells: &Buckets, limit: usize) -> Option<(usize, usize)> {
+    let (mut len, mut nonzero, mut next) = (0, 0, 0);
+    for (i, _) in cells.iter_nonzero() {
+        len += varint_len((i - next) as u64) + 1;
+        if len >= limit {
+            return None;
+        }
+        nonzero += 1;
+        next = i + 1;
+    }
+    len += varint_len(nonzero as u64);
+    if len < limit {
+        Some((len, nonzero))
+    } else {
+        None
+    }
+}
+
+/// Writes the sparse cell data of the cells, of which nonzero are non-zero.
+fn write_sparse<W: Write>(w: &mut W, cells: &Buckets, nonzero: usize) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(WRITE_BUFFER_SIZE);
+    push_varint(&mut buf, nonzero as u64);
+    let mut next = 0;
+    for (i, value) in cells.iter_nonzero() {
+        push_varint(&mut buf, (i - next) as u64);
+        buf.push(value);
+        next = i + 1;
+        if buf.len() + 11 > WRITE_BUFFER_SIZE {
+            w.write_all(&buf)?;
+            buf.clear();
+        }
+    }
+    w.write_all(&buf)
+}
+
+/// Reads the number of non-zero cells of sparse cell data, which can't
+/// exceed the number of cells.
+fn read_sparse_count<R: Read>(r: &mut R, header: &Header) -> Result<u64, SnapshotError> {
+    let nonzero = read_varint(r)?;
+    if nonzero > header.m as u64 {
+        return Err(SnapshotError::Corrupted);
+    }
+    Ok(nonzero)
+}
+
+/// Sets the cell of sparse cell data at gap cells after next, and returns
+/// the index following it. Fails if the cell or its value is out of range.
+fn set_sparse_cell(
+    cells: &mut Buckets,
+    next: usize,
+    gap: u64,
+    value: u8,
+) -> Result<usize, SnapshotError> {
+    let index = match (next as u64).checked_add(gap) {
+        Some(index) if index < cells.count() as u64 => index as usize,
+        _ => return Err(SnapshotError::Corrupted),
+    };
+    if value == 0 || value > cells.max_bucket_value() {
+        return Err(SnapshotError::Corrupted);
+    }
+    cells.set(index, value);
+    Ok(index + 1)
+}
+
+/// Returns the number of bytes of the varint encoding of value.
+fn varint_len(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
+}
+
+/// Appends the unsigned LEB128 encoding of value to buf.
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint of at most 64 bits.
+fn read_varint<R: Read>(r: &mut R) -> Result<u64, SnapshotError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(r)?;
+        if shift == 63 && byte > 1 {
+            return Err(SnapshotError::Corrupted);
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SnapshotError::Corrupted)
+}
+
+/// Reads a little-endian u64 which must fit in a usize, e.g. on 32-bit
+/// targets.
+fn read_usize<R: Read>(r: &mut R) -> Result<usize, SnapshotError> {
+    usize::try_from(read_u64(r)?).map_err(|_| SnapshotError::UnsupportedLayout)
+}
+
+/// Reads a byte.
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+/// Reads a little-endian u32.
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Reads a little-endian u64.
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checksummed, DENSE, MAGIC, SPARSE, VERSION};
+    use crate::error::SnapshotError;
+    use crate::hash::HashScheme;
+    use crate::stable::{ProbeScheme, StableBloomFilter};
+    use crate::Filter;
+    use std::io::{self, Write};
+
+    /// Returns a filter with most cells set, which is written as dense cell
+    /// data.
+    pub(super) fn dense_filter() -> StableBloomFilter {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        f.set_eviction_enabled(false);
+        for i in 0..300 {
+            f.add(i.to_string().as_bytes());
+        }
+        f
+    }
+
+    /// Returns the snapshot of the given earlier version holding the same
+    /// filter as buf, a snapshot with dense cell data.
+    pub(super) fn legacy_snapshot(buf: &[u8], version: u8) -> Vec<u8> {
+        assert_eq!(buf[6], DENSE);
+        let mut legacy = buf[..buf.len() - 4].to_vec();
+        legacy[4] = version;
+        legacy.remove(6);
+        if version < 3 {
+            legacy.remove(5);
+        }
+        if version >= 4 {
+            let mut crc = Checksummed::new(io::sink());
+            crc.write_all(&legacy).unwrap();
+            legacy.extend_from_slice(&crc.crc().to_le_bytes());
+        }
+        legacy
+    }
+
+    // Ensures that a filter read from a snapshot keeps its parameters, cells
+    // and memberships.
+    #[test]
+    fn test_round_trip() {
+        let mut f = StableBloomFilter::new(1000, 3, 0.01);
+        f.set_eviction_enabled(false);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[6], SPARSE);
+        assert!(buf.len() < 44 + 375 + 4);
+
+        let restored = StableBloomFilter::read_from(buf.as_slice()).unwrap();
+        assert_eq!(restored.cells(), f.cells());
+        assert_eq!(restored.d(), f.d());
+        assert_eq!(restored.k(), f.k());
+        assert_eq!(restored.p(), f.p());
+        assert_eq!(restored.target_fp_rate(), f.target_fp_rate());
+        for i in 0..(f.cells()) {
+            assert_eq!(restored.cell_value(i), f.cell_value(i));
+        }
+        for i in 0..50 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+    }
+
+    // Ensures that the header is written in the documented little-endian
+    // layout.
+    #[test]
+    fn test_header_layout() {
+        let f = StableBloomFilter::new(16, 2, 0.01);
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+
+        assert_eq!(&buf[0..4], &MAGIC);
+        assert_eq!(buf[4], VERSION);
+        assert_eq!(buf[5], HashScheme::FnvV2.id());
+        assert_eq!(buf[6], SPARSE);
+        assert_eq!(buf[7], 2);
+        assert_eq!(&buf[8..12], &(f.k() as u32).to_le_bytes());
+        assert_eq!(&buf[12..20], &(f.p() as u64).to_le_bytes());
+        assert_eq!(&buf[20..28], &16u64.to_le_bytes());
+        assert_eq!(&buf[28..36], &0.01f64.to_bits().to_le_bytes());
+        assert_eq!(&buf[36..44], &0xcbf2_9ce4_8422_2325u64.to_le_bytes());
+        assert_eq!(buf[44], 0);
+        assert_eq!(buf.len(), 44 + 1 + 4);
+    }
+
+    // Ensures that invalid and truncated snapshots are rejected.
+    #[test]
+    fn test_invalid_snapshots() {
+        let f = StableBloomFilter::new(1000, 2, 0.01);
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+
+        let mut bad = buf.clone();
+        bad[0] = b'X';
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::BadMagic) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut bad = buf.clone();
+        bad[4] = VERSION + 1;
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::UnsupportedVersion(v)) => assert_eq!(v, VERSION + 1),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut bad = buf.clone();
+        bad[5] = HashScheme::Sip24V1.id();
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::UnsupportedHashScheme(id)) => assert_eq!(id, 3),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut bad = buf.clone();
+        bad[6] = 2;
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::UnsupportedLayout) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut bad = buf.clone();
+        bad[7] = 9;
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::InvalidBucketSize(9)) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut bad = buf.clone();
+        bad[8..12].copy_from_slice(&[0; 4]);
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::InvalidHashFunctions) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        match StableBloomFilter::read_from(&buf[..buf.len() - 1]) {
+            Err(SnapshotError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let headers: Vec<(usize, Vec<u8>)> = vec![
+            (20, 0u64.to_le_bytes().to_vec()),
+            (8, 1001u32.to_le_bytes().to_vec()),
+            (28, f64::NAN.to_bits().to_le_bytes().to_vec()),
+        ];
+        for (offset, field) in headers {
+            let mut bad = buf.clone();
+            bad[offset..offset + field.len()].copy_from_slice(&field);
+            match StableBloomFilter::read_from(bad.as_slice()) {
+                Err(SnapshotError::InconsistentHeader) => {}
+                other => panic!("unexpected result: {:?}", other.err()),
+            }
+        }
+
+        let mut bad = buf.clone();
+        bad[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::UnsupportedLayout) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    // Ensures that snapshots of more cells than the limit are rejected, and
+    // that a dense snapshot claiming more cells than it holds is rejected
+    // without allocating them.
+    #[test]
+    fn test_read_limit() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[6], DENSE);
+
+        match StableBloomFilter::read_from_with_limit(buf.as_slice(), 999) {
+            Err(SnapshotError::TooManyCells { cells, max }) => {
+                assert_eq!((cells, max), (1000, 999))
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        let restored = StableBloomFilter::read_from_with_limit(buf.as_slice(), 1000).unwrap();
+        assert_eq!(restored.buckets(), f.buckets());
+
+        let mut bad = buf.clone();
+        bad[20..28].copy_from_slice(&(1u64 << 50).to_le_bytes());
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        // Sparse cell data is bounded by the limit too, and cells which can't
+        // be allocated are reported instead of aborting.
+        let mut buf = Vec::new();
+        StableBloomFilter::new(1000, 2, 0.01)
+            .write_to(&mut buf)
+            .unwrap();
+        assert_eq!(buf[6], SPARSE);
+        match StableBloomFilter::read_from_with_limit(buf.as_slice(), 999) {
+            Err(SnapshotError::TooManyCells { cells, max }) => {
+                assert_eq!((cells, max), (1000, 999))
+            }
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        assert!(StableBloomFilter::read_from_with_limit(buf.as_slice(), 1000).is_ok());
+        let mut bad = buf.clone();
+        bad[20..28].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        match StableBloomFilter::read_from(bad.as_slice()) {
+            Err(SnapshotError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::OutOfMemory),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    // Ensures that the hash scheme is recorded in snapshots and restored.
+    #[test]
+    fn test_hash_scheme() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        f.set_probe_scheme(ProbeScheme::DoubleHashing);
+        f.add(b"a");
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[5], HashScheme::FnvV1.id());
+
+        let read = StableBloomFilter::read_from(buf.as_slice()).unwrap();
+        assert_eq!(read.hash_scheme(), HashScheme::FnvV1);
+        assert!(read.test(b"a"));
+
+        let f = StableBloomFilter::new(1000, 2, 0.01);
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        let read = StableBloomFilter::read_from(buf.as_slice()).unwrap();
+        assert_eq!(read.hash_scheme(), HashScheme::FnvV2);
+    }
+
+    // Ensures that snapshots of earlier versions are read, with the hash
+    // scheme implied by their version if they don't record it.
+    #[test]
+    fn test_earlier_versions() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+
+        for version in 1..VERSION {
+            let legacy = legacy_snapshot(&buf, version);
+            let read = StableBloomFilter::read_from(legacy.as_slice()).unwrap();
+            let scheme = match version {
+                1 => HashScheme::FnvV1,
+                _ => HashScheme::FnvV2,
+            };
+            assert_eq!(read.hash_scheme(), scheme);
+            assert_eq!(read.buckets(), f.buckets());
+        }
+    }
+
+    // Ensures that filters are written as whichever cell data is smaller and
+    // read back identically from both.
+    #[test]
+    fn test_cell_encodings() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[6], DENSE);
+        assert_eq!(buf.len(), 44 + 250 + 4);
+        let read = StableBloomFilter::read_from(buf.as_slice()).unwrap();
+        assert_eq!(read.buckets(), f.buckets());
+
+        let mut f = StableBloomFilter::new(1_000_000, 4, 0.01);
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[6], SPARSE);
+        assert_eq!(buf.len(), 44 + 1 + 4);
+        assert_eq!(StableBloomFilter::read_from(buf.as_slice()).unwrap(), f);
+
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        assert_eq!(buf[6], SPARSE);
+        assert!(buf.len() < 44 + 30_000);
+        assert_eq!(StableBloomFilter::read_from(buf.as_slice()).unwrap(), f);
+    }
+
+    // Ensures that malformed sparse cell data is rejected.
+    #[test]
+    fn test_invalid_sparse_cells() {
+        let f = StableBloomFilter::new(16, 2, 0.01);
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        buf.truncate(44);
+
+        let cases: Vec<&[u8]> = vec![
+            // more non-zero cells than cells
+            &[17],
+            // index out of range
+            &[1, 16, 1],
+            // zero value
+            &[1, 0, 0],
+            // value above the maximum
+            &[1, 0, 4],
+            // overlong varint
+            &[
+                0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01,
+            ],
+        ];
+        for cells in cases {
+            let mut bad = buf.clone();
+            bad.extend_from_slice(cells);
+            let mut crc = Checksummed::new(io::sink());
+            crc.write_all(&bad).unwrap();
+            bad.extend_from_slice(&crc.crc().to_le_bytes());
+            match StableBloomFilter::read_from(bad.as_slice()) {
+                Err(SnapshotError::Corrupted) => {}
+                other => panic!("unexpected result: {:?}", other.err()),
+            }
+        }
+    }
+
+    // Ensures that snapshots match byte for byte the conformance vectors,
+    // which every platform must write and read identically. The second
+    // vector has 3-bit cells which straddle byte boundaries and the third
+    // has sparse cell data.
+    #[test]
+    fn test_conformance_vectors() {
+        #[rustfmt::skip]
+        const HEADER_TAIL: [u8; 16] = [
+            0x7b, 0x14, 0xae, 0x47, 0xe1, 0x7a, 0x84, 0x3f,
+            0x25, 0x23, 0x22, 0x84, 0xe4, 0x9c, 0xf2, 0xcb,
+        ];
+        let vector = |head: [u8; 28], cells: &[u8], crc: [u8; 4]| {
+            let mut v = head.to_vec();
+            v.extend_from_slice(&HEADER_TAIL);
+            v.extend_from_slice(cells);
+            v.extend_from_slice(&crc);
+            v
+        };
+        #[rustfmt::skip]
+        let cases = vec![
+            (
+                16,
+                2,
+                vec![&b"a"[..]],
+                vector(
+                    [
+                        0x53, 0x42, 0x46, 0x00, 0x05, 0x02, 0x00, 0x02, 0x03, 0x00, 0x00, 0x00,
+                        0x2b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    ],
+                    &[0x00, 0x30, 0x0c, 0x03],
+                    [0x64, 0xc8, 0xf0, 0x03],
+                ),
+                vec![0, 0, 0, 0, 0, 0, 3, 0, 0, 3, 0, 0, 3, 0, 0, 0],
+            ),
+            (
+                10,
+                3,
+                vec![&b"a"[..], &b"b"[..]],
+                vector(
+                    [
+                        0x53, 0x42, 0x46, 0x00, 0x05, 0x02, 0x00, 0x03, 0x03, 0x00, 0x00, 0x00,
+                        0x79, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                        0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    ],
+                    &[0x3f, 0x00, 0xfc, 0x3f],
+                    [0x4e, 0xd4, 0x8f, 0x1b],
+                ),
+                vec![7, 7, 0, 0, 0, 0, 7, 7, 7, 7],
+            ),
+            (
+                200,
+                1,
+                vec![&b"a"[..]],
+                vector(
+                    [
+                        0x53, 0x42, 0x46, 0x00, 0x05, 0x02, 0x01, 0x01, 0x03, 0x00, 0x00, 0x00,
+                        0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                        0xc8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    ],
+                    &[0x03, 0x24, 0x01, 0x3c, 0x01, 0x3c, 0x01],
+                    [0x88, 0xa6, 0xd5, 0x22],
+                ),
+                (0..200).map(|i| (i % 61 == 36) as u8).collect(),
+            ),
+        ];
+
+        for (m, d, members, expected, cells) in cases {
+            let mut f = StableBloomFilter::new(m, d, 0.01);
+            f.set_eviction_enabled(false);
+            for data in members.iter() {
+                f.add(data);
+            }
+            let mut buf = Vec::new();
+            f.write_to(&mut buf).unwrap();
+            assert_eq!(buf, expected);
+
+            let read = StableBloomFilter::read_from(expected.as_slice()).unwrap();
+            assert_eq!(read.buckets().iter().collect::<Vec<u8>>(), cells);
+            for data in members.iter() {
+                assert!(read.test(data));
+            }
+        }
+    }
+
+    // Ensures that the checksum is CRC-32C and that snapshots whose cells,
+    // header or checksum were damaged are rejected as corrupted.
+    #[test]
+    fn test_checksum() {
+        let mut crc = Checksummed::new(io::sink());
+        crc.write_all(b"123456789").unwrap();
+        assert_eq!(crc.crc(), 0xe306_9283);
+
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+
+        for i in [37, 50, buf.len() - 1].iter() {
+            let mut bad = buf.clone();
+            bad[*i] ^= 0x10;
+            match StableBloomFilter::read_from(bad.as_slice()) {
+                Err(SnapshotError::Corrupted) => {}
+                other => panic!("unexpected result: {:?}", other.err()),
+            }
+        }
+
+        // A snapshot whose cells were never flushed reads back as zeros.
+        let mut bad = buf.clone();
+        let cells = 44..(buf.len() - 4);
+        bad[cells].iter_mut().for_each(|b| *b = 0);
+        assert_eq!(
+            StableBloomFilter::read_from(bad.as_slice())
+                .err()
+                .map(|e| e.to_string()),
+            Some("corrupted snapshot".to_string())
+        );
+    }
+
+    // Ensures that compressed snapshots are read back into the same filter
+    // and are smaller than uncompressed ones.
+    #[cfg(feature = "compress")]
+    #[test]
+    fn test_compressed_round_trip() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        let mut compressed = Vec::new();
+        f.write_to_compressed(&mut compressed, 0).unwrap();
+        assert!(compressed.len() < buf.len());
+
+        let read = StableBloomFilter::read_from_compressed(compressed.as_slice()).unwrap();
+        assert_eq!(read.buckets(), f.buckets());
+
+        match StableBloomFilter::read_from_compressed(buf.as_slice()) {
+            Err(SnapshotError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+}
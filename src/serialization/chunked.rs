@@ -0,0 +1,529 @@
+use super::{read_varint, set_sparse_cell, Checksummed, Header, MAGIC, VERSION};
+use crate::buckets::Buckets;
+use crate::error::SnapshotError;
+use crate::stable::StableBloomFilter;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Size of the buffer `read_from` reads into.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Longest varint of at most 64 bits, in bytes.
+const MAX_VARINT_LEN: usize = 10;
+
+/// Phase is the part of a snapshot a `ChunkedReader` expects next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Header,
+    DenseCells,
+    SparseCount,
+    SparseGap,
+    SparseValue,
+    Checksum,
+    Complete,
+}
+
+/// ChunkedReader reads a snapshot written by `StableBloomFilter::write_to` or
+/// `write_chunks` incrementally, from chunks of any size. It holds no more
+/// than the cells of the filter, so a multi-GB snapshot can be streamed from
+/// object storage without buffering it. If reading fails midway, e.g.
+/// because a connection dropped, the bytes read so far are kept and reading
+/// resumes from `position` with a new reader.
+///
+///     use stable_bloom_filter::serialization::ChunkedReader;
+///     use stable_bloom_filter::stable::StableBloomFilter;
+///     use stable_bloom_filter::Filter;
+///
+///     let mut f = StableBloomFilter::new(1000, 2, 0.01);
+///     f.add(b"a");
+///     let mut snapshot = Vec::new();
+///     f.write_to(&mut snapshot).unwrap();
+///
+///     let mut reader = ChunkedReader::new();
+///     reader.read_from(&snapshot[..30]).unwrap();
+///     assert_eq!(reader.position(), 30);
+///     reader.read_from(&snapshot[30..]).unwrap();
+///     assert!(reader.finish().unwrap().test(b"a"));
+pub struct ChunkedReader {
+    /// part of the snapshot expected next
+    phase: Phase,
+    /// bytes of the header, varint or checksum being received
+    pending: Vec<u8>,
+    /// header, once it has been received
+    header: Option<Header>,
+    /// maximum number of cells of the snapshot
+    max_cells: usize,
+    /// dense cell data received so far
+    data: Vec<u8>,
+    /// cells restored from sparse cell data
+    cells: Option<Buckets>,
+    /// number of sparse cells yet to be received
+    remaining: u64,
+    /// index following the previous sparse cell
+    next: usize,
+    /// distance to the sparse cell whose value is expected
+    gap: u64,
+    /// checksum of the header and cell data received so far
+    crc: Checksummed<io::Sink>,
+    /// number of snapshot bytes received
+    position: u64,
+}
+
+impl ChunkedReader {
+    /// Creates a reader expecting the start of a snapshot.
+    pub fn new() -> Self {
+        Self::with_limit(usize::MAX)
+    }
+
+    /// Creates a reader like `new`, which rejects a snapshot of more than
+    /// max_cells cells with `TooManyCells` once its header has been read, so
+    /// that untrusted snapshots can't make it allocate arbitrary amounts of
+    /// memory.
+    pub fn with_limit(max_cells: usize) -> Self {
+        ChunkedReader {
+            phase: Phase::Header,
+            pending: Vec::new(),
+            header: None,
+            max_cells,
+            data: Vec::new(),
+            cells: None,
+            remaining: 0,
+            next: 0,
+            gap: 0,
+            crc: Checksummed::new(io::sink()),
+            position: 0,
+        }
+    }
+
+    /// Returns the number of snapshot bytes read so far, which is the offset
+    /// to resume reading from.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns true once the whole snapshot has been read.
+    pub fn is_complete(&self) -> bool {
+        self.phase == Phase::Complete
+    }
+
+    /// Reads the next chunk of the snapshot from r until r is exhausted or
+    /// the snapshot is complete, and returns the number of snapshot bytes
+    /// read. Bytes of r following the snapshot may be consumed and dropped.
+    pub fn read_from<R: Read>(&mut self, mut r: R) -> Result<u64, SnapshotError> {
+        let start = self.position;
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        while !self.is_complete() {
+            let n = match r.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(SnapshotError::Io(err)),
+            };
+            self.push(&buf[..n])?;
+        }
+        Ok(self.position - start)
+    }
+
+    /// Returns the filter read from the snapshot, or an error if the snapshot
+    /// isn't complete.
+    pub fn finish(self) -> Result<StableBloomFilter, SnapshotError> {
+        match self.header {
+            Some(header) if self.phase == Phase::Complete => {
+                let cells = match self.cells {
+                    Some(cells) => cells,
+                    None => header.dense_cells(self.data),
+                };
+                Ok(header.into_filter(cells))
+            }
+            _ => Err(SnapshotError::Io(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the snapshot is incomplete",
+            ))),
+        }
+    }
+
+    /// Consumes the snapshot bytes in chunk and returns how many were
+    /// consumed, which is fewer than the chunk only once the snapshot is
+    /// complete.
+    fn push(&mut self, chunk: &[u8]) -> Result<usize, SnapshotError> {
+        let mut consumed = 0;
+        while consumed < chunk.len() && !self.is_complete() {
+            let rest = &chunk[consumed..];
+            let phase = self.phase;
+            let n = match phase {
+                Phase::Header => self.push_header(rest)?,
+                Phase::DenseCells => self.push_dense(rest),
+                Phase::SparseCount | Phase::SparseGap => self.push_varint(rest[0])?,
+                Phase::SparseValue => self.push_sparse_value(rest[0])?,
+                Phase::Checksum => self.push_checksum(rest)?,
+                Phase::Complete => unreachable!("complete snapshots consume no bytes"),
+            };
+            if phase != Phase::Checksum {
+                self.crc.update(&rest[..n]);
+            }
+            consumed += n;
+            self.position += n as u64;
+        }
+        Ok(consumed)
+    }
+
+    /// Consumes header bytes and parses the header once it is complete.
+    fn push_header(&mut self, rest: &[u8]) -> Result<usize, SnapshotError> {
+        // The header length depends on the version, which is the fifth byte.
+        let len = match self.pending.get(4) {
+            Some(version) => Header::len(*version),
+            None => 5,
+        };
+        let n = rest.len().min(len - self.pending.len());
+        self.pending.extend_from_slice(&rest[..n]);
+        if self.pending.len() <= 5 {
+            check_prefix(&self.pending)?;
+        }
+        if self.pending.len() < 5 || self.pending.len() < Header::len(self.pending[4]) {
+            return Ok(n);
+        }
+
+        let header = Header::read(&mut self.pending.as_slice())?;
+        self.pending.clear();
+        if header.sparse {
+            self.cells = Some(header.sparse_cells(self.max_cells)?);
+            self.phase = Phase::SparseCount;
+        } else {
+            header.check_cells(self.max_cells)?;
+            self.phase = Phase::DenseCells;
+        }
+        self.header = Some(header);
+        Ok(n)
+    }
+
+    /// Consumes dense cell data. The data grows as it arrives, so that a
+    /// truncated snapshot doesn't allocate the cells it claims to hold.
+    fn push_dense(&mut self, rest: &[u8]) -> usize {
+        let len = self
+            .header
+            .as_ref()
+            .expect("the header precedes the cells")
+            .data_len();
+        let n = rest.len().min(len - self.data.len());
+        self.data.extend_from_slice(&rest[..n]);
+        if self.data.len() == len {
+            self.end_cells();
+        }
+        n
+    }
+
+    /// Consumes a byte of the number of sparse cells or the distance to the
+    /// next one.
+    fn push_varint(&mut self, byte: u8) -> Result<usize, SnapshotError> {
+        self.pending.push(byte);
+        if byte & 0x80 != 0 && self.pending.len() < MAX_VARINT_LEN {
+            return Ok(1);
+        }
+        let value = read_varint(&mut self.pending.as_slice())?;
+        self.pending.clear();
+
+        if self.phase == Phase::SparseGap {
+            self.gap = value;
+            self.phase = Phase::SparseValue;
+            return Ok(1);
+        }
+        let header = self.header.as_ref().expect("the header precedes the cells");
+        if value > header.m as u64 {
+            return Err(SnapshotError::Corrupted);
+        }
+        self.remaining = value;
+        self.phase = Phase::SparseGap;
+        if value == 0 {
+            self.end_cells();
+        }
+        Ok(1)
+    }
+
+    /// Consumes the value of a sparse cell.
+    fn push_sparse_value(&mut self, value: u8) -> Result<usize, SnapshotError> {
+        let cells = self.cells.as_mut().expect("sparse cells are allocated");
+        self.next = set_sparse_cell(cells, self.next, self.gap, value)?;
+        self.remaining -= 1;
+        self.phase = Phase::SparseGap;
+        if self.remaining == 0 {
+            self.end_cells();
+        }
+        Ok(1)
+    }
+
+    /// Consumes checksum bytes and verifies the checksum once it is complete.
+    fn push_checksum(&mut self, rest: &[u8]) -> Result<usize, SnapshotError> {
+        let n = rest.len().min(4 - self.pending.len());
+        self.pending.extend_from_slice(&rest[..n]);
+        if self.pending.len() == 4 {
+            let expected = u32::from_le_bytes([
+                self.pending[0],
+                self.pending[1],
+                self.pending[2],
+                self.pending[3],
+            ]);
+            if expected != self.crc.crc() {
+                return Err(SnapshotError::Corrupted);
+            }
+            self.pending.clear();
+            self.phase = Phase::Complete;
+        }
+        Ok(n)
+    }
+
+    /// Moves on from the cell data to the checksum, if there is one.
+    fn end_cells(&mut self) {
+        let header = self.header.as_ref().expect("the header precedes the cells");
+        self.phase = if header.has_checksum() {
+            Phase::Checksum
+        } else {
+            Phase::Complete
+        };
+    }
+}
+
+impl Default for ChunkedReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for ChunkedReader {
+    /// Consumes the next bytes of the snapshot, so that a snapshot can be
+    /// copied into the reader with `io::copy`. Fails with `InvalidData` if
+    /// the snapshot is invalid, and writes zero bytes once it is complete.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf).map_err(|err| match err {
+            SnapshotError::Io(err) => err,
+            err => io::Error::new(ErrorKind::InvalidData, err),
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Validates the magic bytes and version received so far, so that data which
+/// isn't a snapshot is rejected as early as possible.
+fn check_prefix(prefix: &[u8]) -> Result<(), SnapshotError> {
+    let magic_len = prefix.len().min(MAGIC.len());
+    if prefix[..magic_len] != MAGIC[..magic_len] {
+        return Err(SnapshotError::BadMagic);
+    }
+    match prefix.get(4) {
+        Some(version) if *version == 0 || *version > VERSION => {
+            Err(SnapshotError::UnsupportedVersion(*version))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkedReader;
+    use crate::error::SnapshotError;
+    use crate::serialization::tests::{dense_filter, legacy_snapshot};
+    use crate::serialization::SPARSE;
+    use crate::stable::{ProbeScheme, StableBloomFilter};
+    use crate::Filter;
+    use std::io::{self, Read, Write};
+
+    /// Returns the snapshot of a filter with a few members, which is written
+    /// as sparse cell data.
+    fn snapshot() -> (StableBloomFilter, Vec<u8>) {
+        let mut f = StableBloomFilter::new(1000, 3, 0.01);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        (f, buf)
+    }
+
+    // Ensures that a snapshot split into chunks at any offset is read into
+    // the same filter, with both sparse and dense cell data.
+    #[test]
+    fn test_read_chunks() {
+        let dense = dense_filter();
+        let mut dense_buf = Vec::new();
+        dense.write_to(&mut dense_buf).unwrap();
+        let read = |buf: &[u8], split: usize| {
+            let mut reader = ChunkedReader::new();
+            assert_eq!(reader.read_from(&buf[..split]).unwrap(), split as u64);
+            assert_eq!(reader.is_complete(), split == buf.len());
+            reader.read_from(&buf[split..]).unwrap();
+            assert!(reader.is_complete());
+            reader.finish().unwrap()
+        };
+        for split in 0..=dense_buf.len() {
+            assert_eq!(read(&dense_buf, split).buckets(), dense.buckets());
+        }
+
+        let (f, buf) = snapshot();
+        assert_eq!(buf[6], SPARSE);
+        for split in 0..=buf.len() {
+            assert_eq!(read(&buf, split), f);
+        }
+
+        let mut reader = ChunkedReader::new();
+        for byte in buf.iter() {
+            assert_eq!(reader.write(&[*byte]).unwrap(), 1);
+        }
+        assert_eq!(reader.write(b"x").unwrap(), 0);
+        assert_eq!(reader.finish().unwrap(), f);
+    }
+
+    /// FailingReader returns an error after reading limit bytes.
+    struct FailingReader<'a> {
+        data: &'a [u8],
+        limit: usize,
+    }
+
+    impl<'a> Read for FailingReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.limit == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+            }
+            let n = buf.len().min(self.limit).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            self.limit -= n;
+            Ok(n)
+        }
+    }
+
+    // Ensures that reading resumes from the position reached before a
+    // failure.
+    #[test]
+    fn test_resume() {
+        let (f, buf) = snapshot();
+        let mut reader = ChunkedReader::new();
+        let mut failing = FailingReader {
+            data: &buf,
+            limit: 200,
+        };
+        match reader.read_from(&mut failing) {
+            Err(SnapshotError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(reader.position(), 200);
+
+        let resumed = &buf[reader.position() as usize..];
+        reader.read_from(resumed).unwrap();
+        assert_eq!(reader.finish().unwrap(), f);
+    }
+
+    // Ensures that incomplete, invalid and corrupted snapshots are rejected.
+    #[test]
+    fn test_invalid_chunks() {
+        let (_, buf) = snapshot();
+
+        let mut reader = ChunkedReader::new();
+        reader.read_from(&buf[..buf.len() - 1]).unwrap();
+        match reader.finish() {
+            Err(SnapshotError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+
+        let mut reader = ChunkedReader::new();
+        match reader.read_from(&b"SBX\0"[..]) {
+            Err(SnapshotError::BadMagic) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut bad = buf.clone();
+        bad[100] ^= 1;
+        match ChunkedReader::new().read_from(bad.as_slice()) {
+            Err(SnapshotError::Corrupted) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut reader = ChunkedReader::new();
+        let err = reader.write_all(&bad).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bad = buf.clone();
+        bad[20..28].copy_from_slice(&0u64.to_le_bytes());
+        match ChunkedReader::new().read_from(bad.as_slice()) {
+            Err(SnapshotError::InconsistentHeader) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // Ensures that a dense or sparse snapshot of more cells than the limit is
+    // rejected once its header has been read.
+    #[test]
+    fn test_read_chunks_limit() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+
+        match ChunkedReader::with_limit(999).read_from(buf.as_slice()) {
+            Err(SnapshotError::TooManyCells { cells, max }) => {
+                assert_eq!((cells, max), (1000, 999))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mut reader = ChunkedReader::with_limit(1000);
+        reader.read_from(buf.as_slice()).unwrap();
+        assert_eq!(reader.finish().unwrap().buckets(), f.buckets());
+
+        let (_, buf) = snapshot();
+        assert_eq!(buf[6], SPARSE);
+        match ChunkedReader::with_limit(10).read_from(buf.as_slice()) {
+            Err(SnapshotError::TooManyCells { max: 10, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // Ensures that snapshots of versions without a checksum are read too.
+    #[test]
+    fn test_read_chunks_legacy_version() {
+        let f = dense_filter();
+        let mut buf = Vec::new();
+        f.write_to(&mut buf).unwrap();
+        let buf = legacy_snapshot(&buf, 1);
+
+        let mut reader = ChunkedReader::new();
+        reader.read_from(&buf[..10]).unwrap();
+        reader.read_from(&buf[10..]).unwrap();
+        let read = reader.finish().unwrap();
+        assert_eq!(read.probe_scheme(), ProbeScheme::DoubleHashing);
+        assert_eq!(read.buckets(), f.buckets());
+    }
+
+    /// Parts records the chunks flushed to it.
+    #[derive(Default)]
+    struct Parts {
+        parts: Vec<Vec<u8>>,
+        current: Vec<u8>,
+    }
+
+    impl Write for Parts {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.current.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.parts.push(std::mem::take(&mut self.current));
+            Ok(())
+        }
+    }
+
+    // Ensures that write_chunks flushes full chunks of the snapshot.
+    #[test]
+    fn test_write_chunks() {
+        let (f, buf) = snapshot();
+        for chunk_size in [1, 7, 64, buf.len(), buf.len() + 1].iter() {
+            let mut parts = Parts::default();
+            f.write_chunks(&mut parts, *chunk_size).unwrap();
+            assert!(parts.current.is_empty());
+            assert_eq!(parts.parts.len(), buf.len().div_ceil(*chunk_size));
+            let (last, full) = parts.parts.split_last().unwrap();
+            assert!(full.iter().all(|part| part.len() == *chunk_size));
+            assert!(!last.is_empty() && last.len() <= *chunk_size);
+            assert_eq!(parts.parts.concat(), buf);
+        }
+    }
+}
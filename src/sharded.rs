@@ -0,0 +1,231 @@
+use crate::fnv::FnvBuildHasher;
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+/// ShardedStableBloomFilter partitions elements across independent Stable
+/// Bloom Filters, each behind its own lock, so that threads adding elements
+/// which belong to different shards don't contend. An element's shard is
+/// picked by Fibonacci hashing of its hash, so that shards receive elements
+/// evenly even with weakly mixed hashes such as FNV.
+pub struct ShardedStableBloomFilter<S = FnvBuildHasher> {
+    /// inner filters
+    shards: Vec<Mutex<StableBloomFilter<S>>>,
+    /// builds the hash function shared by all shards
+    hash: S,
+}
+
+impl ShardedStableBloomFilter {
+    /// Creates a new sharded filter with m cells in total, split evenly across
+    /// the provided number of shards, and d bits allocated per cell optimized
+    /// for the target false-positive rate. Panics if shards is zero.
+    pub fn new(shards: usize, m: usize, d: u8, fp_rate: f64) -> Self {
+        Self::with_hasher(shards, m, d, fp_rate, FnvBuildHasher::default())
+    }
+}
+
+impl<S: BuildHasher + Clone> ShardedStableBloomFilter<S> {
+    /// Creates a new sharded filter like `new`, which uses hash_builder to
+    /// hash the data.
+    pub fn with_hasher(shards: usize, m: usize, d: u8, fp_rate: f64, hash_builder: S) -> Self {
+        assert!(shards > 0, "a sharded filter needs at least one shard");
+        let shard_m = m.div_ceil(shards);
+
+        ShardedStableBloomFilter {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(StableBloomFilter::with_hasher(
+                        shard_m,
+                        d,
+                        fp_rate,
+                        hash_builder.clone(),
+                    ))
+                })
+                .collect(),
+            hash: hash_builder,
+        }
+    }
+}
+
+impl<S: BuildHasher> ShardedStableBloomFilter<S> {
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the total number of cells across all shards.
+    pub fn cells(&self) -> usize {
+        self.shards.iter().map(|s| lock(s).cells()).sum()
+    }
+
+    /// Returns the index of the shard the data belongs to.
+    pub fn shard_of(&self, data: &[u8]) -> usize {
+        self.route(self.hash64(data))
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    pub fn test(&self, data: &[u8]) -> bool {
+        let hash = self.hash64(data);
+        lock(&self.shards[self.route(hash)]).test_hash(hash)
+    }
+
+    /// Will add the data to the shard it belongs to.
+    pub fn add(&self, data: &[u8]) {
+        let hash = self.hash64(data);
+        lock(&self.shards[self.route(hash)]).add_hash(hash);
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    pub fn test_and_add(&self, data: &[u8]) -> bool {
+        let hash = self.hash64(data);
+        lock(&self.shards[self.route(hash)]).test_and_add_hash(hash)
+    }
+
+    /// Returns the mean of the shards' stable points, i.e. the expected
+    /// fraction of zero cells across all shards once they have become stable.
+    pub fn stable_point(&self) -> f64 {
+        self.mean(|f| f.stable_point())
+    }
+
+    /// Returns the mean of the shards' upper bounds on false positives. Every
+    /// lookup only checks one shard, so this is the upper bound for the
+    /// sharded filter.
+    pub fn false_positive_rate(&self) -> f64 {
+        self.mean(|f| f.false_positive_rate())
+    }
+
+    /// Returns the fraction of non-zero cells across all shards.
+    pub fn fill_ratio(&self) -> f64 {
+        let (filled, cells) = self.shards.iter().fold((0.0, 0), |(filled, cells), s| {
+            let f = lock(s);
+            (
+                filled + f.fill_ratio() * (f.cells() as f64),
+                cells + f.cells(),
+            )
+        });
+        filled / (cells as f64)
+    }
+
+    /// Returns the 64-bit hash of the data, used both to pick its shard and as
+    /// the kernel for its cell indices.
+    #[inline]
+    fn hash64(&self, data: &[u8]) -> u64 {
+        let mut hasher = self.hash.build_hasher();
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    /// Returns the shard of the hash.
+    #[inline]
+    fn route(&self, hash: u64) -> usize {
+        fibonacci_shard(hash, self.shards.len())
+    }
+
+    /// Returns the mean of the statistic over all shards.
+    fn mean<F: Fn(&StableBloomFilter<S>) -> f64>(&self, statistic: F) -> f64 {
+        let sum: f64 = self.shards.iter().map(|s| statistic(&lock(s))).sum();
+        sum / (self.shards.len() as f64)
+    }
+}
+
+/// Returns the shard, out of shards, of the hash, mapped from the top bits of
+/// the hash multiplied by 2^64 divided by the golden ratio, which depend on
+/// every bit of the hash.
+#[inline]
+pub(crate) fn fibonacci_shard(hash: u64, shards: usize) -> usize {
+    let mixed = hash.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    ((u128::from(mixed) * (shards as u128)) >> 64) as usize
+}
+
+impl<S: BuildHasher> Filter for ShardedStableBloomFilter<S> {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    fn test(&self, data: &[u8]) -> bool {
+        ShardedStableBloomFilter::test(self, data)
+    }
+
+    /// Will add the data to the shard it belongs to.
+    fn add(&mut self, data: &[u8]) {
+        ShardedStableBloomFilter::add(self, data)
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        ShardedStableBloomFilter::test_and_add(self, data)
+    }
+}
+
+/// Locks the shard. A shard whose lock was poisoned by a panicking thread is
+/// still usable, as a partially applied add only leaves extra cells set.
+#[inline]
+pub(crate) fn lock<T>(shard: &Mutex<T>) -> MutexGuard<'_, T> {
+    shard.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedStableBloomFilter;
+    use crate::stable::StableBloomFilter;
+    use std::thread;
+
+    // Ensures that elements added from many threads are members of the
+    // sharded filter and are spread across all shards.
+    #[test]
+    fn test_concurrent_add() {
+        let f = ShardedStableBloomFilter::new(4, 400_000, 4, 0.01);
+        assert_eq!(f.shards(), 4);
+        assert_eq!(f.cells(), 400_000);
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let f = &f;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        f.add(format!("{}-{}", t, i).as_bytes());
+                    }
+                });
+            }
+        });
+
+        let mut per_shard = [0; 4];
+        for t in 0..4 {
+            for i in 0..1000 {
+                let data = format!("{}-{}", t, i);
+                assert!(f.test(data.as_bytes()));
+                per_shard[f.shard_of(data.as_bytes())] += 1;
+            }
+        }
+        for count in per_shard.iter() {
+            assert!(*count > 800 && *count < 1200);
+        }
+    }
+
+    // Ensures that test_and_add reports membership before adding.
+    #[test]
+    fn test_test_and_add() {
+        let f = ShardedStableBloomFilter::new(3, 30_000, 2, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        assert!(f.test(b"a"));
+        assert!(!f.test(b"b"));
+    }
+
+    // Ensures that the aggregate statistics match the statistics of the
+    // shards.
+    #[test]
+    fn test_aggregate_statistics() {
+        let f = ShardedStableBloomFilter::new(4, 40_000, 2, 0.01);
+        let shard = StableBloomFilter::new(10_000, 2, 0.01);
+        assert!((f.stable_point() - shard.stable_point()).abs() < 1e-12);
+        assert!((f.false_positive_rate() - shard.false_positive_rate()).abs() < 1e-12);
+
+        assert_eq!(f.fill_ratio(), 0.0);
+        f.add(b"a");
+        let expected = (shard.k() as f64) / 40_000.0;
+        assert!((f.fill_ratio() - expected).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,111 @@
+//! Vectorized loads of the cells probed by a membership test.
+//!
+//! A membership test reads k cells scattered over the filter. On x86_64 CPUs
+//! with AVX2, up to eight of them are fetched with a single gather of 32-bit
+//! words, shifted so that every lane holds its cell in the lowest bits, and
+//! compared against zero at once. Only 1-bit and 8-bit cells are handled, as
+//! other sizes may straddle two bytes.
+
+use std::arch::x86_64::*;
+
+/// Returns whether the cells at all of the indices of data, holding cells of
+/// bucket_size bits, are non-zero, or None if the indices can't be gathered,
+/// in which case the caller falls back to scalar loads.
+#[inline]
+pub(crate) fn all_nonzero(data: &[u8], bucket_size: u8, indices: &[usize]) -> Option<bool> {
+    if (bucket_size != 1 && bucket_size != 8)
+        || data.len() < 4
+        || data.len() > i32::MAX as usize
+        || !is_x86_feature_detected!("avx2")
+    {
+        return None;
+    }
+
+    for chunk in indices.chunks(8) {
+        // Safe since AVX2 support was checked above.
+        if !unsafe { all_nonzero_avx2(data, bucket_size, chunk) } {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+/// Gathers up to eight cells and returns whether all of them are non-zero.
+/// Every lane reads the 32-bit word starting at the byte holding its cell,
+/// moved back so that it ends within data, and is shifted right by the
+/// cell's bit offset within that word. Unused lanes repeat the first index.
+#[target_feature(enable = "avx2")]
+unsafe fn all_nonzero_avx2(data: &[u8], bucket_size: u8, indices: &[usize]) -> bool {
+    debug_assert!(!indices.is_empty() && indices.len() <= 8);
+    let last_word = data.len() - 4;
+    let mut offsets = [0i32; 8];
+    let mut shifts = [0i32; 8];
+
+    for lane in 0..8 {
+        let index = indices[if lane < indices.len() { lane } else { 0 }];
+        let (byte, bit) = if bucket_size == 8 {
+            (index, 0)
+        } else {
+            (index / 8, index % 8)
+        };
+        assert!(byte < data.len());
+        let offset = byte.min(last_word);
+        offsets[lane] = offset as i32;
+        shifts[lane] = ((byte - offset) * 8 + bit) as i32;
+    }
+
+    let offsets = _mm256_loadu_si256(offsets.as_ptr() as *const __m256i);
+    let shifts = _mm256_loadu_si256(shifts.as_ptr() as *const __m256i);
+    let words = _mm256_i32gather_epi32::<1>(data.as_ptr() as *const i32, offsets);
+    let mask = _mm256_set1_epi32(if bucket_size == 8 { 0xff } else { 1 });
+    let cells = _mm256_and_si256(_mm256_srlv_epi32(words, shifts), mask);
+    let zeros = _mm256_cmpeq_epi32(cells, _mm256_setzero_si256());
+
+    _mm256_testz_si256(zeros, zeros) == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::all_nonzero;
+    use rand::{thread_rng, Rng};
+
+    // Ensures that gathered cells match cells loaded one at a time, including
+    // cells in the last bytes of the data.
+    #[test]
+    fn test_all_nonzero_matches_scalar() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut rng = thread_rng();
+
+        for &bucket_size in [1u8, 8].iter() {
+            let cells = 200;
+            let bytes = cells * usize::from(bucket_size) / 8;
+            let get = |data: &[u8], i: usize| {
+                if bucket_size == 8 {
+                    data[i]
+                } else {
+                    (data[i / 8] >> (i % 8)) & 1
+                }
+            };
+
+            for _ in 0..1000 {
+                // Mostly set cells, so that all probes are often non-zero.
+                let data: Vec<u8> = (0..bytes)
+                    .map(|_| rng.gen::<u8>() | rng.gen::<u8>() | rng.gen::<u8>())
+                    .collect();
+                let k = rng.gen_range(1, 12);
+                let mut indices: Vec<usize> = (0..k).map(|_| rng.gen_range(0, cells)).collect();
+                indices.push(cells - 1);
+
+                let expected = indices.iter().all(|i| get(&data, *i) != 0);
+                assert_eq!(all_nonzero(&data, bucket_size, &indices), Some(expected));
+            }
+        }
+
+        assert_eq!(all_nonzero(&[0xff; 8], 4, &[0]), None);
+        assert_eq!(all_nonzero(&[0xff; 3], 8, &[0]), None);
+        assert_eq!(all_nonzero(&[0xff, 0, 0, 0], 8, &[3]), Some(false));
+        assert_eq!(all_nonzero(&[0, 0, 0, 0x80], 1, &[31]), Some(true));
+    }
+}
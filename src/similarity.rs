@@ -0,0 +1,187 @@
+use crate::error::MergeError;
+use crate::fnv::FnvBuildHasher;
+use std::hash::{BuildHasher, Hasher};
+
+/// MinHash computes fixed-size MinHash signatures as described by Broder in
+/// On the Resemblance and Containment of Documents:
+///
+/// https://www.cs.princeton.edu/courses/archive/spring13/cos598C/broder97resemblance.pdf
+///
+/// A signature holds, for each of its hash functions, the minimum hash of
+/// all elements added. The fraction of hash functions for which two
+/// signatures agree is an unbiased estimate of the Jaccard similarity of the
+/// two sets of elements, whose error shrinks with the number of hash
+/// functions.
+///
+/// Like the filters of this crate, every element is hashed once, and the
+/// hash functions are derived from that single 64-bit hash. Each one
+/// re-mixes the hash with a different odd constant, since the minima of
+/// linear combinations of two halves would be strongly correlated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinHash {
+    /// minimum hash per hash function
+    signature: Vec<u64>,
+    /// hash function (kernel for all functions)
+    hash: FnvBuildHasher,
+}
+
+impl MinHash {
+    /// Creates a new, empty signature with the specified number of hash
+    /// functions. Panics if num_hashes is zero.
+    pub fn new(num_hashes: usize) -> Self {
+        assert!(
+            num_hashes > 0,
+            "a signature needs at least one hash function"
+        );
+
+        MinHash {
+            signature: vec![u64::MAX; num_hashes],
+            hash: FnvBuildHasher::default(),
+        }
+    }
+
+    /// Returns the number of hash functions, i.e. the signature size.
+    pub fn num_hashes(&self) -> usize {
+        self.signature.len()
+    }
+
+    /// Returns the signature.
+    pub fn signature(&self) -> &[u64] {
+        &self.signature
+    }
+
+    /// Will add the element to the set summarized by the signature. It
+    /// returns the signature to allow for chaining.
+    pub fn add(&mut self, element: &[u8]) -> &Self {
+        let mut hasher = self.hash.build_hasher();
+        hasher.write(element);
+        let hash = hasher.finish();
+
+        for (i, min) in self.signature.iter_mut().enumerate() {
+            let value = mix(hash ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            if value < *min {
+                *min = value;
+            }
+        }
+        self
+    }
+
+    /// Will add every window of width consecutive bytes, i.e. every shingle,
+    /// of the byte stream. A stream shorter than width is added as a single
+    /// element. It returns the signature to allow for chaining.
+    pub fn add_shingles(&mut self, data: &[u8], width: usize) -> &Self {
+        if data.len() <= width || width == 0 {
+            return self.add(data);
+        }
+        for shingle in data.windows(width) {
+            self.add(shingle);
+        }
+        self
+    }
+
+    /// Returns the estimated Jaccard similarity of the sets summarized by the
+    /// two signatures, between 0 (disjoint) and 1 (identical). Both
+    /// signatures must use the same number of hash functions.
+    pub fn jaccard(&self, other: &Self) -> Result<f64, MergeError> {
+        if self.signature.len() != other.signature.len() {
+            return Err(MergeError::HashFunctionsMismatch {
+                left: self.signature.len(),
+                right: other.signature.len(),
+            });
+        }
+
+        let equal = self
+            .signature
+            .iter()
+            .zip(other.signature.iter())
+            .filter(|(a, b)| a == b)
+            .count();
+        Ok((equal as f64) / (self.signature.len() as f64))
+    }
+
+    /// Restores the signature to its original, empty state. It returns the
+    /// signature to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.signature.iter_mut().for_each(|min| *min = u64::MAX);
+        self
+    }
+}
+
+/// Returns the 64-bit finalizer of SplitMix64 applied to x, which spreads
+/// every input bit over the whole output.
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MinHash;
+    use crate::error::MergeError;
+
+    // Ensures that the estimated similarity of overlapping sets is close to
+    // their Jaccard similarity.
+    #[test]
+    fn test_jaccard() {
+        let mut a = MinHash::new(256);
+        let mut b = MinHash::new(256);
+        assert_eq!(a.num_hashes(), 256);
+        // 500 shared elements out of 1500 distinct ones.
+        for i in 0..1000 {
+            a.add(i.to_string().as_bytes());
+        }
+        for i in 500..1500 {
+            b.add(i.to_string().as_bytes());
+        }
+
+        let similarity = a.jaccard(&b).unwrap();
+        assert!((similarity - 1.0 / 3.0).abs() < 0.1);
+        assert_eq!(a.jaccard(&a.clone()), Ok(1.0));
+
+        let mut c = MinHash::new(256);
+        c.add(b"unrelated");
+        assert!(a.jaccard(&c).unwrap() < 0.05);
+    }
+
+    // Ensures that near-identical documents have similar signatures when
+    // added as shingles.
+    #[test]
+    fn test_shingles() {
+        let text = b"the quick brown fox jumps over the lazy dog";
+        let edited = b"the quick brown fox jumped over the lazy dog";
+        let other = b"lorem ipsum dolor sit amet, consectetur adipiscing";
+
+        let mut a = MinHash::new(128);
+        a.add_shingles(text, 4);
+        let mut b = MinHash::new(128);
+        b.add_shingles(edited, 4);
+        let mut c = MinHash::new(128);
+        c.add_shingles(other, 4);
+
+        assert!(a.jaccard(&b).unwrap() > 0.6);
+        assert!(a.jaccard(&c).unwrap() < 0.2);
+
+        // Order doesn't matter, and adding a shingle twice has no effect.
+        let mut d = MinHash::new(128);
+        d.add_shingles(edited, 4);
+        d.add_shingles(edited, 4);
+        assert_eq!(d, b);
+
+        d.reset();
+        assert!(d.signature().iter().all(|min| *min == u64::MAX));
+    }
+
+    // Ensures that signatures of different sizes can't be compared.
+    #[test]
+    fn test_jaccard_mismatch() {
+        assert_eq!(
+            MinHash::new(16).jaccard(&MinHash::new(32)),
+            Err(MergeError::HashFunctionsMismatch {
+                left: 16,
+                right: 32
+            })
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::error::MergeError;
+use crate::fnv::FnvHasher;
+use crate::stable::split_hash;
+use std::hash::Hasher;
+
+/// CountMinSketch implements a Count-Min Sketch as described by Cormode and
+/// Muthukrishnan in An Improved Data Stream Summary: The Count-Min Sketch and
+/// its Applications:
+///
+/// http://dimacs.rutgers.edu/~graham/pubs/papers/cm-full.pdf
+///
+/// A Count-Min Sketch (CMS) is a probabilistic data structure which
+/// approximates the frequency of events in a data stream. Unlike a hash map,
+/// a CMS uses sub-linear space at the expense of over-counting some events
+/// due to collisions. Frequency estimates never undercount and overcount by
+/// at most epsilon times the total count with probability 1 - delta.
+///
+/// Like the filters of this crate, the d row indices of an event are derived
+/// from a single 64-bit hash. Counters are 64 bits wide, as frequencies
+/// quickly outgrow the at most 8-bit cells of `Buckets`.
+pub struct CountMinSketch {
+    /// count matrix, d rows of w counters
+    matrix: Vec<u64>,
+    /// matrix width
+    w: usize,
+    /// matrix depth
+    d: usize,
+    /// number of items added
+    count: u64,
+    /// relative-accuracy factor
+    epsilon: f64,
+    /// relative-accuracy probability
+    delta: f64,
+    /// hash function (kernel for all d functions)
+    hash: FnvHasher,
+}
+
+impl CountMinSketch {
+    /// Creates a new Count-Min Sketch whose relative accuracy is within a
+    /// factor of epsilon with probability delta. Both of these parameters
+    /// affect the space and time complexity.
+    pub fn new(epsilon: f64, delta: f64) -> Self {
+        let w = (std::f64::consts::E / epsilon).ceil().max(1.0) as usize;
+        let d = (1.0 / delta).ln().ceil().max(1.0) as usize;
+
+        CountMinSketch {
+            matrix: vec![0; w * d],
+            w,
+            d,
+            count: 0,
+            epsilon,
+            delta,
+            hash: FnvHasher::default(),
+        }
+    }
+
+    /// Returns the relative-accuracy factor, epsilon.
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    /// Returns the relative-accuracy probability, delta.
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    /// Returns the width, i.e. the number of counters per row.
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    /// Returns the depth, i.e. the number of rows and hash functions.
+    pub fn depth(&self) -> usize {
+        self.d
+    }
+
+    /// Returns the number of items added to the sketch.
+    pub fn total_count(&self) -> u64 {
+        self.count
+    }
+
+    /// Will add one occurrence of the data to the sketch. It returns the
+    /// sketch to allow for chaining.
+    pub fn increment(&mut self, data: &[u8]) -> &Self {
+        self.increment_by(data, 1)
+    }
+
+    /// Will add n occurrences of the data to the sketch. It returns the sketch
+    /// to allow for chaining.
+    pub fn increment_by(&mut self, data: &[u8], n: u64) -> &Self {
+        let (lower, upper) = self.hash_kernel(data);
+
+        // Increment count in each row.
+        for i in 0..(self.d) {
+            let index = self.location(lower, upper, i);
+            self.matrix[index] = self.matrix[index].saturating_add(n);
+        }
+        self.count = self.count.saturating_add(n);
+        self
+    }
+
+    /// Returns the approximate count for the specified data, biased towards
+    /// overestimation.
+    pub fn estimate(&self, data: &[u8]) -> u64 {
+        let (lower, upper) = self.hash_kernel(data);
+
+        // Find the minimum count.
+        (0..(self.d))
+            .map(|i| self.matrix[self.location(lower, upper, i)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Combines this sketch with another, so that estimates account for the
+    /// data of both. Returns an error if the sketches' dimensions differ.
+    pub fn merge(&mut self, other: &Self) -> Result<(), MergeError> {
+        if self.w != other.w {
+            return Err(MergeError::CellsMismatch {
+                left: self.w,
+                right: other.w,
+            });
+        }
+        if self.d != other.d {
+            return Err(MergeError::HashFunctionsMismatch {
+                left: self.d,
+                right: other.d,
+            });
+        }
+
+        for (cell, other) in self.matrix.iter_mut().zip(other.matrix.iter()) {
+            *cell = cell.saturating_add(*other);
+        }
+        self.count = self.count.saturating_add(other.count);
+        Ok(())
+    }
+
+    /// Restores the sketch to its original state. It returns the sketch to
+    /// allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.matrix.iter_mut().for_each(|cell| *cell = 0);
+        self.count = 0;
+        self
+    }
+
+    /// Returns the 32-bit lower and upper halves of the hash of the data.
+    fn hash_kernel(&self, data: &[u8]) -> (u32, u32) {
+        let mut hasher = self.hash.clone();
+        hasher.write(data);
+        split_hash(hasher.finish())
+    }
+
+    /// Returns the matrix index of the counter of the i-th row.
+    #[inline]
+    fn location(&self, lower: u32, upper: u32, i: usize) -> usize {
+        i * self.w + (lower as usize + upper as usize * i) % self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountMinSketch;
+    use crate::error::MergeError;
+
+    // Ensures that the dimensions are derived from epsilon and delta.
+    #[test]
+    fn test_sketch_dimensions() {
+        let s = CountMinSketch::new(0.001, 0.99);
+        assert_eq!(s.width(), 2719);
+        assert_eq!(s.depth(), 1);
+        assert_eq!(s.epsilon(), 0.001);
+        assert_eq!(s.delta(), 0.99);
+
+        let s = CountMinSketch::new(0.01, 0.001);
+        assert_eq!(s.width(), 272);
+        assert_eq!(s.depth(), 7);
+    }
+
+    // Ensures that estimates never undercount and stay within the error
+    // bound of the true counts.
+    #[test]
+    fn test_sketch_estimate() {
+        let mut s = CountMinSketch::new(0.001, 0.01);
+        for i in 0..1000u64 {
+            s.increment_by(i.to_string().as_bytes(), i % 10 + 1);
+        }
+        s.increment(b"0");
+        s.increment(b"0");
+        assert_eq!(s.total_count(), 5502);
+
+        // Each estimate exceeds the bound with probability delta at most.
+        let bound = (s.epsilon() * (s.total_count() as f64)) as u64;
+        let mut exceeded = 0;
+        for i in 0..1000u64 {
+            let expected = i % 10 + 1 + if i == 0 { 2 } else { 0 };
+            let estimate = s.estimate(i.to_string().as_bytes());
+            assert!(estimate >= expected);
+            if estimate > expected + bound {
+                exceeded += 1;
+            }
+        }
+        assert!(exceeded <= 10);
+
+        s.reset();
+        assert_eq!(s.total_count(), 0);
+        assert_eq!(s.estimate(b"0"), 0);
+    }
+
+    // Ensures that merged sketches estimate the counts of both sketches and
+    // that sketches of different dimensions can't be merged.
+    #[test]
+    fn test_sketch_merge() {
+        let mut a = CountMinSketch::new(0.01, 0.01);
+        let mut b = CountMinSketch::new(0.01, 0.01);
+        a.increment_by(b"a", 3);
+        b.increment_by(b"a", 4);
+        b.increment(b"b");
+
+        a.merge(&b).unwrap();
+        assert_eq!(a.total_count(), 8);
+        assert!(a.estimate(b"a") >= 7);
+        assert!(a.estimate(b"b") >= 1);
+
+        assert_eq!(
+            a.merge(&CountMinSketch::new(0.1, 0.01)),
+            Err(MergeError::CellsMismatch {
+                left: 272,
+                right: 28
+            })
+        );
+        assert_eq!(
+            a.merge(&CountMinSketch::new(0.01, 0.1)),
+            Err(MergeError::HashFunctionsMismatch { left: 5, right: 3 })
+        );
+    }
+}
@@ -1,15 +1,125 @@
-use crate::buckets::Buckets;
-use crate::fnv::FnvHasher;
-use crate::Filter;
-use crate::{optimal_k, optimal_stable_p};
-use rand::{thread_rng, Rng};
-use std::hash::Hasher;
-
-pub struct StableBloomFilter {
+use crate::buckets::{expected_byte_len, Buckets};
+use crate::error::{MergeError, SbfError};
+use crate::fnv::FnvBuildHasher;
+use crate::hash::HashScheme;
+#[cfg(feature = "siphash")]
+use crate::hash::SipHashBuilder;
+use crate::sharded::fibonacci_shard;
+use crate::view::FilterView;
+use crate::{optimal_k, optimal_m, optimal_stable_p};
+use crate::{Filter, HashFilter};
+use rand::rngs::SmallRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::collections::{HashSet, VecDeque};
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+mod background;
+mod builder;
+mod concurrent;
+
+pub use self::builder::StableBloomFilterBuilder;
+pub use self::concurrent::ConcurrentStableBloomFilter;
+
+/// Number of observations after which the probe order is recomputed.
+const PROBE_REORDER_INTERVAL: u64 = 1024;
+
+/// Number of bytes needed to transfer one entry of a cell delta, a 64-bit
+/// index and an 8-bit value.
+pub const DELTA_ENTRY_SIZE: usize = 9;
+
+/// Number of inserts between two checks of the fill ratio when overload
+/// protection is enabled.
+const OVERLOAD_CHECK_INTERVAL: u64 = 1024;
+
+/// Boosts eviction while the filter is overloaded.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct OverloadProtection {
+    /// fill ratio above which eviction is boosted
+    trigger_fill: f64,
+    /// number of cells to decrement on every add while boosted
+    boosted_p: usize,
+    /// whether eviction is currently boosted
+    active: bool,
+}
+
+/// Tracks how often each of the k probes hits an empty cell.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ProbeStats {
+    /// number of empty cells seen per probe
+    zeros: Vec<u64>,
+    /// number of lookups observed
+    observations: u64,
+}
+
+/// Records the fill ratio at regular intervals.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct FillHistory {
+    /// most recent fill ratios, oldest first
+    samples: VecDeque<f64>,
+    /// maximum number of samples kept
+    capacity: usize,
+    /// number of adds between two samples
+    interval: u64,
+}
+
+/// Describes why a key tests positive or negative, as returned by
+/// `membership_status`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MembershipStatus {
+    /// All of the key's cells are set, it tests positive.
+    Present,
+    /// Some of the key's cells are set but not all of them. The key was likely
+    /// added and then partially evicted.
+    LikelyEvicted,
+    /// None of the key's cells are set. The key was never added or has been
+    /// fully evicted.
+    Absent,
+}
+
+/// ProbeScheme is how the k cell indices of an element are derived from the
+/// lower and upper halves of its 64-bit hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProbeScheme {
+    /// The i-th index is lower + upper * i modulo m, as in BoomFilters. If
+    /// upper is a multiple of m, all k probes map to the same cell. Filters
+    /// created before enhanced double hashing use this scheme.
+    DoubleHashing,
+    /// Enhanced double hashing as described by Dillinger and Manolios: the
+    /// step, upper forced to be odd and non-zero modulo m, grows by i after
+    /// the i-th probe, so the i-th index is
+    /// lower + step * i + i * (i - 1) * (i - 2) / 6 modulo m. Probes don't
+    /// collapse onto one cell and their distribution is closer to k
+    /// independent hash functions. This is the default.
+    EnhancedDoubleHashing,
+}
+
+/// KeyHandle caches the hash of a key so that it can be re-added with
+/// `refresh` without hashing it again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyHandle {
+    hash: u64,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "FilterState<S>"))]
+pub struct StableBloomFilter<S = FnvBuildHasher> {
     /// filter data
     cells: Buckets,
-    /// hash function (kernel for all k functions)
-    hash: FnvHasher,
+    /// builds the hash function (kernel for all k functions)
+    hash: S,
     /// number of cells
     m: usize,
     /// number of cells to decrement
@@ -20,6 +130,31 @@ pub struct StableBloomFilter {
     max: u8,
     /// buffer used to cache indices
     index_buffer: Vec<usize>,
+    /// target false-positive rate
+    fp_rate: f64,
+    /// keys known not to be members
+    suppressed: HashSet<Vec<u8>>,
+    /// order in which test checks the k probes
+    probe_order: Vec<usize>,
+    /// per-probe statistics, if probe ordering is enabled
+    probe_stats: Option<ProbeStats>,
+    /// number of elements added since creation or the last reset
+    inserts: u64,
+    /// value cells are set to on add
+    fill_value: u8,
+    /// overload protection settings, if enabled
+    overload: Option<OverloadProtection>,
+    /// fraction of a decrement iteration left over by time-based decay
+    decay_residual: f64,
+    /// whether adds decrement cells
+    eviction: bool,
+    /// recorded fill ratios, if fill history is enabled
+    fill_history: Option<FillHistory>,
+    /// generator picking the cells to decrement
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rng: SmallRng,
+    /// derivation of the k cell indices from the hash
+    probe_scheme: ProbeScheme,
 }
 
 impl StableBloomFilter {
@@ -27,24 +162,44 @@ impl StableBloomFilter {
     /// bits allocated per cell optimized for the target false-positive rate. Use
     /// default if you don't want to calculate d.
     pub fn new(m: usize, d: u8, fp_rate: f64) -> Self {
-        let mut k = optimal_k(fp_rate) / 2;
-        if k > m {
-            k = m;
-        } else if k == 0 {
-            k = 1;
-        }
+        Self::with_hasher(m, d, fp_rate, FnvBuildHasher::default())
+    }
 
-        let cells = Buckets::new(m, d);
+    /// Creates a new Stable Bloom Filter like `new`, but returns an error
+    /// instead of panicking or computing meaningless parameters if m is zero,
+    /// d isn't between 1 and 8 or fp_rate isn't strictly between 0 and 1.
+    pub fn try_new(m: usize, d: u8, fp_rate: f64) -> Result<Self, SbfError> {
+        check_parameters(m, d, fp_rate)?;
+        Ok(Self::new(m, d, fp_rate))
+    }
 
-        StableBloomFilter {
-            hash: FnvHasher::default(),
-            m,
-            k,
-            p: optimal_stable_p(m, k, d, fp_rate),
-            max: cells.max_bucket_value(),
-            cells,
-            index_buffer: vec![0; k],
-        }
+    /// Creates a new Stable Bloom Filter with m 1-bit cells like
+    /// `new_default`, but returns an error if the parameters are invalid.
+    pub fn try_new_default(m: usize, fp_rate: f64) -> Result<Self, SbfError> {
+        Self::try_new(m, 1, fp_rate)
+    }
+
+    /// Creates a traditional Bloom filter like `new_unstable`, but returns an
+    /// error if the parameters are invalid.
+    pub fn try_new_unstable(m: usize, fp_rate: f64) -> Result<Self, SbfError> {
+        check_parameters(m, 1, fp_rate)?;
+        Ok(Self::new_unstable(m, fp_rate))
+    }
+
+    /// Creates a new Stable Bloom Filter like `new`, whose cells to decrement
+    /// are picked by a random number generator seeded with seed. Filters
+    /// created with the same parameters and seed which are given the same
+    /// sequence of operations end up with identical cells.
+    pub fn new_with_seed(m: usize, d: u8, fp_rate: f64, seed: u64) -> Self {
+        let mut f = Self::new(m, d, fp_rate);
+        f.rng = SmallRng::seed_from_u64(seed);
+        f
+    }
+
+    /// Returns a builder for a Stable Bloom Filter, which names every
+    /// parameter and validates them on build.
+    pub fn builder() -> StableBloomFilterBuilder {
+        StableBloomFilterBuilder::new()
     }
 
     /// Creates a new Stable Bloom Filter with m 1-bit
@@ -60,25 +215,223 @@ impl StableBloomFilter {
     /// hash functions for the target false-positive rate. Unlike the stable
     /// variant, data is not evicted and a cell contains a maximum of 1 hash value.
     pub fn new_unstable(m: usize, fp_rate: f64) -> Self {
-        let cells = Buckets::new(m, 1);
-        let k = optimal_k(fp_rate);
+        Self::unstable_with_hasher(m, fp_rate, FnvBuildHasher::default())
+    }
+
+    /// Returns the number of cells needed for a Stable Bloom Filter with d
+    /// bits per cell to retain about `retention` recent elements while keeping
+    /// its stable false-positive rate at fp_rate. The bits per cell only
+    /// affect how many cells are decremented on every add, not the number of
+    /// cells needed.
+    pub fn m_for(retention: usize, fp_rate: f64, d: u8) -> usize {
+        debug_assert!(d > 0 && d <= 8);
+        let k = (optimal_k(fp_rate) / 2).max(1);
+        // Expected fraction of zero cells at the stable point for fp_rate.
+        let stable_point = 1.0 - fp_rate.powf(1.0 / (k as f64));
+        let m = 1.0 / (1.0 - (stable_point.ln() / ((k * retention) as f64)).exp());
+
+        m.ceil() as usize
+    }
+
+    /// Returns the number of bytes used by a Stable Bloom Filter with 1-bit
+    /// cells sized by `m_for` to retain about `retention` recent elements at
+    /// fp_rate, and by a classic Bloom filter holding `retention` elements at
+    /// the same false-positive rate, in that order. The classic filter only
+    /// meets fp_rate until it has seen `retention` elements, while the stable
+    /// filter keeps it indefinitely.
+    pub fn storage_vs_classic(retention: usize, fp_rate: f64) -> (usize, usize) {
+        let stable = expected_byte_len(Self::m_for(retention, fp_rate, 1), 1);
+        let classic = expected_byte_len(optimal_m(retention, fp_rate), 1);
+
+        (stable, classic)
+    }
+
+    /// Returns the number of cells to decrement on every add, p, for which a
+    /// Stable Bloom Filter with m cells, k hash functions and the given
+    /// maximum cell value has a stable point of target_zeros, i.e. the
+    /// expected fraction of zero cells once it has become stable.
+    pub fn p_for_stable_point(m: usize, k: usize, max: u8, target_zeros: f64) -> usize {
+        (stable_p(m, k, max, target_zeros).round() as usize).max(1)
+    }
+
+    /// Returns the minimum number of cells to decrement on every add, p, for
+    /// which a Stable Bloom Filter with m cells, k hash functions and the given
+    /// maximum cell value has a stable false-positive rate of at most
+    /// fp_bound. This is the inverse of `false_positive_rate` with respect to
+    /// p, rounded up.
+    pub fn p_for_fp_bound(m: usize, k: usize, max: u8, fp_bound: f64) -> usize {
+        let target_zeros = 1.0 - fp_bound.powf(1.0 / (k as f64));
+        (stable_p(m, k, max, target_zeros).ceil() as usize).max(1)
+    }
+
+    /// Creates a filter from restored cells and hash kernel, with k hash
+    /// functions and p cells decremented on every add.
+    pub(crate) fn from_parts(
+        cells: Buckets,
+        hash: FnvBuildHasher,
+        k: usize,
+        p: usize,
+        fp_rate: f64,
+    ) -> Self {
+        Self::from_parts_with_hasher(cells, hash, k, p, fp_rate)
+    }
+
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, whose non-zero cells
+    /// are restored from the entries produced by `to_sparse`. Returns an error
+    /// if the parameters are invalid or an entry doesn't fit the filter, see
+    /// `apply_delta`.
+    pub fn from_sparse(
+        entries: &[(usize, u8)],
+        m: usize,
+        d: u8,
+        fp_rate: f64,
+    ) -> Result<Self, SbfError> {
+        let mut f = Self::try_new(m, d, fp_rate)?;
+        f.apply_delta(entries)?;
+        Ok(f)
+    }
+
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, whose cells are
+    /// restored from data. Returns an error if the length of data isn't
+    /// `expected_byte_len(m, d)`.
+    pub fn from_bytes(data: &[u8], m: usize, d: u8, fp_rate: f64) -> Result<Self, SbfError> {
+        let cells = Buckets::from_raw_parts(data.to_vec(), m, d)?;
+        Ok(Self::from_buckets(cells, fp_rate))
+    }
+
+    /// Creates a new Stable Bloom Filter over existing cells, optimized for
+    /// the target false-positive rate. The number of cells and bits per cell
+    /// are taken from the buckets, which may be held in memory or, with the
+    /// `mmap` feature, in a memory-mapped file.
+    pub fn from_buckets(cells: Buckets, fp_rate: f64) -> Self {
+        let m = cells.count();
+        let k = stable_k(m, fp_rate);
+        let p = optimal_stable_p(m, k, cells.bucket_size(), fp_rate);
+        Self::from_parts(cells, FnvBuildHasher::default(), k, p, fp_rate)
+    }
+
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, whose cells live in
+    /// the memory-mapped file at path. See `Buckets::open_mmap`.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P, m: usize, d: u8, fp_rate: f64) -> io::Result<Self> {
+        Ok(Self::from_buckets(Buckets::open_mmap(path, m, d)?, fp_rate))
+    }
+
+    /// Creates a new Stable Bloom Filter with m 1-bit cells optimized for the
+    /// target false-positive rate, and adds every key yielded by iter. It's
+    /// the sized counterpart of `FromIterator`, which can't carry m and
+    /// fp_rate.
+    pub fn from_iter_sized<I: IntoIterator<Item = Vec<u8>>>(
+        m: usize,
+        fp_rate: f64,
+        iter: I,
+    ) -> Self {
+        let mut f = Self::new_default(m, fp_rate);
+        f.add_all(iter);
+        f
+    }
+}
+
+#[cfg(feature = "siphash")]
+impl StableBloomFilter<SipHashBuilder> {
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, which hashes data
+    /// with SipHash-2-4 keyed with the provided secret key. Use it when the
+    /// filter is fed untrusted input, as the FNV kernel lets an adversary
+    /// craft colliding keys. The key must be kept secret.
+    pub fn new_keyed(m: usize, d: u8, fp_rate: f64, key: [u8; 16]) -> Self {
+        Self::with_hasher(m, d, fp_rate, SipHashBuilder::new_with_key(key))
+    }
+}
+
+impl<S: BuildHasher> StableBloomFilter<S> {
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated per
+    /// cell optimized for the target false-positive rate, which uses hash_builder
+    /// to hash the data. The same hash is used as the kernel for all k hash
+    /// functions.
+    pub fn with_hasher(m: usize, d: u8, fp_rate: f64, hash_builder: S) -> Self {
+        let k = stable_k(m, fp_rate);
+        Self::with_parameters(
+            m,
+            k,
+            optimal_stable_p(m, k, d, fp_rate),
+            d,
+            fp_rate,
+            hash_builder,
+        )
+    }
+
+    /// Creates a traditional Bloom filter with m bits and an optimal number of
+    /// hash functions for the target false-positive rate, like `new_unstable`,
+    /// which uses hash_builder to hash the data.
+    pub fn unstable_with_hasher(m: usize, fp_rate: f64, hash_builder: S) -> Self {
+        Self::with_parameters(m, optimal_k(fp_rate), 0, 1, fp_rate, hash_builder)
+    }
+
+    /// Creates a filter over existing cells with k hash functions and p cells
+    /// decremented on every add, hashing data with hash.
+    pub(crate) fn from_parts_with_hasher(
+        cells: Buckets,
+        hash: S,
+        k: usize,
+        p: usize,
+        fp_rate: f64,
+    ) -> Self {
+        let m = cells.count();
+        let max = cells.max_bucket_value();
 
         StableBloomFilter {
-            hash: FnvHasher::default(),
+            hash,
             m,
             k,
-            p: 0,
-            max: cells.max_bucket_value(),
+            p,
+            max,
             cells,
             index_buffer: vec![0; k],
+            fp_rate,
+            suppressed: HashSet::new(),
+            probe_order: (0..k).collect(),
+            probe_stats: None,
+            inserts: 0,
+            fill_value: max,
+            overload: None,
+            decay_residual: 0.0,
+            eviction: true,
+            fill_history: None,
+            rng: new_rng(),
+            probe_scheme: ProbeScheme::EnhancedDoubleHashing,
         }
     }
 
+    /// Creates a filter with m cells of d bits, k hash functions and p cells
+    /// decremented on every add, hashing data with hash.
+    fn with_parameters(m: usize, k: usize, p: usize, d: u8, fp_rate: f64, hash: S) -> Self {
+        Self::from_parts_with_hasher(Buckets::new(m, d), hash, k, p, fp_rate)
+    }
+
+    /// Returns the underlying cells.
+    pub(crate) fn buckets(&self) -> &Buckets {
+        &self.cells
+    }
+
+    /// Returns the filter's hash builder.
+    pub fn hasher(&self) -> &S {
+        &self.hash
+    }
+
     /// Returns the number of cells in the Stable Bloom Filter.
     pub fn cells(&self) -> usize {
         self.m
     }
 
+    /// Returns the current value of the cell at the specified index.
+    pub fn cell_value(&self, index: usize) -> u8 {
+        self.cells.get(index)
+    }
+
     /// Returns the number of hash functions.
     pub fn k(&self) -> usize {
         self.k
@@ -93,15 +446,75 @@ impl StableBloomFilter {
         self.max
     }
 
+    /// Returns the value cells are set to when an element is added.
+    pub fn fill_value(&self) -> u8 {
+        self.fill_value
+    }
+
+    /// Sets the value cells are set to when an element is added, clamped to
+    /// between 1 and the maximum cell value. A lower fill value means fewer
+    /// decrements are needed to evict an element, which shortens retention
+    /// independently of the bits per cell. Defaults to the maximum cell value.
+    pub fn set_fill_value(&mut self, fill_value: u8) -> &Self {
+        self.fill_value = fill_value.max(1).min(self.max);
+        self
+    }
+
+    /// Returns the number of bits allocated per cell.
+    pub fn d(&self) -> u8 {
+        self.cells.bucket_size()
+    }
+
+    /// Returns the number of elements added since the filter was created or
+    /// last reset.
+    pub fn insert_count(&self) -> u64 {
+        self.inserts
+    }
+
+    /// Returns the current fraction of non-zero cells.
+    pub fn fill_ratio(&self) -> f64 {
+        let set = self.cells.iter().filter(|value| *value != 0).count();
+        (set as f64) / (self.m as f64)
+    }
+
+    /// Returns the current fraction of zero cells, which converges to the
+    /// stable point as elements are added.
+    pub fn zeros_fraction(&self) -> f64 {
+        let zeros = self.cells.iter().filter(|value| *value == 0).count();
+        (zeros as f64) / (self.m as f64)
+    }
+
+    /// Returns the current fraction of cells holding the maximum value.
+    pub fn saturation(&self) -> f64 {
+        let saturated = self.cells.iter().filter(|value| *value == self.max).count();
+        (saturated as f64) / (self.m as f64)
+    }
+
+    /// Returns a JSON object with the parameters and current statistics of
+    /// the filter, suitable for periodic logging.
+    #[cfg(feature = "serde")]
+    pub fn stats_json(&self) -> String {
+        serde_json::json!({
+            "m": self.m,
+            "k": self.k,
+            "p": self.p,
+            "d": self.d(),
+            "target_fp": self.fp_rate,
+            "false_positive_rate": self.false_positive_rate(),
+            "fill_ratio": self.fill_ratio(),
+            "saturation": self.saturation(),
+            "insert_count": self.inserts,
+        })
+        .to_string()
+    }
+
     /// Returns the limit of the expected fraction of zeros in the
     /// Stable Bloom Filter when the number of iterations goes to infinity. When
     /// this limit is reached, the Stable Bloom Filter is considered stable.
+    /// Cells are set to the fill value on add, so it depends on the fill value
+    /// rather than on the maximum cell value.
     pub fn stable_point(&self) -> f64 {
-        let sub_denom = (self.p as f64) * ((1.0 / (self.k as f64)) - (1.0 / (self.m as f64)));
-        let denom = 1.0 + 1.0 / sub_denom;
-        let base = 1.0 / denom;
-
-        base.powf(f64::from(self.max))
+        stable_zeros(self.m, self.k, self.p, self.fill_value)
     }
 
     /// Returns the upper bound on false positives when the filter
@@ -110,231 +523,2700 @@ impl StableBloomFilter {
         (1.0 - self.stable_point()).powf(self.k as f64)
     }
 
-    #[inline]
-    pub fn hash_kernel(&self, data: &[u8]) -> (u32, u32) {
-        let mut hasher = self.hash.clone();
-        hasher.write(data);
-        let hash: u64 = hasher.finish();
-        let lower = hash as u32;
-        let upper = (hash >> 32) as u32;
-        (lower, upper)
+    /// Returns the number of times each cell is expected to be decremented
+    /// over the given number of adds, i.e. inserts * p / m. Comparing it with
+    /// the maximum cell value tells whether p evicts too fast or too slowly.
+    pub fn expected_decrements_per_cell(&self, inserts: usize) -> f64 {
+        (inserts as f64) * (self.p as f64) / (self.m as f64)
     }
 
-    /// Restores the Stable Bloom Filter to its original state. It returns the
-    /// filter to allow for chaining.
-    pub fn reset(&mut self) -> &Self {
-        self.cells.reset();
-        self
+    /// Returns the number of bits of storage per element retained by the
+    /// filter once it has become stable. Returns zero for filters that never
+    /// evict, as they don't have a stable point.
+    pub fn bits_per_stable_element(&self) -> f64 {
+        (self.m * usize::from(self.cells.bucket_size())) as f64 / self.equilibrium_cardinality()
     }
 
-    /// Will decrement a random cell and (p-1) adjacent cells by 1. This
-    /// is faster than generating p random numbers. Although the processes of
-    /// picking the p cells are not independent, each cell has a probability of p/m
-    /// for being picked at each iteration, which means the properties still hold.
-    #[inline]
-    pub fn decrement(&mut self) {
-        let mut rng = thread_rng();
-        let r: usize = rng.gen_range(0, self.m);
+    /// Returns the number of distinct elements that leave the fraction of
+    /// zero cells at the stable point, i.e. the number of elements retained by
+    /// the filter once it has become stable. It grows with m and shrinks as p
+    /// grows. Returns infinity for filters that never evict.
+    pub fn equilibrium_cardinality(&self) -> f64 {
+        cardinality(self.m, self.k, self.stable_point())
+    }
 
-        for i in 0..(self.p) {
-            let idx = (r + i) % self.m;
-            self.cells.decrease(idx, 1);
-        }
+    /// Returns an estimate of the number of distinct elements currently
+    /// represented by the filter, derived from the fraction of zero cells, k
+    /// and m. For a classic Bloom filter created by `new_unstable` this is the
+    /// number of distinct elements added; for a stable filter, elements whose
+    /// cells were evicted are no longer counted, so the estimate levels off at
+    /// `equilibrium_cardinality`. Returns infinity once every cell is set.
+    pub fn estimated_count(&self) -> f64 {
+        cardinality(self.m, self.k, self.zeros_fraction())
     }
-}
 
-impl Filter for StableBloomFilter {
-    /// Will test for membership of the data and returns true if it is a
-    /// member, false if not. This is a probabilistic test, meaning there is a
-    /// non-zero probability of false positives and false negatives.
-    #[inline]
-    fn test(&self, data: &[u8]) -> bool {
-        let (lower, upper) = self.hash_kernel(data);
-        for i in 0..(self.k) {
-            if self
-                .cells
-                .get((lower as usize + upper as usize * i) % self.m)
-                == 0
-            {
-                return false;
-            }
+    /// Returns an estimate of the number of distinct elements represented by
+    /// either filter, based on the fraction of cells that are zero in both,
+    /// i.e. that would be zero after taking the element-wise maximum. Neither
+    /// filter is modified. The filters must be compatible.
+    pub fn union_cardinality(&self, other: &Self) -> Result<f64, MergeError> {
+        self.check_compatible(other)?;
+
+        let zeros = (0..(self.m))
+            .filter(|i| self.cells.get(*i) == 0 && other.cells.get(*i) == 0)
+            .count();
+
+        Ok(cardinality(
+            self.m,
+            self.k,
+            (zeros as f64) / (self.m as f64),
+        ))
+    }
+
+    /// Returns the probability that the data, assumed to have never been
+    /// added, tests positive. If all of its k cells are currently non-zero it
+    /// is a false positive right now and 1.0 is returned. Otherwise it is
+    /// estimated from the current fill ratio as the chance that k random cells
+    /// are all non-zero.
+    pub fn false_positive_probability(&self, data: &[u8]) -> f64 {
+        if self.test_hash(self.hash64(data)) {
+            return 1.0;
         }
-        true
+        self.fill_ratio().powf(self.k as f64)
     }
 
-    /// Will add the data to the Stable Bloom Filter. It returns the filter to
-    /// allow for chaining.
-    #[inline]
-    fn add(&mut self, data: &[u8]) -> &Self {
-        // Randomly decrement p cells to make room for new elements.
-        self.decrement();
-        let (lower, upper) = self.hash_kernel(data);
+    /// Returns whether the data is present, was likely evicted or is absent,
+    /// based on how many of its k cells are set. Unlike `test`, this tells a
+    /// key whose cells were partially decremented to zero apart from one
+    /// that was never added. Suppressed keys are reported as absent.
+    pub fn membership_status(&self, data: &[u8]) -> MembershipStatus {
+        if self.is_suppressed(data) {
+            return MembershipStatus::Absent;
+        }
 
-        for i in 0..(self.k) {
-            self.cells
-                .set((lower as usize + upper as usize * i) % self.m, self.max);
+        let (lower, upper) = split_hash(self.hash64(data));
+        let set = (0..(self.k))
+            .filter(|i| self.cells.get(self.location(lower, upper, *i)) != 0)
+            .count();
+
+        if set == self.k {
+            MembershipStatus::Present
+        } else if set > 0 {
+            MembershipStatus::LikelyEvicted
+        } else {
+            MembershipStatus::Absent
         }
+    }
 
-        self
+    /// Returns how the k cell indices of an element are derived from its
+    /// hash.
+    pub fn probe_scheme(&self) -> ProbeScheme {
+        self.probe_scheme
     }
 
-    /// Is equivalent to calling Test followed by Add. It returns true if
-    /// the data is a member, false if not.
-    #[inline]
-    fn test_and_add(&mut self, data: &[u8]) -> bool {
-        let (lower, upper) = self.hash_kernel(data);
-        let mut member = true;
+    /// Returns the hash scheme of the filter, given by its hash kernel and
+    /// probe scheme. Filters with the same scheme and hash builder derive the
+    /// same cell indices in every crate version.
+    pub fn hash_scheme(&self) -> HashScheme
+    where
+        S: 'static,
+    {
+        HashScheme::of::<S>(self.probe_scheme)
+    }
 
-        // If any of the K cells are 0, then it's not a member.
-        for i in 0..(self.k) {
-            self.index_buffer[i] = (lower as usize + upper as usize * i) % self.m;
-            if self.cells.get(self.index_buffer[i]) == 0 {
-                member = false;
-            }
-        }
+    /// Sets how the k cell indices of an element are derived from its hash.
+    /// The cells must be empty or have been written with the same scheme.
+    pub(crate) fn set_probe_scheme(&mut self, scheme: ProbeScheme) {
+        self.probe_scheme = scheme;
+    }
 
-        // Randomly decrement p cells to make room for new elements.
-        self.decrement();
-        // Set the K cells to max.
-        for i in self.index_buffer.iter() {
-            self.cells.set(*i, self.max);
-        }
+    /// Returns the false-positive rate the filter was created for.
+    pub fn target_fp_rate(&self) -> f64 {
+        self.fp_rate
+    }
 
-        member
+    /// Returns how far the realized upper bound on false positives deviates
+    /// from the target rate. Because k and p are rounded to integers and
+    /// clamped, the two rarely match exactly. A positive value means the filter
+    /// produces more false positives than requested.
+    pub fn fp_rate_error(&self) -> f64 {
+        self.false_positive_rate() - self.target_fp_rate()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::StableBloomFilter;
-    use crate::optimal_k;
-    use crate::Filter;
-    use float_cmp::ApproxEq;
-    use std::f64;
+    /// Returns the expected number of inserts after which an element has a 50%
+    /// chance of having been evicted. Every insert decrements a given cell with
+    /// probability p/m, and an element is evicted once any of its k cells has
+    /// been decremented fill value times. Cells refreshed by other elements
+    /// are not taken into account. Returns infinity for filters that never
+    /// evict.
+    pub fn retention_half_life(&self) -> f64 {
+        if self.p == 0 {
+            return f64::INFINITY;
+        }
 
-    fn round(val: f64, round_on: f64, places: usize) -> f64 {
-        let pow = (10.0_f64).powf(places as f64);
-        let digit = pow * val;
-        let div = digit - digit.floor();
-        let round = if div >= round_on {
-            digit.ceil()
-        } else {
-            digit.floor()
+        let q = ((self.p as f64) / (self.m as f64)).min(1.0);
+        // Probability that a cell has been decremented fewer than max times,
+        // approximating the number of decrements by a Poisson distribution.
+        let survival = |lambda: f64| {
+            let mut term = (-lambda).exp();
+            let mut sum = term;
+            for j in 1..self.fill_value {
+                term *= lambda / f64::from(j);
+                sum += term;
+            }
+            sum
         };
+        // All k cells have to survive for the element to be retained.
+        let target = 0.5_f64.powf(1.0 / (self.k as f64));
 
-        round / pow
+        let mut low = 0.0;
+        let mut high = 1.0;
+        while survival(high) > target {
+            high *= 2.0;
+        }
+        for _ in 0..64 {
+            let mid = (low + high) / 2.0;
+            if survival(mid) > target {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        high / q
     }
 
-    // Ensures that new_unstable creates a Stable Bloom Filter with p=0,
-    // max=1 and k hash functions.
-    #[test]
-    fn test_new_unstable() {
-        let f = StableBloomFilter::new_unstable(100, 0.1);
-        let k = optimal_k(0.1);
+    /// Returns the 64-bit hash of the data from which the k cell indices are
+    /// derived.
+    #[inline]
+    pub fn hash64(&self, data: &[u8]) -> u64 {
+        let mut hasher = self.hash.build_hasher();
+        hasher.write(data);
+        hasher.finish()
+    }
 
-        assert_eq!(f.k, k);
-        assert_eq!(f.m, 100);
-        assert_eq!(f.p(), 0);
-        assert_eq!(f.max(), 1);
+    /// Returns the shard, out of num_shards, the data belongs to. Routing uses
+    /// the filter's deterministic hash, mixed like `ShardedStableBloomFilter`
+    /// does so that the shards are even with FNV, so a key always lands in
+    /// the same shard across process restarts. Panics if num_shards is zero.
+    #[inline]
+    pub fn shard_for(&self, data: &[u8], num_shards: usize) -> usize {
+        assert!(num_shards > 0, "num_shards must be positive");
+        fibonacci_shard(self.hash64(data), num_shards)
     }
 
-    // Ensures that Cells returns the number of cells, m, in the Stable Bloom
-    // Filter.
-    #[test]
-    fn test_cells() {
-        let f = StableBloomFilter::new(100, 1, 0.1);
+    /// Returns the average fraction of output bits of `hash64` that change
+    /// when a single input bit of one of the sample keys is flipped. An ideal
+    /// hash changes half of its output bits, scoring 0.5. Empty keys are
+    /// ignored, and 0.0 is returned if there are no input bits to flip.
+    pub fn avalanche_score(&self, sample_keys: &[&[u8]]) -> f64 {
+        let mut changed = 0u64;
+        let mut flips = 0u64;
 
-        assert_eq!(f.cells(), 100);
-    }
+        for key in sample_keys.iter() {
+            let hash = self.hash64(key);
+            let mut flipped = key.to_vec();
+            for bit in 0..(key.len() * 8) {
+                flipped[bit / 8] ^= 1 << (bit % 8);
+                changed += u64::from((hash ^ self.hash64(&flipped)).count_ones());
+                flipped[bit / 8] ^= 1 << (bit % 8);
+                flips += 1;
+            }
+        }
 
-    // Ensures that K returns the number of hash functions in the Stable Bloom
-    // Filter.
-    #[test]
-    fn test_k() {
-        let f = StableBloomFilter::new(100, 1, 0.01);
-        assert_eq!(f.k(), 3);
+        if flips == 0 {
+            return 0.0;
+        }
+        (changed as f64) / ((flips * 64) as f64)
     }
 
-    // Ensures that Test, Add, and TestAndAdd behave correctly.
-    #[test]
-    fn test_test_and_add() {
-        let mut f = StableBloomFilter::new_default(1_000, 0.01);
-        assert!(!f.test(b"a"));
+    #[inline]
+    pub fn hash_kernel(&self, data: &[u8]) -> (u32, u32) {
+        split_hash(self.hash64(data))
+    }
 
-        f.add(b"a");
-        assert!(f.test(b"a"));
+    /// Returns the k cell indices of the data, in the order of the hash
+    /// functions. The i-th index is derived from the lower and upper halves
+    /// of `hash64` by the filter's `probe_scheme`, modulo the number of
+    /// cells. Indices may repeat.
+    pub fn hash_indices(&self, data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+        let (lower, upper) = self.hash_kernel(data);
+        (0..(self.k)).map(move |i| self.location(lower, upper, i))
+    }
 
-        assert!(f.test_and_add(b"a"));
+    /// Returns, for every cell, how many of the sample keys map to it. Keys
+    /// are counted once per cell even if several of their hash functions hit
+    /// the same cell. Cells with a high load are the ones most likely to cause
+    /// false positives.
+    pub fn cell_load(&self, keys: &[&[u8]]) -> Vec<usize> {
+        let mut load = vec![0; self.m];
+        let mut indices = Vec::with_capacity(self.k);
 
-        assert!(!f.test_and_add(b"b"));
-        assert!(f.test(b"a"));
+        for key in keys.iter() {
+            indices.clear();
+            indices.extend(self.hash_indices(key));
+            indices.sort_unstable();
+            indices.dedup();
 
-        assert!(f.test(b"b"));
+            for i in indices.iter() {
+                load[*i] += 1;
+            }
+        }
 
-        assert!(!f.test(b"c"));
+        load
+    }
 
-        for i in 0..1_000_000 {
-            f.test_and_add(i.to_string().as_bytes());
-        }
+    /// Returns a read-only view of the filter, which can test for membership
+    /// and report statistics but not modify the filter.
+    pub fn view(&self) -> FilterView<'_, S> {
+        FilterView::new(self)
+    }
 
-        // `a` should have been evicted.
-        assert!(!f.test(b"a"));
+    /// Writes outstanding changes of memory-mapped cells back to their file.
+    /// Does nothing for cells held in memory.
+    pub fn flush(&self) -> io::Result<()> {
+        self.cells.flush()
     }
 
-    // Ensures that StablePoint returns the expected fraction of zeros for large
-    // iterations.
-    #[test]
-    fn test_stable_point() {
-        let mut f = StableBloomFilter::new(1000, 1, 0.1);
-        for i in 0..1_000_000 {
-            f.add(i.to_string().as_bytes());
+    /// Restores the Stable Bloom Filter to its original state. It returns the
+    /// filter to allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.cells.reset();
+        self.inserts = 0;
+        if let Some(history) = self.fill_history.as_mut() {
+            history.samples.clear();
         }
+        self
+    }
 
-        let mut zero = 0;
-        for i in 0..(f.m) {
-            if f.cells.get(i) == 0 {
-                zero += 1;
+    /// Returns the `(index, value)` pairs of every cell whose value differs
+    /// from the corresponding cell in `base`. Applying the result to `base`
+    /// with `apply_delta` reproduces this filter's cells. Both filters must
+    /// have the same number of cells and bits per cell.
+    pub fn cell_delta(&self, base: &Self) -> Result<Vec<(usize, u8)>, MergeError> {
+        self.check_geometry(base)?;
+
+        let mut delta = Vec::new();
+        for i in 0..(self.m) {
+            let value = self.cells.get(i);
+            if value != base.cells.get(i) {
+                delta.push((i, value));
             }
         }
 
-        let actual = round(f64::from(zero) / (f.m as f64), 0.5, 1);
-        let expected = round(f.stable_point(), 0.5, 1);
+        Ok(delta)
+    }
 
-        assert!(actual.approx_eq(expected, (f64::EPSILON, 1)));
-        // A classic Bloom filter is a special case of SBF where P is 0 and max is
-        // 1. It doesn't have a stable point.
-        let bf = StableBloomFilter::new_unstable(1000, 0.1);
-        assert!(bf.stable_point().approx_eq(0.0, (f64::EPSILON, 1)));
+    /// Returns the number of bytes a delta sync against `base` would transfer,
+    /// i.e. the number of differing cells times `DELTA_ENTRY_SIZE`, without
+    /// building the delta. Comparing it with the size of the full cell data
+    /// tells whether a delta sync is cheaper than a full transfer.
+    pub fn delta_size_estimate(&self, base: &Self) -> Result<usize, MergeError> {
+        self.check_geometry(base)?;
+
+        let differing = (0..(self.m))
+            .filter(|i| self.cells.get(*i) != base.cells.get(*i))
+            .count();
+
+        Ok(differing * DELTA_ENTRY_SIZE)
     }
 
-    // Ensures that FalsePositiveRate returns the upper bound on false positives
+    /// Sets every cell listed in the delta to its new value. The delta is
+    /// typically produced by `cell_delta`. It returns the filter to allow for
+    /// chaining, or an error, leaving the filter unchanged, if an index isn't
+    /// below the number of cells or a value exceeds the maximum cell value.
+    pub fn apply_delta(&mut self, delta: &[(usize, u8)]) -> Result<&Self, SbfError> {
+        for &(index, value) in delta.iter() {
+            if index >= self.m {
+                return Err(SbfError::CellIndexOutOfRange {
+                    index,
+                    cells: self.m,
+                });
+            }
+            if value > self.max {
+                return Err(SbfError::CellValueOutOfRange {
+                    value,
+                    max: self.max,
+                });
+            }
+        }
+        for &(index, value) in delta.iter() {
+            self.cells.set(index, value);
+        }
+        Ok(self)
+    }
+
+    /// Returns the `(index, value)` pairs of all non-zero cells. For filters
+    /// with low occupancy this is much smaller than the dense cell data.
+    pub fn to_sparse(&self) -> Vec<(usize, u8)> {
+        self.cells.iter_nonzero().collect()
+    }
+
+    /// Enables adaptive probe ordering. `test_and_add` records how often each
+    /// of the k probes hits an empty cell, and `test` checks the probes that
+    /// are most likely to be empty first so that negative lookups can return
+    /// early. Membership results are unchanged. This is a micro-optimization
+    /// for negative-heavy workloads and only pays off when the probes don't
+    /// hit empty cells equally often.
+    pub fn enable_probe_ordering(&mut self) {
+        self.probe_stats = Some(ProbeStats {
+            zeros: vec![0; self.k],
+            observations: 0,
+        });
+    }
+
+    /// Enables or disables eviction on add. While disabled, `add` and
+    /// `test_and_add` don't decrement any cells, so the filter behaves like a
+    /// classic Bloom filter and can saturate, e.g. during a bulk burst. Decay
+    /// applied explicitly with `apply_time_decay` still takes place.
+    pub fn set_eviction_enabled(&mut self, enabled: bool) -> &Self {
+        self.eviction = enabled;
+        self
+    }
+
+    /// Returns true if adds decrement cells to evict stale elements.
+    pub fn is_eviction_enabled(&self) -> bool {
+        self.eviction
+    }
+
+    /// Enables overload protection. Once the fill ratio exceeds trigger_fill,
+    /// boosted_p cells instead of p are decremented on every add until the
+    /// fill ratio is back at the expected fill ratio of the stable point. The
+    /// fill ratio is checked every 1024 adds, each check scanning all cells.
+    /// Boosted eviction increases the rate of false negatives while it lasts.
+    pub fn enable_overload_protection(&mut self, trigger_fill: f64, boosted_p: usize) {
+        self.overload = Some(OverloadProtection {
+            trigger_fill,
+            boosted_p,
+            active: false,
+        });
+    }
+
+    /// Returns true if overload protection is currently boosting eviction.
+    pub fn is_overloaded(&self) -> bool {
+        self.overload.as_ref().is_some_and(|o| o.active)
+    }
+
+    /// Periodically updates whether eviction needs to be boosted.
+    #[inline]
+    fn check_overload(&mut self) {
+        if self.overload.is_none() || self.inserts % OVERLOAD_CHECK_INTERVAL != 0 {
+            return;
+        }
+
+        let fill = self.fill_ratio();
+        let stable_fill = 1.0 - self.stable_point();
+        if let Some(overload) = self.overload.as_mut() {
+            if fill > overload.trigger_fill {
+                overload.active = true;
+            } else if fill <= stable_fill {
+                overload.active = false;
+            }
+        }
+    }
+
+    /// Enables recording the fill ratio every interval adds, keeping the most
+    /// recent samples. Each sample scans all cells, so with an interval of m
+    /// the cost is amortized to about one cell read per add. Panics if
+    /// interval is zero.
+    pub fn enable_fill_history(&mut self, samples: usize, interval: u64) {
+        assert!(interval > 0, "interval must be positive");
+        self.fill_history = Some(FillHistory {
+            samples: VecDeque::with_capacity(samples),
+            capacity: samples,
+            interval,
+        });
+    }
+
+    /// Returns the recorded fill ratios, oldest first. Returns no samples if
+    /// fill history isn't enabled.
+    pub fn fill_history(&self) -> impl Iterator<Item = f64> + '_ {
+        self.fill_history
+            .iter()
+            .flat_map(|history| history.samples.iter().copied())
+    }
+
+    /// Records the fill ratio every interval adds if fill history is enabled.
+    #[inline]
+    fn record_fill(&mut self) {
+        match self.fill_history.as_ref() {
+            Some(history) if self.inserts % history.interval == 0 => {}
+            _ => return,
+        }
+
+        let fill = self.fill_ratio();
+        if let Some(history) = self.fill_history.as_mut() {
+            if history.capacity == 0 {
+                return;
+            }
+            if history.samples.len() == history.capacity {
+                history.samples.pop_front();
+            }
+            history.samples.push_back(fill);
+        }
+    }
+
+    /// Marks the key as known not to be a member. From then on `test` and
+    /// `test_and_add` report it as absent even if all of its cells are set,
+    /// which removes false positives for a known-negative set of keys. Every
+    /// suppressed key is stored in full in a `HashSet`, so memory grows with
+    /// the number and size of suppressed keys.
+    pub fn suppress(&mut self, key: &[u8]) {
+        self.suppressed.insert(key.to_vec());
+    }
+
+    /// Returns true if the key was marked as a known non-member.
+    #[inline]
+    fn is_suppressed(&self, data: &[u8]) -> bool {
+        !self.suppressed.is_empty() && self.suppressed.contains(data)
+    }
+
+    /// Exchanges the cells of both filters. Both filters must be compatible
+    /// and hash data the same way, like for `merge`, as cells swapped into a
+    /// filter which derives different indices would yield false negatives.
+    pub(crate) fn swap_cells(&mut self, other: &mut Self) -> Result<(), MergeError> {
+        self.check_compatible(other)?;
+        self.check_hash(other)?;
+        std::mem::swap(&mut self.cells, &mut other.cells);
+        Ok(())
+    }
+
+    /// Merges the other filter into this one by taking the element-wise
+    /// maximum of their cells, so that elements added to either filter are
+    /// members of the result. The insert counts are summed. Both filters must
+    /// be compatible and hash data the same way, e.g. use the same seed.
+    pub fn merge(&mut self, other: &Self) -> Result<(), MergeError> {
+        self.check_compatible(other)?;
+        self.check_hash(other)?;
+
+        for i in 0..(self.m) {
+            let value = other.cells.get(i);
+            if value > self.cells.get(i) {
+                self.cells.set(i, value);
+            }
+        }
+        self.inserts = self.inserts.saturating_add(other.inserts);
+        Ok(())
+    }
+
+    /// Ensures that both filters hash data the same way, by comparing the
+    /// hashes of a few probe inputs.
+    fn check_hash(&self, other: &Self) -> Result<(), MergeError> {
+        let probes: [&[u8]; 3] = [b"", b"stable-bloom-filter", &[0xff; 32]];
+        if self.probe_scheme != other.probe_scheme
+            || probes.iter().any(|p| self.hash64(p) != other.hash64(p))
+        {
+            return Err(MergeError::HashMismatch);
+        }
+        Ok(())
+    }
+
+    /// Returns true if both filters have the same number of cells, bits per
+    /// cell, maximum cell value and hash functions, which is required for
+    /// element-wise operations between them.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.check_compatible(other).is_ok()
+    }
+
+    /// Ensures that the filters are compatible for element-wise operations.
+    /// The maximum cell value follows from the bits per cell.
+    fn check_compatible(&self, other: &Self) -> Result<(), MergeError> {
+        self.check_geometry(other)?;
+        if self.k != other.k {
+            return Err(MergeError::HashFunctionsMismatch {
+                left: self.k,
+                right: other.k,
+            });
+        }
+        Ok(())
+    }
+
+    /// Ensures that both filters have the same number of cells and bits per
+    /// cell, so that their cells can be compared index by index.
+    fn check_geometry(&self, other: &Self) -> Result<(), MergeError> {
+        if self.m != other.m {
+            return Err(MergeError::CellsMismatch {
+                left: self.m,
+                right: other.m,
+            });
+        }
+        if self.cells.bucket_size() != other.cells.bucket_size() {
+            return Err(MergeError::BucketSizeMismatch {
+                left: self.cells.bucket_size(),
+                right: other.cells.bucket_size(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Will decrement a random cell and (p-1) adjacent cells by 1. This
+    /// is faster than generating p random numbers. Although the processes of
+    /// picking the p cells are not independent, each cell has a probability of p/m
+    /// for being picked at each iteration, which means the properties still hold.
+    #[inline]
+    pub fn decrement(&mut self) {
+        let p = match &self.overload {
+            Some(overload) if overload.active => overload.boosted_p,
+            _ => self.p,
+        };
+        self.decrement_cells(p);
+    }
+
+    /// Will decrement a random cell and (n-1) adjacent cells by 1, wrapping
+    /// around the end of the filter. Unlike decrement, the number of cells
+    /// doesn't depend on p, which lets eviction run at a pace independent of
+    /// adds.
+    pub fn decrement_cells(&mut self, n: usize) {
+        let r: usize = self.rng.gen_range(0, self.m);
+
+        // The n cells wrap around the end of the filter at most once, so
+        // they are split into two ranges rather than taking every index
+        // modulo m.
+        let end = r + n.min(self.m);
+        for idx in r..end.min(self.m) {
+            self.cells.decrease(idx, 1);
+        }
+        for idx in 0..end.saturating_sub(self.m) {
+            self.cells.decrease(idx, 1);
+        }
+    }
+
+    /// Applies the decay accumulated over the elapsed time, running one
+    /// decrement iteration per 1/rate_per_sec seconds. The fraction of an
+    /// iteration left over is carried into the next call, so that short
+    /// intervals aren't lost. This lets filters age during idle periods.
+    /// Past max·m/p iterations, after which every cell is expected to have
+    /// been decremented down to zero, the cells are cleared at once instead,
+    /// which approximates running the iterations one by one. Filters which
+    /// don't decrement any cells (p = 0) are left unchanged and 0 is
+    /// returned. Otherwise, returns the number of iterations the elapsed time
+    /// amounts to. Panics if rate_per_sec is negative or not finite.
+    pub fn apply_time_decay(&mut self, elapsed: Duration, rate_per_sec: f64) -> usize {
+        assert!(
+            rate_per_sec.is_finite() && rate_per_sec >= 0.0,
+            "rate_per_sec must be finite and non-negative"
+        );
+        if self.p == 0 {
+            return 0;
+        }
+        let iterations = self.decay_residual + elapsed.as_secs_f64() * rate_per_sec;
+        let whole = iterations.floor();
+
+        let limit = usize::from(self.max) * self.m.div_ceil(self.p);
+        if whole > limit as f64 {
+            self.decay_residual = 0.0;
+            self.cells.reset();
+        } else {
+            self.decay_residual = iterations - whole;
+            for _ in 0..(whole as usize) {
+                self.decrement();
+            }
+        }
+
+        whole as usize
+    }
+
+    /// Returns a handle caching the hash of the data, for keys which are
+    /// re-added repeatedly.
+    pub fn handle(&self, data: &[u8]) -> KeyHandle {
+        KeyHandle {
+            hash: self.hash64(data),
+        }
+    }
+
+    /// Re-adds the key behind the handle, decrementing p cells and setting its
+    /// k cells again, without re-hashing the key. Refreshing a key regularly
+    /// keeps it from being evicted. It returns the filter to allow for
+    /// chaining.
+    pub fn refresh(&mut self, handle: &KeyHandle) -> &Self {
+        self.add_hash(handle.hash)
+    }
+
+    /// Will test for membership of an element whose 64-bit hash was computed
+    /// upfront, e.g. by `hash64`. The hash is used as the kernel for the k
+    /// cell indices directly instead of hashing the data again.
+    #[inline]
+    pub fn test_hash(&self, hash: u64) -> bool {
+        let (lower, upper) = split_hash(hash);
+        #[cfg(feature = "simd")]
+        {
+            // Probes are checked eight at a time, the width of a gather.
+            let mut locations = [0; 8];
+            for probes in self.probe_order.chunks(locations.len()) {
+                for (location, &i) in locations.iter_mut().zip(probes) {
+                    *location = self.location(lower, upper, i);
+                }
+                if !self.cells.all_nonzero(&locations[..probes.len()]) {
+                    return false;
+                }
+            }
+        }
+        #[cfg(not(feature = "simd"))]
+        for &i in self.probe_order.iter() {
+            if self.cells.get(self.location(lower, upper, i)) == 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Will add an element whose 64-bit hash was computed upfront to the
+    /// Stable Bloom Filter. Cells already above the fill value, e.g. set by
+    /// `add_with_level`, are left as is. It returns the filter to allow for
+    /// chaining.
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) -> &Self {
+        self.add_hash_at(hash, self.fill_value)
+    }
+
+    /// Will add the data to the Stable Bloom Filter with its k cells set to
+    /// at least the provided level, clamped to between 1 and the maximum
+    /// cell value, instead of the fill value. Items added below the maximum
+    /// need fewer decrements to be evicted, so they are retained for a
+    /// shorter time than items added at a higher level. Cells already above
+    /// the level are left as is, so that the item doesn't shorten the
+    /// retention of items sharing them. It returns the filter to allow for
+    /// chaining.
+    pub fn add_with_level(&mut self, data: &[u8], level: u8) -> &Self {
+        self.add_hash_with_level(self.hash64(data), level)
+    }
+
+    /// Is equivalent to add_with_level for an element whose 64-bit hash was
+    /// computed upfront. It returns the filter to allow for chaining.
+    pub fn add_hash_with_level(&mut self, hash: u64, level: u8) -> &Self {
+        self.add_hash_at(hash, level.max(1).min(self.max))
+    }
+
+    /// Adds the element whose 64-bit hash is provided, raising its k cells
+    /// to at least level, which must be between 1 and the maximum cell value.
+    #[inline]
+    fn add_hash_at(&mut self, hash: u64, level: u8) -> &Self {
+        self.inserts += 1;
+        self.check_overload();
+        self.record_fill();
+        // Randomly decrement p cells to make room for new elements.
+        if self.eviction {
+            self.decrement();
+        }
+        let (lower, upper) = split_hash(hash);
+
+        for i in 0..(self.k) {
+            let location = self.location(lower, upper, i);
+            if self.cells.get(location) < level {
+                self.cells.set(location, level);
+            }
+        }
+        self
+    }
+
+    /// Will add all items to the Stable Bloom Filter. This is equivalent to
+    /// calling add for every item, but all items are hashed up front. It
+    /// returns the filter to allow for chaining.
+    pub fn add_all<I>(&mut self, items: I) -> &Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let hashes: Vec<u64> = items
+            .into_iter()
+            .map(|item| self.hash64(item.as_ref()))
+            .collect();
+
+        for hash in hashes {
+            self.add_hash(hash);
+        }
+        self
+    }
+
+    /// Will test for membership of every item and returns whether each is a
+    /// member, in the order of the items.
+    pub fn test_batch<I>(&self, items: I) -> Vec<bool>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        items
+            .into_iter()
+            .map(|item| self.test_hash(self.hash64(item.as_ref())))
+            .collect()
+    }
+
+    /// Is equivalent to calling test_hash followed by add_hash. It returns
+    /// true if the element is a member, false if not.
+    #[inline]
+    pub fn test_and_add_hash(&mut self, hash: u64) -> bool {
+        self.inserts += 1;
+        self.check_overload();
+        self.record_fill();
+        let (lower, upper) = split_hash(hash);
+        let mut member = true;
+
+        // If any of the K cells are 0, then it's not a member.
+        for i in 0..(self.k) {
+            self.index_buffer[i] = self.location(lower, upper, i);
+            if self.cells.get(self.index_buffer[i]) == 0 {
+                member = false;
+                if let Some(stats) = self.probe_stats.as_mut() {
+                    stats.zeros[i] += 1;
+                }
+            }
+        }
+
+        // Periodically move the probes most likely to be empty to the front.
+        if let Some(stats) = self.probe_stats.as_mut() {
+            stats.observations += 1;
+            if stats.observations % PROBE_REORDER_INTERVAL == 0 {
+                let zeros = &stats.zeros;
+                self.probe_order.sort_by(|a, b| zeros[*b].cmp(&zeros[*a]));
+            }
+        }
+
+        // Randomly decrement p cells to make room for new elements.
+        if self.eviction {
+            self.decrement();
+        }
+        // Raise the K cells to the fill value.
+        for i in self.index_buffer.iter() {
+            if self.cells.get(*i) < self.fill_value {
+                self.cells.set(*i, self.fill_value);
+            }
+        }
+
+        member
+    }
+
+    /// Returns the index of the cell for the i-th hash function.
+    #[inline]
+    fn location(&self, lower: u32, upper: u32, i: usize) -> usize {
+        location(self.probe_scheme, lower, upper, i, self.m)
+    }
+}
+
+/// Returns the index, out of m cells, of the i-th probe of the probe scheme.
+#[inline]
+pub(crate) fn location(probe: ProbeScheme, lower: u32, upper: u32, i: usize, m: usize) -> usize {
+    match probe {
+        // Computed in 64 bits, so that indices don't depend on the width of
+        // usize.
+        ProbeScheme::DoubleHashing => {
+            ((u64::from(lower) + u64::from(upper) * i as u64) % m as u64) as usize
+        }
+        ProbeScheme::EnhancedDoubleHashing => enhanced_location(lower, upper, i, m),
+    }
+}
+
+/// Returns the index, out of m cells, of the i-th probe of enhanced double
+/// hashing. Iteratively, the index starts at lower, the step at upper, and
+/// after the i-th probe the index grows by the step and the step by i, which
+/// sums up to lower + upper * i + i * (i - 1) * (i - 2) / 6.
+#[inline]
+pub(crate) fn enhanced_location(lower: u32, upper: u32, i: usize, m: usize) -> usize {
+    let m = m as u64;
+    let step = match u64::from(upper | 1) % m {
+        0 => 1,
+        step => step,
+    };
+    let i = i as u64;
+    let cubic = (i * i.saturating_sub(1) * i.saturating_sub(2) / 6) % m;
+
+    ((u64::from(lower) % m + (i * step) % m + cubic) % m) as usize
+}
+
+/// Returns the probe scheme of filters serialized before the scheme was
+/// recorded.
+#[cfg(feature = "serde")]
+fn legacy_probe_scheme() -> ProbeScheme {
+    ProbeScheme::DoubleHashing
+}
+
+/// Serialized form of `StableBloomFilter`, which is validated before the
+/// filter is restored from it.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct FilterState<S> {
+    cells: Buckets,
+    hash: S,
+    m: usize,
+    p: usize,
+    k: usize,
+    max: u8,
+    index_buffer: Vec<usize>,
+    fp_rate: f64,
+    suppressed: HashSet<Vec<u8>>,
+    probe_order: Vec<usize>,
+    probe_stats: Option<ProbeStats>,
+    inserts: u64,
+    fill_value: u8,
+    overload: Option<OverloadProtection>,
+    decay_residual: f64,
+    eviction: bool,
+    fill_history: Option<FillHistory>,
+    #[serde(default = "legacy_probe_scheme")]
+    probe_scheme: ProbeScheme,
+}
+
+#[cfg(feature = "serde")]
+impl<S> TryFrom<FilterState<S>> for StableBloomFilter<S> {
+    type Error = SbfError;
+
+    fn try_from(state: FilterState<S>) -> Result<Self, SbfError> {
+        if state.m == 0 {
+            return Err(SbfError::ZeroCells);
+        }
+        if state.m != state.cells.count() {
+            return Err(SbfError::InconsistentState(
+                "number of cells doesn't match the cell data",
+            ));
+        }
+        if state.max != state.cells.max_bucket_value() {
+            return Err(SbfError::InconsistentState(
+                "maximum value doesn't match the cell data",
+            ));
+        }
+        if state.k == 0 {
+            return Err(SbfError::InconsistentState("no hash functions"));
+        }
+        if state.index_buffer.len() != state.k || state.probe_order.len() != state.k {
+            return Err(SbfError::InconsistentState(
+                "probe buffers don't match the number of hash functions",
+            ));
+        }
+        let mut probes = vec![false; state.k];
+        for &i in state.probe_order.iter() {
+            if i >= state.k || std::mem::replace(&mut probes[i], true) {
+                return Err(SbfError::InconsistentState(
+                    "probe order isn't a permutation of the hash functions",
+                ));
+            }
+        }
+        if let Some(stats) = state.probe_stats.as_ref() {
+            if stats.zeros.len() != state.k {
+                return Err(SbfError::InconsistentState(
+                    "probe statistics don't match the number of hash functions",
+                ));
+            }
+        }
+        if state.fill_value == 0 || state.fill_value > state.max {
+            return Err(SbfError::InvalidFillValue(state.fill_value));
+        }
+        if !(0.0..1.0).contains(&state.decay_residual) {
+            return Err(SbfError::InconsistentState(
+                "decay residual isn't a fraction of an iteration",
+            ));
+        }
+        if let Some(history) = state.fill_history.as_ref() {
+            if history.interval == 0 || history.samples.len() > history.capacity {
+                return Err(SbfError::InconsistentState(
+                    "fill history exceeds its capacity or has no interval",
+                ));
+            }
+        }
+
+        Ok(StableBloomFilter {
+            cells: state.cells,
+            hash: state.hash,
+            m: state.m,
+            p: state.p,
+            k: state.k,
+            max: state.max,
+            index_buffer: state.index_buffer,
+            fp_rate: state.fp_rate,
+            suppressed: state.suppressed,
+            probe_order: state.probe_order,
+            probe_stats: state.probe_stats,
+            inserts: state.inserts,
+            fill_value: state.fill_value,
+            overload: state.overload,
+            decay_residual: state.decay_residual,
+            eviction: state.eviction,
+            fill_history: state.fill_history,
+            rng: new_rng(),
+            probe_scheme: state.probe_scheme,
+        })
+    }
+}
+
+/// Returns the number of hash functions of a Stable Bloom Filter with m cells
+/// optimized for the target false-positive rate.
+fn stable_k(m: usize, fp_rate: f64) -> usize {
+    let k = optimal_k(fp_rate) / 2;
+    if k > m {
+        m
+    } else if k == 0 {
+        1
+    } else {
+        k
+    }
+}
+
+/// Returns the number of distinct elements which, added to a filter with m
+/// cells and k hash functions, are expected to leave the given fraction of
+/// cells zero.
+fn cardinality(m: usize, k: usize, zeros: f64) -> f64 {
+    zeros.ln() / ((k as f64) * (1.0 - 1.0 / (m as f64)).ln())
+}
+
+/// Returns the expected fraction of zero cells of a filter with m cells, k
+/// hash functions, p cells decremented on every add and the given maximum
+/// cell value once it has become stable.
+pub(crate) fn stable_zeros(m: usize, k: usize, p: usize, max: u8) -> f64 {
+    let sub_denom = (p as f64) * ((1.0 / (k as f64)) - (1.0 / (m as f64)));
+    let denom = 1.0 + 1.0 / sub_denom;
+    let base = 1.0 / denom;
+
+    base.powf(f64::from(max))
+}
+
+/// Inverts the stable point formula, returning the fractional number of cells
+/// to decrement for which the expected fraction of zero cells is target_zeros.
+fn stable_p(m: usize, k: usize, max: u8, target_zeros: f64) -> f64 {
+    let base = target_zeros.powf(1.0 / f64::from(max));
+    let sub_denom = 1.0 / (1.0 / base - 1.0);
+    sub_denom / (1.0 / (k as f64) - 1.0 / (m as f64))
+}
+
+/// Returns a random number generator to pick the cells to decrement, seeded
+/// from the thread-local generator. The filter owns it, so that adds don't
+/// look up the thread-local generator every time.
+fn new_rng() -> SmallRng {
+    SmallRng::from_rng(thread_rng()).expect("thread-local generator failed")
+}
+
+/// Returns an error if a filter can't be created with m cells of d bits for
+/// the target false-positive rate.
+pub(crate) fn check_parameters(m: usize, d: u8, fp_rate: f64) -> Result<(), SbfError> {
+    if m == 0 {
+        return Err(SbfError::ZeroCells);
+    }
+    if d == 0 || d > 8 {
+        return Err(SbfError::InvalidBucketSize(d));
+    }
+    if !(fp_rate > 0.0 && fp_rate < 1.0) {
+        return Err(SbfError::InvalidFpRate(fp_rate));
+    }
+    Ok(())
+}
+
+/// Splits a 64-bit hash into the lower and upper halves used as the kernel
+/// for the k hash functions.
+#[inline]
+pub(crate) fn split_hash(hash: u64) -> (u32, u32) {
+    (hash as u32, (hash >> 32) as u32)
+}
+
+impl<S: BuildHasher + Clone> StableBloomFilter<S> {
+    /// Returns a new, empty filter with the same parameters, hash kernel and
+    /// settings as this one. Cells, statistics and suppressed keys aren't
+    /// copied.
+    pub fn empty_like(&self) -> Self {
+        let mut f = Self::with_parameters(
+            self.m,
+            self.k,
+            self.p,
+            self.d(),
+            self.fp_rate,
+            self.hash.clone(),
+        );
+        f.fill_value = self.fill_value;
+        f.eviction = self.eviction;
+        f.probe_scheme = self.probe_scheme;
+        if self.probe_stats.is_some() {
+            f.enable_probe_ordering();
+        }
+        if let Some(overload) = self.overload.as_ref() {
+            f.enable_overload_protection(overload.trigger_fill, overload.boosted_p);
+        }
+        if let Some(history) = self.fill_history.as_ref() {
+            f.enable_fill_history(history.capacity, history.interval);
+        }
+        f
+    }
+
+    /// Returns a filter approximating the intersection of both filters, whose
+    /// cells are the element-wise minimum of their cells. For classic Bloom
+    /// filters created with `new_unstable` this is the bitwise AND of their
+    /// bits: every element added to both filters is a member of the result,
+    /// with a false-positive rate at least as high as either filter's. The
+    /// filters must be compatible and hash data the same way.
+    pub fn intersect(&self, other: &Self) -> Result<Self, MergeError> {
+        self.check_compatible(other)?;
+        self.check_hash(other)?;
+
+        let mut f = self.empty_like();
+        for i in 0..(self.m) {
+            f.cells.set(i, self.cells.get(i).min(other.cells.get(i)));
+        }
+        Ok(f)
+    }
+}
+
+impl<S: BuildHasher> Filter for StableBloomFilter<S> {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not. This is a probabilistic test, meaning there is a
+    /// non-zero probability of false positives and false negatives.
+    #[inline]
+    fn test(&self, data: &[u8]) -> bool {
+        !self.is_suppressed(data) && self.test_hash(self.hash64(data))
+    }
+
+    /// Will add the data to the Stable Bloom Filter.
+    #[inline]
+    fn add(&mut self, data: &[u8]) {
+        self.add_hash(self.hash64(data));
+    }
+
+    /// Is equivalent to calling Test followed by Add. It returns true if
+    /// the data is a member, false if not.
+    #[inline]
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.test_and_add_hash(self.hash64(data)) && !self.is_suppressed(data)
+    }
+
+    /// Will remove the data from the Stable Bloom Filter by zeroing its k
+    /// cells. Other elements sharing any of those cells are removed as well
+    /// and will test negative.
+    fn remove(&mut self, data: &[u8]) {
+        let (lower, upper) = split_hash(self.hash64(data));
+        for i in 0..(self.k) {
+            self.cells.set(self.location(lower, upper, i), 0);
+        }
+    }
+}
+
+impl<S: BuildHasher> HashFilter for StableBloomFilter<S> {
+    /// Will test for membership of the item and returns true if it is a
+    /// member, false if not. Items are hashed with their `Hash`
+    /// implementation, which for byte slices differs from hashing the bytes
+    /// with `test`, and suppressed keys are not taken into account.
+    #[inline]
+    fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.test_hash(self.hash.hash_one(item))
+    }
+
+    /// Will add the item to the Stable Bloom Filter. It returns the filter to
+    /// allow for chaining.
+    #[inline]
+    fn insert<T: Hash + ?Sized>(&mut self, item: &T) -> &Self {
+        self.add_hash(self.hash.hash_one(item))
+    }
+}
+
+/// Filters are equal if they have the same parameters, hash data the same
+/// way and hold the same cells and suppressed keys. Statistics such as the
+/// insert count, and the state of the random number generator, are ignored.
+impl<S: PartialEq> PartialEq for StableBloomFilter<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.m == other.m
+            && self.k == other.k
+            && self.p == other.p
+            && self.fp_rate == other.fp_rate
+            && self.fill_value == other.fill_value
+            && self.eviction == other.eviction
+            && self.hash == other.hash
+            && self.probe_scheme == other.probe_scheme
+            && self.cells == other.cells
+            && self.suppressed == other.suppressed
+    }
+}
+
+/// Prints the parameters and current statistics of the filter rather than
+/// its cells.
+impl<S: BuildHasher> fmt::Debug for StableBloomFilter<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StableBloomFilter")
+            .field("m", &self.m)
+            .field("k", &self.k)
+            .field("p", &self.p)
+            .field("d", &self.d())
+            .field("stable_point", &self.stable_point())
+            .field("false_positive_rate", &self.false_positive_rate())
+            .field("fill_ratio", &self.fill_ratio())
+            .finish()
+    }
+}
+
+/// Prints a one-line summary of the filter.
+impl<S: BuildHasher> fmt::Display for StableBloomFilter<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "StableBloomFilter(m={}, k={}, p={}, d={}, fp<={:.4}, fill={:.4})",
+            self.m,
+            self.k,
+            self.p,
+            self.d(),
+            self.false_positive_rate(),
+            self.fill_ratio()
+        )
+    }
+}
+
+/// Returns true only if the data tests positive in every filter. Each filter
+/// hashes the data independently, so the filters don't need to share their
+/// parameters. Returns true for an empty slice of filters.
+pub fn test_all<S: BuildHasher>(filters: &[&StableBloomFilter<S>], data: &[u8]) -> bool {
+    filters.iter().all(|f| f.test(data))
+}
+
+/// Returns the false-positive rate of a lookup that checks every filter and
+/// reports a match if any of them tests positive, i.e.
+/// 1 - (1 - fpr_1) * ... * (1 - fpr_n) where fpr_i is the stable
+/// false-positive rate of each filter. Returns zero for an empty slice of
+/// filters.
+pub fn combined_false_positive_rate<S: BuildHasher>(filters: &[&StableBloomFilter<S>]) -> f64 {
+    1.0 - filters
+        .iter()
+        .map(|f| 1.0 - f.false_positive_rate())
+        .product::<f64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        combined_false_positive_rate, enhanced_location, expected_byte_len, split_hash,
+        stable_zeros, test_all, MembershipStatus, StableBloomFilter, DELTA_ENTRY_SIZE,
+    };
+    use crate::error::{MergeError, SbfError};
+    use crate::optimal_k;
+    use crate::sharded::ShardedStableBloomFilter;
+    use crate::Filter;
+    use float_cmp::ApproxEq;
+    use std::f64;
+    use std::time::Duration;
+
+    fn round(val: f64, round_on: f64, places: usize) -> f64 {
+        let pow = (10.0_f64).powf(places as f64);
+        let digit = pow * val;
+        let div = digit - digit.floor();
+        let round = if div >= round_on {
+            digit.ceil()
+        } else {
+            digit.floor()
+        };
+
+        round / pow
+    }
+
+    // Ensures that new_unstable creates a Stable Bloom Filter with p=0,
+    // max=1 and k hash functions.
+    #[test]
+    fn test_new_unstable() {
+        let f = StableBloomFilter::new_unstable(100, 0.1);
+        let k = optimal_k(0.1);
+
+        assert_eq!(f.k, k);
+        assert_eq!(f.m, 100);
+        assert_eq!(f.p(), 0);
+        assert_eq!(f.max(), 1);
+    }
+
+    // Ensures that Cells returns the number of cells, m, in the Stable Bloom
+    // Filter.
+    #[test]
+    fn test_cells() {
+        let f = StableBloomFilter::new(100, 1, 0.1);
+
+        assert_eq!(f.cells(), 100);
+    }
+
+    // Ensures that K returns the number of hash functions in the Stable Bloom
+    // Filter.
+    #[test]
+    fn test_k() {
+        let f = StableBloomFilter::new(100, 1, 0.01);
+        assert_eq!(f.k(), 3);
+    }
+
+    // Ensures that Test, Add, and TestAndAdd behave correctly.
+    #[test]
+    fn test_test_and_add() {
+        // Seeded, so that `a` is evicted rather than left a false positive.
+        let mut f = StableBloomFilter::new_with_seed(1_000, 1, 0.01, 42);
+        assert!(!f.test(b"a"));
+
+        f.add(b"a");
+        assert!(f.test(b"a"));
+
+        assert!(f.test_and_add(b"a"));
+
+        assert!(!f.test_and_add(b"b"));
+        assert!(f.test(b"a"));
+
+        assert!(f.test(b"b"));
+
+        assert!(!f.test(b"c"));
+
+        for i in 0..1_000_000 {
+            f.test_and_add(i.to_string().as_bytes());
+        }
+
+        // `a` should have been evicted.
+        assert!(!f.test(b"a"));
+    }
+
+    // Ensures that StablePoint returns the expected fraction of zeros for large
+    // iterations.
+    #[test]
+    fn test_stable_point() {
+        let mut f = StableBloomFilter::new(1000, 1, 0.1);
+        for i in 0..1_000_000 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let actual = round(f.zeros_fraction(), 0.5, 1);
+        let expected = round(f.stable_point(), 0.5, 1);
+
+        assert!(actual.approx_eq(expected, (f64::EPSILON, 1)));
+        // A classic Bloom filter is a special case of SBF where P is 0 and max is
+        // 1. It doesn't have a stable point.
+        let bf = StableBloomFilter::new_unstable(1000, 0.1);
+        assert!(bf.stable_point().approx_eq(0.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that FalsePositiveRate returns the upper bound on false positives
     // for stable filters.
     #[test]
-    fn test_false_positive_rate() {
+    fn test_false_positive_rate() {
+        let f = StableBloomFilter::new_default(1000, 0.01);
+        let fps = round(f.false_positive_rate(), 0.5, 2);
+
+        assert!(fps.approx_eq(0.01, (f64::EPSILON, 1)));
+
+        // Classic Bloom filters have an unbounded rate of false positives. Once
+        // they become full, every query returns a false positive.
+        let bf = StableBloomFilter::new_unstable(1000, 0.1);
+        assert!(bf.false_positive_rate().approx_eq(1.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that Reset sets every cell to zero.
+    #[test]
+    fn test_reset() {
+        let mut f = StableBloomFilter::new_default(1000, 0.01);
+
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        f.reset();
+
+        for i in 0..(f.m) {
+            assert_eq!(f.cells.get(i), 0);
+        }
+    }
+
+    // Ensures that applying the delta computed against a base filter to that
+    // base reproduces the current filter, and that mismatched geometry is
+    // rejected.
+    #[test]
+    fn test_cell_delta_and_apply_delta() {
+        let base = StableBloomFilter::new(1000, 2, 0.01);
+        let mut current = StableBloomFilter::new(1000, 2, 0.01);
+
+        for i in 0..10 {
+            current.add(i.to_string().as_bytes());
+        }
+
+        let delta = current.cell_delta(&base).unwrap();
+        assert!(!delta.is_empty());
+
+        let mut synced = StableBloomFilter::new(1000, 2, 0.01);
+        synced.apply_delta(&delta).unwrap();
+
+        for i in 0..(current.m) {
+            assert_eq!(synced.cells.get(i), current.cells.get(i));
+        }
+        assert!(synced.cell_delta(&current).unwrap().is_empty());
+
+        let other = StableBloomFilter::new(100, 2, 0.01);
+        assert_eq!(
+            current.cell_delta(&other),
+            Err(MergeError::CellsMismatch {
+                left: 1000,
+                right: 100
+            })
+        );
+
+        let other = StableBloomFilter::new(1000, 1, 0.01);
+        assert_eq!(
+            current.cell_delta(&other),
+            Err(MergeError::BucketSizeMismatch { left: 2, right: 1 })
+        );
+    }
+
+    // Ensures that after retention_half_life inserts roughly half of a cohort of
+    // added elements has been evicted.
+    #[test]
+    fn test_retention_half_life() {
+        for &d in [1, 2].iter() {
+            let mut f = StableBloomFilter::new(100_000, d, 0.01);
+            let half_life = f.retention_half_life().round() as usize;
+
+            // Warm up the filter until it reaches its stable point.
+            for i in 0..(20 * half_life) {
+                f.add(format!("warmup-{}", i).as_bytes());
+            }
+            // Keep the cohort small compared to the half-life so that its
+            // members are all roughly the same age.
+            for i in 0..200 {
+                f.add(format!("cohort-{}", i).as_bytes());
+            }
+            for i in 0..half_life {
+                f.add(format!("after-{}", i).as_bytes());
+            }
+
+            let evicted = (0..200)
+                .filter(|i| !f.test(format!("cohort-{}", i).as_bytes()))
+                .count();
+            assert!(evicted > 60 && evicted < 140);
+        }
+
+        let bf = StableBloomFilter::new_unstable(1000, 0.1);
+        assert!(bf.retention_half_life().is_infinite());
+    }
+
+    // Ensures that fp_rate_error reports the signed difference between the
+    // realized and the target false-positive rate.
+    #[test]
+    fn test_fp_rate_error() {
+        // p is truncated from ~2.3 to 2, so fewer cells are decremented than
+        // needed and the realized rate is too high.
+        let f = StableBloomFilter::new(1000, 1, 0.3);
+        assert_eq!(f.k(), 1);
+        assert_eq!(f.p(), 2);
+        assert!(f.target_fp_rate().approx_eq(0.3, (f64::EPSILON, 1)));
+        assert!(f.fp_rate_error() > 0.0);
+        assert!(f
+            .fp_rate_error()
+            .approx_eq(f.false_positive_rate() - 0.3, (f64::EPSILON, 1)));
+
+        // k and p are both clamped up from 0 to 1, so more cells are
+        // decremented than needed and the realized rate is too low.
+        let f = StableBloomFilter::new(1000, 1, 0.7);
+        assert_eq!(f.k(), 1);
+        assert_eq!(f.p(), 1);
+        assert!(f.fp_rate_error() < 0.0);
+
+        // A classic Bloom filter eventually returns only false positives.
+        let bf = StableBloomFilter::new_unstable(1000, 0.1);
+        assert!(bf.fp_rate_error().approx_eq(0.9, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that suppressed keys test negative even if all of their cells
+    // are set.
+    #[test]
+    fn test_suppress() {
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        f.add(b"a");
+        f.add(b"b");
+        assert!(f.test(b"a"));
+
+        f.suppress(b"a");
+        assert!(!f.test(b"a"));
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test(b"b"));
+    }
+
+    // Ensures that a filter round-tripped through to_sparse and from_sparse
+    // keeps its cells and memberships.
+    #[test]
+    fn test_sparse_round_trip() {
+        let mut f = StableBloomFilter::new(10_000, 2, 0.01);
+        // Every key stays a member only if adds don't evict.
+        f.set_eviction_enabled(false);
+        for i in 0..20 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let entries = f.to_sparse();
+        assert!(!entries.is_empty());
+        assert!(entries.len() <= 20 * f.k());
+
+        let restored = StableBloomFilter::from_sparse(&entries, 10_000, 2, 0.01).unwrap();
+        for i in 0..20 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+        for i in 0..(f.m) {
+            assert_eq!(restored.cells.get(i), f.cells.get(i));
+        }
+    }
+
+    // Ensures that entries which don't fit the filter are rejected without
+    // modifying it, including indices of the padding bits of the last byte.
+    #[test]
+    fn test_apply_invalid_delta() {
+        let mut f = StableBloomFilter::new(10, 2, 0.01);
+        assert_eq!(
+            f.apply_delta(&[(0, 1), (12, 1)]).err(),
+            Some(SbfError::CellIndexOutOfRange {
+                index: 12,
+                cells: 10
+            })
+        );
+        assert_eq!(
+            f.apply_delta(&[(0, 1), (1, 4)]).err(),
+            Some(SbfError::CellValueOutOfRange { value: 4, max: 3 })
+        );
+        assert_eq!(f.fill_ratio(), 0.0);
+
+        assert_eq!(
+            StableBloomFilter::from_sparse(&[(10, 1)], 10, 2, 0.01).err(),
+            Some(SbfError::CellIndexOutOfRange {
+                index: 10,
+                cells: 10
+            })
+        );
+        assert_eq!(
+            StableBloomFilter::from_sparse(&[], 0, 2, 0.01).err(),
+            Some(SbfError::ZeroCells)
+        );
+    }
+
+    // Ensures that test_all only returns true for data present in every
+    // filter.
+    #[test]
+    fn test_test_all() {
+        let mut a = StableBloomFilter::new_unstable(1000, 0.01);
+        let mut b = StableBloomFilter::new_unstable(2000, 0.001);
+        a.add(b"both");
+        b.add(b"both");
+        a.add(b"only-a");
+
+        assert!(test_all(&[&a, &b], b"both"));
+        assert!(!test_all(&[&a, &b], b"only-a"));
+        assert!(!test_all(&[&a, &b], b"neither"));
+    }
+
+    // Ensures that enabling probe ordering doesn't change membership results.
+    #[test]
+    fn test_probe_ordering() {
+        // Classic filters don't evict, so both filters end up identical.
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        let mut ordered = StableBloomFilter::new_unstable(1000, 0.01);
+        ordered.enable_probe_ordering();
+
+        for i in 0..5000 {
+            let data = (i % 200).to_string();
+            assert_eq!(
+                f.test_and_add(data.as_bytes()),
+                ordered.test_and_add(data.as_bytes())
+            );
+        }
+
+        for i in 0..10_000 {
+            let data = i.to_string();
+            assert_eq!(f.test(data.as_bytes()), ordered.test(data.as_bytes()));
+        }
+    }
+
+    /// Returns the number of probes test checks for the data until it finds an
+    /// empty cell, or k if the data tests positive.
+    fn probes_checked(f: &StableBloomFilter, data: &[u8]) -> usize {
+        let (lower, upper) = split_hash(f.hash64(data));
+        f.probe_order
+            .iter()
+            .position(|&i| f.cells.get(f.location(lower, upper, i)) == 0)
+            .map_or(f.k, |n| n + 1)
+    }
+
+    // Ensures that probe ordering reduces the average number of probes checked
+    // by negative lookups when some probes are more likely to hit set cells
+    // than others, here because the cells of the first two probes of every
+    // key are set.
+    #[test]
+    fn test_probe_ordering_probe_count() {
+        let mut f = StableBloomFilter::new_unstable(100_000, 0.01);
+        let occupy = |f: &mut StableBloomFilter, data: &[u8]| {
+            let (lower, upper) = split_hash(f.hash64(data));
+            for i in 0..2 {
+                let location = f.location(lower, upper, i);
+                f.cells.set(location, 1);
+            }
+        };
+        let negatives: Vec<Vec<u8>> = (0..1000)
+            .map(|i| format!("negative-{}", i).into_bytes())
+            .collect();
+        for data in negatives.iter() {
+            occupy(&mut f, data);
+        }
+        let average = |f: &StableBloomFilter| {
+            let total: usize = negatives.iter().map(|data| probes_checked(f, data)).sum();
+            (total as f64) / (negatives.len() as f64)
+        };
+        let unordered = average(&f);
+        assert!(unordered > 2.0);
+
+        f.enable_probe_ordering();
+        for i in 0..2048 {
+            let data = format!("training-{}", i).into_bytes();
+            occupy(&mut f, &data);
+            f.test_and_add(&data);
+        }
+        let ordered = average(&f);
+        assert!(ordered < 1.5);
+        assert!(ordered < unordered);
+    }
+
+    // Ensures that bits_per_stable_element returns a few bits per element for
+    // a typical configuration.
+    #[test]
+    fn test_bits_per_stable_element() {
+        let f = StableBloomFilter::new_default(10_000, 0.01);
+        let bits = f.bits_per_stable_element();
+        assert!(bits > 1.0 && bits < 32.0);
+
+        // Twice the bits per cell means twice the storage per element.
+        let f = StableBloomFilter::new(10_000, 2, 0.01);
+        assert!(f.bits_per_stable_element() > bits);
+
+        let bf = StableBloomFilter::new_unstable(10_000, 0.01);
+        assert!(bf
+            .bits_per_stable_element()
+            .approx_eq(0.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that adding the 64-bit hash of an element is equivalent to
+    // adding the element itself.
+    #[test]
+    fn test_hash_api() {
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        let mut g = StableBloomFilter::new_unstable(1000, 0.01);
+
+        f.add(b"a");
+        g.add_hash(g.hash64(b"a"));
+        for i in 0..(f.m) {
+            assert_eq!(f.cells.get(i), g.cells.get(i));
+        }
+
+        assert!(g.test(b"a"));
+        assert!(f.test_hash(f.hash64(b"a")));
+        assert!(!f.test_hash(f.hash64(b"b")));
+
+        assert!(!g.test_and_add_hash(g.hash64(b"b")));
+        assert!(g.test_and_add_hash(g.hash64(b"b")));
+        assert!(g.test(b"b"));
+    }
+
+    // Ensures that cell_load counts every key mapping to a cell, so that the
+    // cell shared by two colliding keys has an elevated load.
+    #[test]
+    fn test_cell_load() {
+        let f = StableBloomFilter::new_default(1000, 0.01);
+        let cells_of = |data: &[u8]| {
+            let (lower, upper) = f.hash_kernel(data);
+            (0..(f.k))
+                .map(|i| f.location(lower, upper, i))
+                .collect::<Vec<_>>()
+        };
+
+        // Find a key sharing a cell with `a`.
+        let a = cells_of(b"a");
+        let (other, shared) = (0..)
+            .map(|i: usize| i.to_string())
+            .find_map(|key| {
+                let shared = cells_of(key.as_bytes())
+                    .into_iter()
+                    .find(|i| a.contains(i))?;
+                Some((key, shared))
+            })
+            .unwrap();
+
+        let load = f.cell_load(&[b"a", other.as_bytes(), b"b"]);
+        assert_eq!(load.len(), 1000);
+        assert!(load[shared] >= 2);
+        assert!(load.iter().all(|l| *l <= 3));
+        for i in a.iter() {
+            assert!(load[*i] >= 1);
+        }
+    }
+
+    // Ensures that a filter sized with m_for retains about the requested number
+    // of elements at the requested false-positive rate.
+    #[test]
+    fn test_m_for() {
+        for &d in [1, 2, 4].iter() {
+            let m = StableBloomFilter::m_for(10_000, 0.01, d);
+            let f = StableBloomFilter::new(m, d, 0.01);
+
+            // p is truncated to an integer, which slightly raises the rate.
+            assert!((f.false_positive_rate() - 0.01).abs() < 0.003);
+            assert!((f.equilibrium_cardinality() - 10_000.0).abs() < 1000.0);
+        }
+
+        // Retaining more elements needs more cells.
+        assert!(
+            StableBloomFilter::m_for(20_000, 0.01, 1) > StableBloomFilter::m_for(10_000, 0.01, 1)
+        );
+    }
+
+    // Ensures that fill_ratio, saturation and insert_count reflect the cells
+    // and the number of adds.
+    #[test]
+    fn test_fill_statistics() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        assert!(f.fill_ratio().approx_eq(0.0, (f64::EPSILON, 1)));
+        assert_eq!(f.insert_count(), 0);
+
+        f.cells.set(0, 1);
+        f.cells.set(1, f.max);
+        assert!(f.fill_ratio().approx_eq(0.002, (f64::EPSILON, 1)));
+        assert!(f.saturation().approx_eq(0.001, (f64::EPSILON, 1)));
+
+        f.add(b"a");
+        f.test_and_add(b"b");
+        assert_eq!(f.insert_count(), 2);
+
+        f.reset();
+        assert_eq!(f.insert_count(), 0);
+    }
+
+    // Ensures that stats_json produces an object with the expected keys and
+    // value types.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_stats_json() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        f.add(b"a");
+
+        let stats: serde_json::Value = serde_json::from_str(&f.stats_json()).unwrap();
+        for key in ["m", "k", "p", "d", "insert_count"].iter() {
+            assert!(stats[key].is_u64());
+        }
+        for key in [
+            "target_fp",
+            "false_positive_rate",
+            "fill_ratio",
+            "saturation",
+        ]
+        .iter()
+        {
+            assert!(stats[key].is_f64());
+        }
+        assert_eq!(stats["m"], 1000);
+        assert_eq!(stats["d"], 2);
+        assert_eq!(stats["insert_count"], 1);
+    }
+
+    // Ensures that is_compatible only accepts filters with matching m, k,
+    // bucket size and max.
+    #[test]
+    fn test_is_compatible() {
+        let f = StableBloomFilter::new(1000, 2, 0.01);
+
+        assert!(f.is_compatible(&StableBloomFilter::new(1000, 2, 0.01)));
+        assert!(!f.is_compatible(&StableBloomFilter::new(100, 2, 0.01)));
+        assert!(!f.is_compatible(&StableBloomFilter::new(1000, 1, 0.01)));
+        assert!(!f.is_compatible(&StableBloomFilter::new(1000, 2, 0.0001)));
+        assert_eq!(
+            f.check_compatible(&StableBloomFilter::new(1000, 2, 0.0001)),
+            Err(MergeError::HashFunctionsMismatch { left: 3, right: 7 })
+        );
+    }
+
+    // Ensures that a filter using the p computed by p_for_stable_point settles
+    // at the targeted fraction of zero cells.
+    #[test]
+    fn test_p_for_stable_point() {
+        for &(d, target) in [(1, 0.8), (2, 0.6)].iter() {
+            let mut f = StableBloomFilter::new(10_000, d, 0.1);
+            f.p = StableBloomFilter::p_for_stable_point(f.m, f.k, f.max, target);
+            assert!((f.stable_point() - target).abs() < 0.02);
+
+            for i in 0..200_000 {
+                f.add(i.to_string().as_bytes());
+            }
+
+            assert!((f.zeros_fraction() - target).abs() < 0.03);
+        }
+    }
+
+    // Ensures that false_positive_probability returns 1.0 for data whose cells
+    // are all set and the fill-based estimate otherwise.
+    #[test]
+    fn test_false_positive_probability() {
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        assert!(f
+            .false_positive_probability(b"a")
+            .approx_eq(0.0, (f64::EPSILON, 1)));
+
+        // Setting the cells of `a` makes it a false positive.
+        let (lower, upper) = f.hash_kernel(b"a");
+        for i in 0..(f.k) {
+            let idx = f.location(lower, upper, i);
+            f.cells.set(idx, 1);
+        }
+        assert!(f
+            .false_positive_probability(b"a")
+            .approx_eq(1.0, (f64::EPSILON, 1)));
+
+        let p = f.false_positive_probability(b"b");
+        assert!(p > 0.0 && p < 1.0);
+        assert!(p.approx_eq(f.fill_ratio().powf(f.k as f64), (f64::EPSILON, 1)));
+    }
+
+    // Ensures that a lower fill value shortens retention under an identical
+    // stream of adds.
+    #[test]
+    fn test_fill_value() {
+        let mut full = StableBloomFilter::new(100_000, 2, 0.01);
+        let mut low = StableBloomFilter::new(100_000, 2, 0.01);
+        assert_eq!(full.fill_value(), 3);
+        low.set_fill_value(1);
+        assert_eq!(low.fill_value(), 1);
+
+        for f in [&mut full, &mut low].iter_mut() {
+            for i in 0..200 {
+                f.add(format!("cohort-{}", i).as_bytes());
+            }
+            for i in 0..5000 {
+                f.add(format!("after-{}", i).as_bytes());
+            }
+        }
+
+        let retained = |f: &StableBloomFilter| {
+            (0..200)
+                .filter(|i| f.test(format!("cohort-{}", i).as_bytes()))
+                .count()
+        };
+        assert!(retained(&low) + 40 < retained(&full));
+
+        // The analytics follow the fill value.
+        assert_eq!(low.stable_point(), stable_zeros(low.m, low.k, low.p, 1));
+        assert!(low.stable_point() > full.stable_point());
+        assert!(low.false_positive_rate() < full.false_positive_rate());
+        assert!(low.equilibrium_cardinality() < full.equilibrium_cardinality());
+        assert!(low.retention_half_life() < full.retention_half_life());
+
+        low.set_fill_value(0);
+        assert_eq!(low.fill_value(), 1);
+        low.set_fill_value(100);
+        assert_eq!(low.fill_value(), 3);
+    }
+
+    // Ensures that hash_indices returns the cells set by add, derived from
+    // the FNV-1a hash of the data.
+    #[test]
+    fn test_hash_indices() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        f.set_eviction_enabled(false);
+        assert_eq!(f.k(), 3);
+
+        // FNV-1a of "a" is 0xaf63dc4c8601ec8c.
+        let indices: Vec<usize> = f.hash_indices(b"a").collect();
+        assert_eq!(indices, vec![36, 297, 558]);
+
+        f.add(b"a");
+        let set: Vec<usize> = (0..1000).filter(|i| f.cell_value(*i) != 0).collect();
+        assert_eq!(set, indices);
+    }
+
+    // Ensures that items added at a lower level are evicted sooner than
+    // items added at the maximum level, and that levels never lower cells.
+    #[test]
+    fn test_add_with_level() {
+        let mut f = StableBloomFilter::new_with_seed(100_000, 3, 0.01, 7);
+        for i in 0..200 {
+            f.add_with_level(format!("low-{}", i).as_bytes(), 1);
+            f.add_with_level(format!("high-{}", i).as_bytes(), 100);
+        }
+        for i in 0..5000 {
+            f.add(format!("after-{}", i).as_bytes());
+        }
+
+        let retained = |prefix: &str| {
+            (0..200)
+                .filter(|i| f.test(format!("{}-{}", prefix, i).as_bytes()))
+                .count()
+        };
+        assert!(retained("low") + 40 < retained("high"));
+
+        let mut g = StableBloomFilter::new(1000, 3, 0.01);
+        g.set_eviction_enabled(false);
+        g.add(b"a");
+        g.add_with_level(b"a", 0);
+        let (lower, upper) = split_hash(g.hash64(b"a"));
+        for i in 0..g.k() {
+            assert_eq!(g.cell_value(g.location(lower, upper, i)), 7);
+        }
+
+        // A plain add with a lower fill value doesn't lower the cells either.
+        g.set_fill_value(2);
+        g.add(b"a");
+        for i in 0..g.k() {
+            assert_eq!(g.cell_value(g.location(lower, upper, i)), 7);
+        }
+        g.add(b"b");
+        let (lower, upper) = split_hash(g.hash64(b"b"));
+        assert!((0..g.k()).any(|i| g.cell_value(g.location(lower, upper, i)) == 2));
+    }
+
+    // Ensures that test_and_add doesn't lower the cells of an item added at a
+    // higher level than the fill value.
+    #[test]
+    fn test_test_and_add_after_add_with_level() {
+        let mut f = StableBloomFilter::new(1000, 3, 0.01);
+        f.set_eviction_enabled(false);
+        f.set_fill_value(2);
+        f.add_with_level(b"a", 7);
+        assert!(f.test_and_add(b"a"));
+
+        let (lower, upper) = split_hash(f.hash64(b"a"));
+        for i in 0..f.k() {
+            assert_eq!(f.cell_value(f.location(lower, upper, i)), 7);
+        }
+        assert!(!f.test_and_add(b"b"));
+        let (lower, upper) = split_hash(f.hash64(b"b"));
+        assert!((0..f.k()).any(|i| f.cell_value(f.location(lower, upper, i)) == 2));
+    }
+
+    // Ensures that union_cardinality estimates the number of distinct elements
+    // across two filters with overlapping contents.
+    #[test]
+    fn test_union_cardinality() {
+        let mut a = StableBloomFilter::new_unstable(20_000, 0.01);
+        let mut b = StableBloomFilter::new_unstable(20_000, 0.01);
+        for i in 0..600 {
+            a.add(i.to_string().as_bytes());
+        }
+        for i in 400..1000 {
+            b.add(i.to_string().as_bytes());
+        }
+
+        let union = a.union_cardinality(&b).unwrap();
+        assert!((union - 1000.0).abs() < 100.0);
+        assert!(a
+            .union_cardinality(&b)
+            .unwrap()
+            .approx_eq(b.union_cardinality(&a).unwrap(), (f64::EPSILON, 1)));
+
+        let other = StableBloomFilter::new_unstable(10_000, 0.01);
+        assert!(a.union_cardinality(&other).is_err());
+    }
+
+    // Ensures that overload protection brings the fill ratio of an overloaded
+    // filter back toward the stable point faster than regular eviction.
+    #[test]
+    fn test_overload_protection() {
+        let mut protected = StableBloomFilter::new_default(100_000, 0.01);
+        let mut unprotected = StableBloomFilter::new_default(100_000, 0.01);
+        protected.enable_overload_protection(0.5, 500);
+        assert!(!protected.is_overloaded());
+
+        for f in [&mut protected, &mut unprotected].iter_mut() {
+            for i in 0..(f.m) {
+                f.cells.set(i, f.max);
+            }
+            for i in 0..10_000 {
+                f.add(i.to_string().as_bytes());
+            }
+        }
+
+        let stable_fill = 1.0 - protected.stable_point();
+        assert!(protected.fill_ratio() < 0.5);
+        assert!(protected.fill_ratio() < unprotected.fill_ratio());
+        assert!((protected.fill_ratio() - stable_fill).abs() < 0.1);
+    }
+
+    // Ensures that shard_for routes a key to the same shard in independently
+    // constructed filters and in a sharded filter.
+    #[test]
+    fn test_shard_for() {
         let f = StableBloomFilter::new_default(1000, 0.01);
-        let fps = round(f.false_positive_rate(), 0.5, 2);
+        let g = StableBloomFilter::new(2000, 2, 0.001);
+        let sharded = ShardedStableBloomFilter::new(8, 8000, 2, 0.01);
 
-        assert!(fps.approx_eq(0.01, (f64::EPSILON, 1)));
+        let mut shards = [0; 8];
+        for i in 0..1000 {
+            let data = i.to_string();
+            let shard = f.shard_for(data.as_bytes(), 8);
+            assert_eq!(shard, g.shard_for(data.as_bytes(), 8));
+            assert_eq!(shard, sharded.shard_of(data.as_bytes()));
+            shards[shard] += 1;
+        }
+        assert!(shards.iter().all(|n| *n > 0));
+    }
 
-        // Classic Bloom filters have an unbounded rate of false positives. Once
-        // they become full, every query returns a false positive.
-        let bf = StableBloomFilter::new_unstable(1000, 0.1);
-        assert!(bf.false_positive_rate().approx_eq(1.0, (f64::EPSILON, 1)));
+    // Ensures that shard_for panics when there are no shards.
+    #[test]
+    #[should_panic(expected = "num_shards must be positive")]
+    fn test_shard_for_no_shards() {
+        let f = StableBloomFilter::new_default(1000, 0.01);
+        f.shard_for(b"a", 0);
     }
 
-    // Ensures that Reset sets every cell to zero.
+    // Ensures that apply_time_decay runs one decrement iteration per
+    // 1/rate_per_sec seconds and carries fractions of an iteration over.
     #[test]
-    fn test_reset() {
-        let mut f = StableBloomFilter::new_default(1000, 0.01);
+    fn test_apply_time_decay() {
+        let mut f = StableBloomFilter::new(100, 8, 0.01);
+        f.p = 1;
+        for i in 0..(f.m) {
+            f.cells.set(i, f.max);
+        }
+        let decrements = |f: &StableBloomFilter| {
+            (0..(f.m))
+                .map(|i| usize::from(f.max - f.cells.get(i)))
+                .sum::<usize>()
+        };
+
+        assert_eq!(f.apply_time_decay(Duration::from_secs(2), 10.0), 20);
+        assert_eq!(decrements(&f), 20);
+
+        assert_eq!(f.apply_time_decay(Duration::from_millis(250), 2.0), 0);
+        assert_eq!(f.apply_time_decay(Duration::from_millis(250), 2.0), 1);
+        assert_eq!(decrements(&f), 21);
+
+        // Decay beyond max·m/p iterations clears the cells without running
+        // them one by one.
+        assert_eq!(f.apply_time_decay(Duration::MAX, 1e9), usize::MAX);
+        assert_eq!(decrements(&f), 100 * usize::from(f.max));
+        assert_eq!(f.apply_time_decay(Duration::from_millis(250), 2.0), 0);
+    }
+
+    // Ensures that apply_time_decay leaves classic filters, which don't
+    // decrement any cells, unchanged however much time has elapsed.
+    #[test]
+    fn test_apply_time_decay_classic() {
+        let mut f = StableBloomFilter::new(100, 8, 0.01);
+        f.p = 0;
+        f.add(b"a");
+        let cells = f.cells.clone();
+
+        assert_eq!(f.apply_time_decay(Duration::from_secs(2), 10.0), 0);
+        assert_eq!(f.apply_time_decay(Duration::MAX, 1e9), 0);
+        assert_eq!(f.cells, cells);
+        assert!(f.test(b"a"));
+    }
+
+    // Ensures that apply_time_decay rejects negative and non-finite rates.
+    #[test]
+    fn test_apply_time_decay_invalid_rate() {
+        for rate in [-1.0, f64::NAN, f64::INFINITY].iter() {
+            let result = std::panic::catch_unwind(|| {
+                let mut f = StableBloomFilter::new(100, 8, 0.01);
+                f.apply_time_decay(Duration::from_secs(1), *rate);
+            });
+            assert!(result.is_err());
+        }
+    }
+
+    // Ensures that refreshing a key through its handle keeps it alive across
+    // many adds.
+    #[test]
+    fn test_refresh() {
+        // With 4-bit cells a key is only evicted after 15 decrements, which
+        // won't happen between two refreshes.
+        let mut f = StableBloomFilter::new(10_000, 4, 0.01);
+        let handle = f.handle(b"a");
+        assert_eq!(handle, f.handle(b"a"));
+
+        f.add(b"a");
+        for i in 0..20_000 {
+            if i % 50 == 0 {
+                f.refresh(&handle);
+            }
+            f.add(i.to_string().as_bytes());
+        }
+
+        assert!(f.test(b"a"));
+    }
+
+    // Ensures that delta_size_estimate matches the size of the delta returned
+    // by cell_delta for the same pair of filters.
+    #[test]
+    fn test_delta_size_estimate() {
+        let base = StableBloomFilter::new(1000, 2, 0.01);
+        let mut current = StableBloomFilter::new(1000, 2, 0.01);
+        assert_eq!(current.delta_size_estimate(&base), Ok(0));
+
+        for i in 0..10 {
+            current.add(i.to_string().as_bytes());
+        }
+
+        let delta = current.cell_delta(&base).unwrap();
+        assert_eq!(
+            current.delta_size_estimate(&base),
+            Ok(delta.len() * DELTA_ENTRY_SIZE)
+        );
+        assert!(current
+            .delta_size_estimate(&StableBloomFilter::new(100, 2, 0.01))
+            .is_err());
+    }
+
+    // Ensures that no elements are evicted while eviction is disabled and that
+    // eviction resumes once it is enabled again.
+    #[test]
+    fn test_set_eviction_enabled() {
+        let mut f = StableBloomFilter::new_default(10_000, 0.01);
+        assert!(f.is_eviction_enabled());
 
+        f.set_eviction_enabled(false);
+        assert!(!f.is_eviction_enabled());
+        for i in 0..1000 {
+            f.add(i.to_string().as_bytes());
+        }
         for i in 0..1000 {
+            assert!(f.test(i.to_string().as_bytes()));
+        }
+
+        f.set_eviction_enabled(true);
+        for i in 1000..50_000 {
+            f.add(i.to_string().as_bytes());
+        }
+        let retained = (0..1000)
+            .filter(|i| f.test(i.to_string().as_bytes()))
+            .count();
+        assert!(retained < 100);
+    }
+
+    // Ensures that avalanche_score falls in a plausible range for the FNV
+    // hash kernel.
+    #[test]
+    fn test_avalanche_score() {
+        let f = StableBloomFilter::new_default(1000, 0.01);
+        let keys: Vec<String> = (0..100).map(|i| format!("key-{}", i)).collect();
+        let sample: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+
+        let score = f.avalanche_score(&sample);
+        assert!(score > 0.1 && score < 0.6);
+        assert!(f.avalanche_score(&[b""]).approx_eq(0.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that empty_like returns an empty filter compatible with the
+    // original.
+    #[test]
+    fn test_empty_like() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        f.set_fill_value(2);
+        f.set_eviction_enabled(false);
+        f.add(b"a");
+
+        let g = f.empty_like();
+        assert!(g.is_compatible(&f));
+        assert_eq!(g.p(), f.p());
+        assert_eq!(g.max(), f.max());
+        assert_eq!(g.fill_value(), 2);
+        assert!(!g.is_eviction_enabled());
+        assert_eq!(g.hash64(b"a"), f.hash64(b"a"));
+        assert_eq!(g.insert_count(), 0);
+        assert!(g.to_sparse().is_empty());
+    }
+
+    // Ensures that add_all is equivalent to adding every item in order.
+    #[test]
+    fn test_add_all() {
+        let items: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+
+        let mut f = StableBloomFilter::new_with_seed(10_000, 2, 0.01, 3);
+        for item in items.iter() {
+            f.add(item.as_bytes());
+        }
+
+        let mut batched = StableBloomFilter::new_with_seed(10_000, 2, 0.01, 3);
+        batched.add_all(items.iter());
+
+        assert_eq!(batched.insert_count(), 1000);
+        assert!((0..(f.m)).all(|i| f.cells.get(i) == batched.cells.get(i)));
+    }
+
+    // Ensures that from_iter_sized adds every key yielded by the iterator.
+    #[test]
+    fn test_from_iter_sized() {
+        let keys: Vec<Vec<u8>> = (0..20).map(|i: i32| i.to_string().into_bytes()).collect();
+
+        let f = StableBloomFilter::from_iter_sized(10_000_000, 0.01, keys.clone());
+        assert_eq!(f.insert_count(), 20);
+        for key in keys.iter() {
+            assert!(f.test(key));
+        }
+    }
+
+    // Ensures that expected_decrements_per_cell returns inserts * p / m.
+    #[test]
+    fn test_expected_decrements_per_cell() {
+        let f = StableBloomFilter::new(10_000, 3, 0.01);
+        let expected = 20_000.0 * (f.p() as f64) / 10_000.0;
+        assert!(f
+            .expected_decrements_per_cell(20_000)
+            .approx_eq(expected, (f64::EPSILON, 1)));
+        assert!(f
+            .expected_decrements_per_cell(0)
+            .approx_eq(0.0, (f64::EPSILON, 1)));
+
+        let bf = StableBloomFilter::new_unstable(10_000, 0.01);
+        assert!(bf
+            .expected_decrements_per_cell(20_000)
+            .approx_eq(0.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that from_bytes restores the cells from their data and rejects
+    // data whose length doesn't match the parameters.
+    #[test]
+    fn test_from_bytes() {
+        let full = StableBloomFilter::from_bytes(&[0xff; 250], 1000, 2, 0.01).unwrap();
+        assert!(full.test(b"a"));
+        for i in 0..(full.m) {
+            assert_eq!(full.cell_value(i), 3);
+        }
+
+        let empty = StableBloomFilter::from_bytes(&[0; 250], 1000, 2, 0.01).unwrap();
+        assert!(!empty.test(b"a"));
+        let expected = StableBloomFilter::new(1000, 2, 0.01);
+        assert_eq!((empty.k(), empty.p()), (expected.k(), expected.p()));
+        assert_eq!(empty.hash_scheme(), expected.hash_scheme());
+
+        let err = StableBloomFilter::from_bytes(&[0; 10], 1000, 2, 0.01).err();
+        assert_eq!(
+            err,
+            Some(SbfError::DataLength {
+                expected: expected_byte_len(1000, 2),
+                actual: 10
+            })
+        );
+        assert!(StableBloomFilter::from_bytes(&[0; 251], 1000, 2, 0.01).is_err());
+    }
+
+    // Ensures that combined_false_positive_rate returns the rate of a lookup
+    // matching in any of the filters.
+    #[test]
+    fn test_combined_false_positive_rate() {
+        let a = StableBloomFilter::new(10_000, 1, 0.01);
+        let b = StableBloomFilter::new(20_000, 2, 0.05);
+        let (fpr_a, fpr_b) = (a.false_positive_rate(), b.false_positive_rate());
+
+        let expected = 1.0 - (1.0 - fpr_a) * (1.0 - fpr_b);
+        let combined = combined_false_positive_rate(&[&a, &b]);
+        assert!(combined.approx_eq(expected, (f64::EPSILON, 1)));
+        assert!(combined > fpr_a.max(fpr_b));
+        assert!(combined < fpr_a + fpr_b);
+
+        assert!(combined_false_positive_rate(&[&a]).approx_eq(fpr_a, (f64::EPSILON, 1)));
+        assert!(combined_false_positive_rate(&[] as &[&StableBloomFilter])
+            .approx_eq(0.0, (f64::EPSILON, 1)));
+    }
+
+    // Ensures that the fill history keeps the most recent fill ratios, taken
+    // every interval adds, which level off at the stable point.
+    #[test]
+    fn test_fill_history() {
+        let mut f = StableBloomFilter::new(1000, 2, 0.01);
+        assert_eq!(f.fill_history().count(), 0);
+
+        f.enable_fill_history(5, 1000);
+        for i in 0..2500 {
+            f.add(i.to_string().as_bytes());
+        }
+        let history: Vec<f64> = f.fill_history().collect();
+        assert_eq!(history.len(), 2);
+        assert!(history[0] > 0.0);
+
+        for i in 2500..100_000 {
             f.add(i.to_string().as_bytes());
         }
+        let history: Vec<f64> = f.fill_history().collect();
+        assert_eq!(history.len(), 5);
+        let expected = 1.0 - f.stable_point();
+        for fill in history.iter() {
+            assert!((fill - expected).abs() < 0.1);
+        }
 
         f.reset();
+        assert_eq!(f.fill_history().count(), 0);
+
+        f.enable_fill_history(3, 10);
+        for i in 0..25 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert_eq!(f.fill_history().count(), 2);
+    }
+
+    // Ensures that a filter using the p returned by p_for_fp_bound keeps its
+    // false-positive rate under the bound once it has become stable.
+    #[test]
+    fn test_p_for_fp_bound() {
+        for &(d, bound) in [(1, 0.05), (1, 0.1), (2, 0.04)].iter() {
+            let mut f = StableBloomFilter::new(100_000, d, 0.01);
+            let p = StableBloomFilter::p_for_fp_bound(f.m, f.k, f.max, bound);
+            f.p = p;
+            assert!(f.false_positive_rate() <= bound);
+            f.p = p - 1;
+            assert!(p == 1 || f.false_positive_rate() > bound);
+            f.p = p;
+
+            for i in 0..500_000 {
+                f.add(i.to_string().as_bytes());
+            }
+
+            let false_positives = (500_000..600_000)
+                .filter(|i: &i32| f.test(i.to_string().as_bytes()))
+                .count();
+            let actual = (false_positives as f64) / 100_000.0;
+            assert!(actual <= bound);
+        }
+    }
+
+    // Ensures that membership_status tells present, partially evicted and
+    // absent keys apart.
+    #[test]
+    fn test_membership_status() {
+        let mut f = StableBloomFilter::new(10_000, 3, 0.01);
+        assert!(f.k() > 1);
+        assert_eq!(f.membership_status(b"a"), MembershipStatus::Absent);
+
+        f.add(b"a");
+        assert_eq!(f.membership_status(b"a"), MembershipStatus::Present);
+
+        // Evict the key from its first cell only.
+        let (lower, upper) = split_hash(f.hash64(b"a"));
+        let first = f.location(lower, upper, 0);
+        for _ in 0..(f.max()) {
+            f.cells.decrease(first, 1);
+        }
+        assert!(!f.test(b"a"));
+        assert_eq!(f.membership_status(b"a"), MembershipStatus::LikelyEvicted);
+
+        f.suppress(b"a");
+        assert_eq!(f.membership_status(b"a"), MembershipStatus::Absent);
+    }
+
+    // Ensures that equilibrium_cardinality grows with m and shrinks as p
+    // grows.
+    #[test]
+    fn test_equilibrium_cardinality() {
+        let mut f = StableBloomFilter::new(10_000, 1, 0.01);
+        let n = f.equilibrium_cardinality();
+        assert!(n > 0.0 && n.is_finite());
+
+        let larger = StableBloomFilter::new(100_000, 1, 0.01);
+        assert!(larger.equilibrium_cardinality() > n);
+
+        f.p *= 2;
+        assert!(f.equilibrium_cardinality() < n);
+
+        let bf = StableBloomFilter::new_unstable(10_000, 0.01);
+        assert!(bf.equilibrium_cardinality().is_infinite());
+    }
+
+    // Ensures that remove zeroes the cells of the data, so it tests negative,
+    // without touching unrelated cells.
+    #[test]
+    fn test_remove() {
+        let mut f = StableBloomFilter::new(10_000, 2, 0.01);
+        f.set_eviction_enabled(false);
+        f.add(b"a");
+        f.add(b"b");
+
+        f.remove(b"a");
+        assert!(!f.test(b"a"));
+        assert_eq!(f.membership_status(b"a"), MembershipStatus::Absent);
+        assert_eq!(f.fill_ratio(), (f.k() as f64) / 10_000.0);
+
+        f.remove(b"c");
+        assert!(f.test(b"b"));
+    }
+
+    // Ensures that storage_vs_classic reports the sizes of filters built with
+    // the crate's sizing helpers.
+    #[test]
+    fn test_storage_vs_classic() {
+        let (stable, classic) = StableBloomFilter::storage_vs_classic(10_000, 0.01);
+        assert!(stable > 0);
+        assert!(classic > 0);
+
+        let m = StableBloomFilter::m_for(10_000, 0.01, 1);
+        assert_eq!(stable, expected_byte_len(m, 1));
+        // A classic filter needs about 9.6 bits per element at 1%.
+        assert!((classic as f64 - 10_000.0 * 9.585 / 8.0).abs() < 2.0);
+
+        let (more_stable, more_classic) = StableBloomFilter::storage_vs_classic(20_000, 0.01);
+        assert!(more_stable > stable);
+        assert!(more_classic > classic);
+    }
 
+    // Ensures that a filter restored from its serialized state keeps its
+    // parameters, cells and memberships.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut f = StableBloomFilter::new(1000, 3, 0.01);
+        f.set_eviction_enabled(false);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let json = serde_json::to_string(&f).unwrap();
+        let mut restored: StableBloomFilter = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.cells(), f.cells());
+        assert_eq!(restored.k(), f.k());
+        assert_eq!(restored.p(), f.p());
+        assert_eq!(restored.d(), f.d());
+        assert_eq!(restored.insert_count(), f.insert_count());
+        assert!(!restored.is_eviction_enabled());
         for i in 0..(f.m) {
-            assert_eq!(f.cells.get(i), 0);
+            assert_eq!(restored.cell_value(i), f.cell_value(i));
+        }
+        for i in 0..50 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+
+        restored.add(b"a");
+        assert!(restored.test(b"a"));
+    }
+
+    // Ensures that serialized filters whose fields contradict each other are
+    // rejected instead of being restored.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_invalid_state() {
+        let mut f = StableBloomFilter::new(100, 2, 0.01);
+        f.enable_fill_history(3, 10);
+        let state = serde_json::to_value(&f).unwrap();
+        let k = f.k();
+
+        let cases: Vec<(&str, serde_json::Value)> = vec![
+            ("m", 0.into()),
+            ("m", 101.into()),
+            ("max", 1.into()),
+            ("k", 0.into()),
+            ("index_buffer", vec![0; k + 1].into()),
+            ("probe_order", vec![0; k].into()),
+            ("probe_order", (1..=k).collect::<Vec<_>>().into()),
+            ("fill_value", 4.into()),
+            ("decay_residual", 1.5.into()),
+            (
+                "fill_history",
+                serde_json::json!({ "samples": [], "capacity": 3, "interval": 0 }),
+            ),
+        ];
+        for (field, value) in cases {
+            let mut bad = state.clone();
+            bad[field] = value;
+            let restored: Result<StableBloomFilter, _> = serde_json::from_value(bad);
+            assert!(restored.is_err(), "{} wasn't validated", field);
+        }
+
+        let cells_cases: Vec<(&str, serde_json::Value)> =
+            vec![("data", vec![0u8; 24].into()), ("max", 1.into())];
+        for (field, value) in cells_cases {
+            let mut bad = state.clone();
+            bad["cells"][field] = value;
+            let restored: Result<StableBloomFilter, _> = serde_json::from_value(bad);
+            assert!(restored.is_err(), "cells.{} wasn't validated", field);
+        }
+
+        let restored: Result<StableBloomFilter, _> = serde_json::from_value(state);
+        assert!(restored.is_ok());
+    }
+
+    // Ensures that a filter built with a custom hasher hashes data with it
+    // and otherwise behaves like the default filter.
+    #[test]
+    fn test_with_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+        let builder = BuildHasherDefault::<DefaultHasher>::default();
+        let mut f = StableBloomFilter::with_hasher(10_000, 2, 0.01, builder.clone());
+        let default = StableBloomFilter::new(10_000, 2, 0.01);
+        assert_eq!(f.k(), default.k());
+        assert_eq!(f.p(), default.p());
+
+        let mut hasher = builder.build_hasher();
+        hasher.write(b"a");
+        assert_eq!(f.hash64(b"a"), hasher.finish());
+        assert_ne!(f.hash64(b"a"), default.hash64(b"a"));
+
+        f.add(b"a");
+        assert!(f.test(b"a"));
+        assert!(!f.test(b"b"));
+        assert!(f.view().test(b"a"));
+        assert!(f.empty_like().is_compatible(&f));
+
+        let mut bf = StableBloomFilter::unstable_with_hasher(1000, 0.01, builder);
+        assert_eq!(bf.p(), 0);
+        bf.add(b"a");
+        assert!(bf.test(b"a"));
+    }
+
+    // Ensures that any hashable value can be added and tested without
+    // serializing it to bytes first.
+    #[test]
+    fn test_hash_filter() {
+        use crate::HashFilter;
+        use std::net::Ipv4Addr;
+
+        let mut f = StableBloomFilter::new_unstable(10_000, 0.01);
+        let item = (42u64, Ipv4Addr::new(10, 0, 0, 1));
+        assert!(!f.contains(&item));
+
+        f.insert(&item);
+        assert!(f.contains(&item));
+        assert!(!f.contains(&(42u64, Ipv4Addr::new(10, 0, 0, 2))));
+        assert!(!f.contains(&(43u64, Ipv4Addr::new(10, 0, 0, 1))));
+
+        f.insert("a");
+        assert!(f.contains("a"));
+        assert_eq!(f.insert_count(), 2);
+    }
+
+    // Ensures that a filter over memory-mapped cells keeps its elements
+    // across reopening the file.
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_open_mmap() {
+        let path = std::env::temp_dir().join(format!("sbf-stable-{}.mmap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut f = StableBloomFilter::open_mmap(&path, 10_000, 4, 0.01).unwrap();
+            let expected = StableBloomFilter::new(10_000, 4, 0.01);
+            assert_eq!(f.k(), expected.k());
+            assert_eq!(f.p(), expected.p());
+            f.add(b"a");
+            f.flush().unwrap();
+        }
+
+        let f = StableBloomFilter::open_mmap(&path, 10_000, 4, 0.01).unwrap();
+        assert!(f.test(b"a"));
+        assert!(!f.test(b"b"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Ensures that a merged filter contains the elements of both filters and
+    // that incompatible filters are rejected.
+    #[test]
+    fn test_merge() {
+        let mut a = StableBloomFilter::new(10_000, 2, 0.01);
+        let mut b = StableBloomFilter::new(10_000, 2, 0.01);
+        a.set_eviction_enabled(false);
+        b.set_eviction_enabled(false);
+        a.add(b"a");
+        b.add(b"b");
+
+        a.merge(&b).unwrap();
+        assert!(a.test(b"a"));
+        assert!(a.test(b"b"));
+        assert!(!a.test(b"c"));
+        assert_eq!(a.insert_count(), 2);
+        for i in 0..(b.m) {
+            assert!(a.cell_value(i) >= b.cell_value(i));
+        }
+
+        let mut other = StableBloomFilter::new(20_000, 2, 0.01);
+        assert_eq!(
+            other.merge(&a),
+            Err(MergeError::CellsMismatch {
+                left: 20_000,
+                right: 10_000
+            })
+        );
+
+        let mut seeded = StableBloomFilter::with_hasher(
+            10_000,
+            2,
+            0.01,
+            crate::fnv::FnvBuildHasher::with_key(1),
+        );
+        let unseeded =
+            StableBloomFilter::with_hasher(10_000, 2, 0.01, crate::fnv::FnvBuildHasher::default());
+        assert_eq!(seeded.merge(&unseeded), Err(MergeError::HashMismatch));
+    }
+
+    // Ensures that the intersection of classic filters contains the elements
+    // added to both of them only.
+    #[test]
+    fn test_intersect() {
+        let mut a = StableBloomFilter::new_unstable(10_000, 0.01);
+        let mut b = StableBloomFilter::new_unstable(10_000, 0.01);
+        a.add(b"both");
+        b.add(b"both");
+        a.add(b"only-a");
+        b.add(b"only-b");
+
+        let both = a.intersect(&b).unwrap();
+        assert!(both.test(b"both"));
+        assert!(!both.test(b"only-a"));
+        assert!(!both.test(b"only-b"));
+        for i in 0..(a.m) {
+            assert_eq!(both.cell_value(i), a.cell_value(i) & b.cell_value(i));
+        }
+
+        let c = StableBloomFilter::new_unstable(10_000, 0.1);
+        assert!(matches!(
+            a.intersect(&c),
+            Err(MergeError::HashFunctionsMismatch { .. })
+        ));
+    }
+
+    // Ensures that the fallible constructors return errors for invalid
+    // parameters instead of panicking or computing meaningless parameters.
+    #[test]
+    fn test_try_new() {
+        let f = StableBloomFilter::try_new(10_000, 3, 0.01).unwrap();
+        assert_eq!(f.k(), StableBloomFilter::new(10_000, 3, 0.01).k());
+        assert_eq!(f.p(), StableBloomFilter::new(10_000, 3, 0.01).p());
+        assert_eq!(StableBloomFilter::try_new_default(100, 0.1).unwrap().d(), 1);
+        assert_eq!(
+            StableBloomFilter::try_new_unstable(100, 0.1).unwrap().p(),
+            0
+        );
+
+        assert_eq!(
+            StableBloomFilter::try_new(0, 1, 0.01).err(),
+            Some(SbfError::ZeroCells)
+        );
+        assert_eq!(
+            StableBloomFilter::try_new(100, 9, 0.01).err(),
+            Some(SbfError::InvalidBucketSize(9))
+        );
+        assert_eq!(
+            StableBloomFilter::try_new_default(100, 0.0).err(),
+            Some(SbfError::InvalidFpRate(0.0))
+        );
+        assert!(StableBloomFilter::try_new_unstable(100, f64::NAN).is_err());
+    }
+
+    // Ensures that the fractions of zero, non-zero and saturated cells
+    // account for the cell values.
+    #[test]
+    fn test_zeros_fraction() {
+        let mut f = StableBloomFilter::new(100, 2, 0.01);
+        assert_eq!(f.zeros_fraction(), 1.0);
+        assert_eq!(f.saturation(), 0.0);
+
+        f.cells.set(0, 1);
+        f.cells.set(1, f.max);
+        f.cells.set(2, f.max);
+        assert!((f.zeros_fraction() - 0.97).abs() < 1e-12);
+        assert!((f.fill_ratio() - 0.03).abs() < 1e-12);
+        assert!((f.saturation() - 0.02).abs() < 1e-12);
+    }
+
+    // Ensures that the estimated count is close to the number of distinct
+    // elements added to a classic filter, and that it levels off at the
+    // equilibrium cardinality for stable filters.
+    #[test]
+    fn test_estimated_count() {
+        let mut bf = StableBloomFilter::new_unstable(100_000, 0.01);
+        assert_eq!(bf.estimated_count(), 0.0);
+        for i in 0..5000 {
+            bf.add(i.to_string().as_bytes());
+            bf.add(i.to_string().as_bytes());
+        }
+        assert!((bf.estimated_count() - 5000.0).abs() < 250.0);
+
+        let mut f = StableBloomFilter::new(10_000, 2, 0.01);
+        for i in 0..1_000_000 {
+            f.add(i.to_string().as_bytes());
+        }
+        let equilibrium = f.equilibrium_cardinality();
+        assert!((f.estimated_count() - equilibrium).abs() < 0.1 * equilibrium);
+    }
+
+    // Ensures that test_batch reports the membership of every item in order.
+    #[test]
+    fn test_test_batch() {
+        let mut f = StableBloomFilter::new_unstable(10_000, 0.01);
+        f.add_all(["a", "b"].iter());
+        assert_eq!(
+            f.test_batch(["a", "x", "b"].iter()),
+            vec![true, false, true]
+        );
+        assert!(f.test_batch(Vec::<Vec<u8>>::new()).is_empty());
+    }
+
+    // Ensures that every decrement decreases exactly p cells, including when
+    // the decremented cells wrap around the end of the filter.
+    #[test]
+    fn test_decrement_wraps_around() {
+        let mut f = StableBloomFilter::new(10, 8, 0.01);
+        f.p = 7;
+        for i in 0..10 {
+            f.cells.set(i, f.max);
+        }
+        for _ in 0..100 {
+            f.decrement();
+        }
+
+        let decremented: usize = (0..10).map(|i| usize::from(f.max - f.cells.get(i))).sum();
+        assert_eq!(decremented, 700);
+    }
+
+    // Ensures that filters with the same seed decrement the same cells.
+    #[test]
+    fn test_new_with_seed() {
+        let mut a = StableBloomFilter::new_with_seed(1000, 2, 0.01, 7);
+        let mut b = StableBloomFilter::new_with_seed(1000, 2, 0.01, 7);
+        let mut c = StableBloomFilter::new_with_seed(1000, 2, 0.01, 8);
+        for i in 0..10_000 {
+            let data = i.to_string();
+            a.add(data.as_bytes());
+            b.test_and_add(data.as_bytes());
+            c.add(data.as_bytes());
+        }
+
+        assert!((0..1000).all(|i| a.cells.get(i) == b.cells.get(i)));
+        assert!((0..1000).any(|i| a.cells.get(i) != c.cells.get(i)));
+    }
+
+    // Ensures that Debug and Display print the parameters and statistics of
+    // the filter.
+    #[test]
+    fn test_debug_and_display() {
+        let mut f = StableBloomFilter::new_unstable(100, 0.5);
+        f.add(b"a");
+
+        assert_eq!(
+            format!("{:?}", f),
+            "StableBloomFilter { m: 100, k: 1, p: 0, d: 1, stable_point: 0.0, \
+             false_positive_rate: 1.0, fill_ratio: 0.01 }"
+        );
+        assert_eq!(
+            f.to_string(),
+            "StableBloomFilter(m=100, k=1, p=0, d=1, fp<=1.0000, fill=0.0100)"
+        );
+        assert_eq!(
+            format!("{:?}", f.cells),
+            "Buckets { count: 100, bucket_size: 1, max: 1 }"
+        );
+    }
+
+    // Ensures that a clone is equal to the filter and evolves independently.
+    #[test]
+    fn test_clone_and_eq() {
+        let mut f = StableBloomFilter::new_with_seed(1000, 2, 0.01, 1);
+        for i in 0..100 {
+            f.add(i.to_string().as_bytes());
         }
+
+        let mut snapshot = f.clone();
+        assert!(snapshot == f);
+        assert_eq!(snapshot.insert_count(), 100);
+        // The generator is cloned too, so both replay identically.
+        f.add(b"x");
+        assert!(snapshot != f);
+        snapshot.add(b"x");
+        assert!(snapshot == f);
+
+        assert!(StableBloomFilter::new(1000, 2, 0.01) != StableBloomFilter::new(1000, 3, 0.01));
+        #[cfg(feature = "siphash")]
+        assert!(
+            StableBloomFilter::new_keyed(1000, 1, 0.01, [1; 16])
+                != StableBloomFilter::new_keyed(1000, 1, 0.01, [2; 16])
+        );
+    }
+
+    // Ensures that enhanced double hashing spreads the probes of a hash whose
+    // upper half is a multiple of m, which plain double hashing maps to a
+    // single cell.
+    #[test]
+    fn test_enhanced_location_degenerate_upper() {
+        let m = 1024;
+        let plain: Vec<usize> = (0..8).map(|i| (7 + 2048 * i) % m).collect();
+        assert!(plain.iter().all(|index| *index == 7));
+
+        let mut enhanced: Vec<usize> = (0..8).map(|i| enhanced_location(7, 2048, i, m)).collect();
+        enhanced.sort_unstable();
+        enhanced.dedup();
+        assert_eq!(enhanced.len(), 8);
     }
 }
@@ -0,0 +1,70 @@
+use super::StableBloomFilter;
+use crate::sharded::lock;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+
+impl<S: BuildHasher + Send + 'static> StableBloomFilter<S> {
+    /// Spawns a Tokio task which decrements cells_per_tick random adjacent
+    /// cells of the filter every interval, independently of adds, so that
+    /// stale elements keep expiring when writes stop. The task only holds a
+    /// weak reference to the filter and ends once the filter is dropped, or
+    /// when the returned handle is aborted. Must be called from within a
+    /// Tokio runtime with the timer enabled.
+    pub fn spawn_decay(
+        filter: &Arc<Mutex<Self>>,
+        interval: Duration,
+        cells_per_tick: usize,
+    ) -> JoinHandle<()> {
+        let filter = Arc::downgrade(filter);
+
+        tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes immediately.
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                match filter.upgrade() {
+                    Some(filter) => lock(&filter).decrement_cells(cells_per_tick),
+                    None => return,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // Ensures that the decay task evicts elements without any adds and ends
+    // once the filter is dropped.
+    #[test]
+    fn test_spawn_decay() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut f = StableBloomFilter::new(100, 1, 0.01);
+            f.set_eviction_enabled(false);
+            f.add(b"a");
+            let f = Arc::new(Mutex::new(f));
+
+            let task = StableBloomFilter::spawn_decay(&f, Duration::from_millis(1), 100);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!f.lock().unwrap().test(b"a"));
+            assert_eq!(f.lock().unwrap().fill_ratio(), 0.0);
+
+            drop(f);
+            task.await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,270 @@
+use super::StableBloomFilter;
+use crate::error::SbfError;
+use crate::fnv::FnvBuildHasher;
+#[cfg(feature = "ahash")]
+use crate::hash::AHashBuilder;
+use std::hash::BuildHasher;
+
+/// Default target false-positive rate of built filters.
+const DEFAULT_FP_RATE: f64 = 0.01;
+
+/// StableBloomFilterBuilder creates Stable Bloom Filters from named
+/// parameters. The size of the filter is set either directly with `cells` or
+/// derived from the number of recent elements to retain with
+/// `expected_window`. Cells have 1 bit and the target false-positive rate is
+/// 0.01 unless set otherwise. Parameters are validated by `build`.
+///
+///     use stable_bloom_filter::stable::StableBloomFilter;
+///
+///     let filter = StableBloomFilter::builder()
+///         .expected_window(10_000)
+///         .bits_per_cell(2)
+///         .fp_rate(0.001)
+///         .build()
+///         .unwrap();
+///     assert_eq!(filter.d(), 2);
+#[derive(Clone, Debug)]
+pub struct StableBloomFilterBuilder<S = FnvBuildHasher> {
+    cells: Option<usize>,
+    window: Option<usize>,
+    d: u8,
+    fp_rate: f64,
+    fill_value: Option<u8>,
+    hash_builder: S,
+}
+
+impl StableBloomFilterBuilder {
+    /// Creates a builder with the default parameters, which hashes data with
+    /// FNV-1a.
+    pub fn new() -> Self {
+        StableBloomFilterBuilder {
+            cells: None,
+            window: None,
+            d: 1,
+            fp_rate: DEFAULT_FP_RATE,
+            fill_value: None,
+            hash_builder: FnvBuildHasher::default(),
+        }
+    }
+
+    /// Seeds the FNV-1a hash, so that filters built with different seeds
+    /// place the same data in different cells.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.hash_builder = FnvBuildHasher::with_key(seed);
+        self
+    }
+}
+
+impl Default for StableBloomFilterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: BuildHasher> StableBloomFilterBuilder<S> {
+    /// Sets the number of cells, m.
+    pub fn cells(mut self, m: usize) -> Self {
+        self.cells = Some(m);
+        self
+    }
+
+    /// Sets the number of bits per cell, d.
+    pub fn bits_per_cell(mut self, d: u8) -> Self {
+        self.d = d;
+        self
+    }
+
+    /// Sets the target false-positive rate.
+    pub fn fp_rate(mut self, fp_rate: f64) -> Self {
+        self.fp_rate = fp_rate;
+        self
+    }
+
+    /// Sets the value cells are set to when an element is added, see
+    /// `StableBloomFilter::set_fill_value`. It must be between 1 and the
+    /// maximum cell value, which it defaults to.
+    pub fn fill_value(mut self, fill_value: u8) -> Self {
+        self.fill_value = Some(fill_value);
+        self
+    }
+
+    /// Sizes the filter to retain about n recent elements at the target
+    /// false-positive rate, as computed by `StableBloomFilter::m_for`.
+    pub fn expected_window(mut self, n: usize) -> Self {
+        self.window = Some(n);
+        self
+    }
+
+    /// Sets the hash builder used to hash the data, replacing any seed.
+    pub fn hasher<T: BuildHasher>(self, hash_builder: T) -> StableBloomFilterBuilder<T> {
+        StableBloomFilterBuilder {
+            cells: self.cells,
+            window: self.window,
+            d: self.d,
+            fp_rate: self.fp_rate,
+            fill_value: self.fill_value,
+            hash_builder,
+        }
+    }
+
+    /// Hashes the data with aHash seeded with seed, replacing any seed or
+    /// hash builder set before. aHash is faster than FNV-1a for short keys,
+    /// but its hashes aren't portable, see `AHashBuilder`.
+    #[cfg(feature = "ahash")]
+    pub fn ahash(self, seed: u64) -> StableBloomFilterBuilder<AHashBuilder> {
+        self.hasher(AHashBuilder::with_seed(seed))
+    }
+
+    /// Creates the Stable Bloom Filter, or returns an error if the parameters
+    /// are invalid.
+    pub fn build(self) -> Result<StableBloomFilter<S>, SbfError> {
+        if self.d == 0 || self.d > 8 {
+            return Err(SbfError::InvalidBucketSize(self.d));
+        }
+        if !(self.fp_rate > 0.0 && self.fp_rate < 1.0) {
+            return Err(SbfError::InvalidFpRate(self.fp_rate));
+        }
+        let m = match (self.cells, self.window) {
+            (Some(m), None) => m,
+            (None, Some(n)) => StableBloomFilter::m_for(n, self.fp_rate, self.d),
+            (None, None) => return Err(SbfError::MissingSize),
+            (Some(_), Some(_)) => return Err(SbfError::ConflictingSize),
+        };
+        if m == 0 {
+            return Err(SbfError::ZeroCells);
+        }
+        let max = ((1u16 << self.d) - 1) as u8;
+        if let Some(fill_value) = self.fill_value {
+            if fill_value == 0 || fill_value > max {
+                return Err(SbfError::InvalidFillValue(fill_value));
+            }
+        }
+
+        let mut f = StableBloomFilter::with_hasher(m, self.d, self.fp_rate, self.hash_builder);
+        f.set_fill_value(self.fill_value.unwrap_or(max));
+        Ok(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SbfError;
+    use crate::fnv::FnvBuildHasher;
+    use crate::hash::Murmur3Builder;
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that the builder creates the same filter as the positional
+    // constructor.
+    #[test]
+    fn test_build() {
+        let f = StableBloomFilter::builder()
+            .cells(10_000)
+            .bits_per_cell(3)
+            .fp_rate(0.001)
+            .build()
+            .unwrap();
+        let expected = StableBloomFilter::new(10_000, 3, 0.001);
+        assert_eq!(f.cells(), expected.cells());
+        assert_eq!(f.d(), expected.d());
+        assert_eq!(f.k(), expected.k());
+        assert_eq!(f.p(), expected.p());
+        assert_eq!(f.target_fp_rate(), 0.001);
+
+        let f = StableBloomFilter::builder()
+            .expected_window(5000)
+            .build()
+            .unwrap();
+        assert_eq!(f.cells(), StableBloomFilter::m_for(5000, 0.01, 1));
+        assert_eq!(f.d(), 1);
+    }
+
+    // Ensures that the fill value is passed to the filter and validated.
+    #[test]
+    fn test_build_fill_value() {
+        let f = StableBloomFilter::builder()
+            .cells(1000)
+            .bits_per_cell(2)
+            .build()
+            .unwrap();
+        assert_eq!(f.fill_value(), 3);
+
+        let f = StableBloomFilter::builder()
+            .cells(1000)
+            .bits_per_cell(2)
+            .fill_value(1)
+            .build()
+            .unwrap();
+        assert_eq!(f.fill_value(), 1);
+
+        for fill_value in [0, 4].iter() {
+            assert_eq!(
+                StableBloomFilter::builder()
+                    .cells(1000)
+                    .bits_per_cell(2)
+                    .fill_value(*fill_value)
+                    .build()
+                    .err(),
+                Some(SbfError::InvalidFillValue(*fill_value))
+            );
+        }
+    }
+
+    // Ensures that seeds and hash builders are passed to the filter.
+    #[test]
+    fn test_build_hasher() {
+        let mut f = StableBloomFilter::builder()
+            .cells(1000)
+            .seed(42)
+            .build()
+            .unwrap();
+        assert_eq!(f.hasher(), &FnvBuildHasher::with_key(42));
+        f.add(b"a");
+        assert!(f.test(b"a"));
+
+        let f = StableBloomFilter::builder()
+            .cells(1000)
+            .hasher(Murmur3Builder::with_seed(1))
+            .build()
+            .unwrap();
+        let expected = StableBloomFilter::with_hasher(1000, 1, 0.01, Murmur3Builder::with_seed(1));
+        assert_eq!(f.hash64(b"a"), expected.hash64(b"a"));
+    }
+
+    // Ensures that invalid parameters are reported instead of panicking.
+    #[test]
+    fn test_build_errors() {
+        let builder = StableBloomFilter::builder();
+        assert_eq!(builder.clone().build().err(), Some(SbfError::MissingSize));
+        assert_eq!(
+            builder.clone().cells(0).build().err(),
+            Some(SbfError::ZeroCells)
+        );
+        assert_eq!(
+            builder.clone().cells(10).expected_window(10).build().err(),
+            Some(SbfError::ConflictingSize)
+        );
+        assert_eq!(
+            builder.clone().cells(10).bits_per_cell(9).build().err(),
+            Some(SbfError::InvalidBucketSize(9))
+        );
+        assert_eq!(
+            builder.cells(10).fp_rate(1.0).build().err(),
+            Some(SbfError::InvalidFpRate(1.0))
+        );
+    }
+
+    // Ensures that the builder can select the aHash kernel.
+    #[cfg(feature = "ahash")]
+    #[test]
+    fn test_build_ahash() {
+        let mut f = StableBloomFilter::builder()
+            .cells(1000)
+            .ahash(7)
+            .build()
+            .unwrap();
+        assert_eq!(f.hasher().seed(), 7);
+        f.add(b"a");
+        assert!(f.test(b"a"));
+    }
+}
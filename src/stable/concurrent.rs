@@ -0,0 +1,311 @@
+use super::{enhanced_location, split_hash, stable_zeros};
+use crate::fnv::FnvBuildHasher;
+use crate::{optimal_k, optimal_stable_p, Filter};
+use rand::{thread_rng, Rng};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// ConcurrentStableBloomFilter is a Stable Bloom Filter whose cells are
+/// packed into atomic 64-bit words, so that `add`, `test` and `test_and_add`
+/// can be called from many threads at once through a shared reference. Every
+/// cell update is a lock-free atomic operation on the word holding the cell.
+/// Cells never straddle two words, so with bucket sizes that don't divide 64
+/// a few bits per word are left unused.
+///
+/// Each cell update is atomic, but an add as a whole isn't: a concurrent test
+/// may observe an element whose cells are only partially set.
+pub struct ConcurrentStableBloomFilter<S = FnvBuildHasher> {
+    /// filter data
+    words: Vec<AtomicU64>,
+    /// builds the hash function (kernel for all k functions)
+    hash: S,
+    /// number of cells
+    m: usize,
+    /// number of cells to decrement
+    p: usize,
+    /// number of hash functions
+    k: usize,
+    /// bits per cell
+    d: u8,
+    /// cell max value
+    max: u8,
+    /// number of cells per word
+    cells_per_word: usize,
+}
+
+impl ConcurrentStableBloomFilter {
+    /// Creates a new concurrent Stable Bloom Filter with m cells and d bits
+    /// allocated per cell optimized for the target false-positive rate.
+    pub fn new(m: usize, d: u8, fp_rate: f64) -> Self {
+        Self::with_hasher(m, d, fp_rate, FnvBuildHasher::default())
+    }
+
+    /// Creates a new concurrent Stable Bloom Filter with m 1-bit cells
+    /// optimized for the target false-positive rate.
+    pub fn new_default(m: usize, fp_rate: f64) -> Self {
+        Self::new(m, 1, fp_rate)
+    }
+
+    /// Creates a concurrent traditional Bloom filter with m bits and an
+    /// optimal number of hash functions for the target false-positive rate.
+    /// Data is never evicted.
+    pub fn new_unstable(m: usize, fp_rate: f64) -> Self {
+        Self::with_parameters(m, optimal_k(fp_rate), 0, 1, FnvBuildHasher::default())
+    }
+}
+
+impl<S: BuildHasher> ConcurrentStableBloomFilter<S> {
+    /// Creates a new concurrent Stable Bloom Filter with m cells and d bits
+    /// allocated per cell optimized for the target false-positive rate, which
+    /// uses hash_builder to hash the data.
+    pub fn with_hasher(m: usize, d: u8, fp_rate: f64, hash_builder: S) -> Self {
+        let k = (optimal_k(fp_rate) / 2).clamp(1, m.max(1));
+        Self::with_parameters(m, k, optimal_stable_p(m, k, d, fp_rate), d, hash_builder)
+    }
+
+    /// Creates a filter with m cells of d bits, k hash functions and p cells
+    /// decremented on every add, hashing data with hash.
+    fn with_parameters(m: usize, k: usize, p: usize, d: u8, hash: S) -> Self {
+        if d == 0 || d > 8 {
+            panic!("bucket size must be between 1 and 8");
+        }
+        let cells_per_word = 64 / usize::from(d);
+
+        ConcurrentStableBloomFilter {
+            words: (0..m.div_ceil(cells_per_word))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            hash,
+            m,
+            p,
+            k,
+            d,
+            max: ((1u16 << d) - 1) as u8,
+            cells_per_word,
+        }
+    }
+
+    /// Returns the number of cells in the filter.
+    pub fn cells(&self) -> usize {
+        self.m
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the number of cells decremented on every add.
+    pub fn p(&self) -> usize {
+        self.p
+    }
+
+    /// Returns the maximum cell value.
+    pub fn max(&self) -> u8 {
+        self.max
+    }
+
+    /// Returns the current value of the cell at the specified index.
+    pub fn cell_value(&self, index: usize) -> u8 {
+        let (word, shift) = self.position(index);
+        ((self.words[word].load(Ordering::Relaxed) >> shift) & u64::from(self.max)) as u8
+    }
+
+    /// Returns the limit of the expected fraction of zeros in the filter when
+    /// the number of iterations goes to infinity.
+    pub fn stable_point(&self) -> f64 {
+        stable_zeros(self.m, self.k, self.p, self.max)
+    }
+
+    /// Returns the upper bound on false positives when the filter has become
+    /// stable.
+    pub fn false_positive_rate(&self) -> f64 {
+        (1.0 - self.stable_point()).powf(self.k as f64)
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    pub fn test(&self, data: &[u8]) -> bool {
+        let (lower, upper) = split_hash(self.hash64(data));
+        (0..(self.k)).all(|i| self.cell_value(self.location(lower, upper, i)) != 0)
+    }
+
+    /// Will add the data to the filter.
+    pub fn add(&self, data: &[u8]) {
+        let (lower, upper) = split_hash(self.hash64(data));
+        self.decrement();
+        for i in 0..(self.k) {
+            self.set_max(self.location(lower, upper, i));
+        }
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    pub fn test_and_add(&self, data: &[u8]) -> bool {
+        let (lower, upper) = split_hash(self.hash64(data));
+        let member = (0..(self.k)).all(|i| self.cell_value(self.location(lower, upper, i)) != 0);
+
+        self.decrement();
+        for i in 0..(self.k) {
+            self.set_max(self.location(lower, upper, i));
+        }
+        member
+    }
+
+    /// Returns the 64-bit hash of the data from which the k cell indices are
+    /// derived. It matches `StableBloomFilter::hash64` for the same hash
+    /// builder.
+    #[inline]
+    pub fn hash64(&self, data: &[u8]) -> u64 {
+        let mut hasher = self.hash.build_hasher();
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    /// Will decrement p random consecutive cells by one.
+    fn decrement(&self) {
+        if self.p == 0 {
+            return;
+        }
+        let r: usize = thread_rng().gen_range(0, self.m);
+        for i in 0..(self.p) {
+            self.update((r + i) % self.m, |value| value.saturating_sub(1));
+        }
+    }
+
+    /// Sets the cell at the specified index to the maximum cell value.
+    #[inline]
+    fn set_max(&self, index: usize) {
+        let (word, shift) = self.position(index);
+        self.words[word].fetch_or(u64::from(self.max) << shift, Ordering::Relaxed);
+    }
+
+    /// Atomically replaces the value of the cell at the specified index with
+    /// f applied to it.
+    #[inline]
+    fn update<F: Fn(u8) -> u8>(&self, index: usize, f: F) {
+        let (word, shift) = self.position(index);
+        let mask = u64::from(self.max) << shift;
+        let _ = self.words[word].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            let value = ((bits & mask) >> shift) as u8;
+            Some((bits & !mask) | (u64::from(f(value)) << shift))
+        });
+    }
+
+    /// Returns the word holding the cell at the specified index and the
+    /// offset of the cell within the word.
+    #[inline]
+    fn position(&self, index: usize) -> (usize, usize) {
+        (
+            index / self.cells_per_word,
+            (index % self.cells_per_word) * usize::from(self.d),
+        )
+    }
+
+    /// Returns the cell index of the i-th hash function, derived by enhanced
+    /// double hashing like the cell indices of `StableBloomFilter`.
+    #[inline]
+    fn location(&self, lower: u32, upper: u32, i: usize) -> usize {
+        enhanced_location(lower, upper, i, self.m)
+    }
+}
+
+impl<S: BuildHasher> Filter for ConcurrentStableBloomFilter<S> {
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    fn test(&self, data: &[u8]) -> bool {
+        ConcurrentStableBloomFilter::test(self, data)
+    }
+
+    /// Will add the data to the filter.
+    fn add(&mut self, data: &[u8]) {
+        ConcurrentStableBloomFilter::add(self, data)
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        ConcurrentStableBloomFilter::test_and_add(self, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConcurrentStableBloomFilter;
+    use crate::fnv::FnvBuildHasher;
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+    use std::thread;
+
+    // Ensures that elements added from many threads through a shared
+    // reference are all members.
+    #[test]
+    fn test_concurrent_add() {
+        let f = ConcurrentStableBloomFilter::new_unstable(100_000, 0.01);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let f = &f;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        f.add(format!("{}-{}", t, i).as_bytes());
+                    }
+                });
+            }
+        });
+
+        for t in 0..4 {
+            for i in 0..1000 {
+                assert!(f.test(format!("{}-{}", t, i).as_bytes()));
+            }
+        }
+        assert!(!f.test(b"missing"));
+    }
+
+    // Ensures that the filter sets the same cells as the single-threaded
+    // filter for the same parameters.
+    #[test]
+    fn test_matches_stable_bloom_filter() {
+        for &d in [1, 3].iter() {
+            let mut expected = StableBloomFilter::new(1000, d, 0.01);
+            expected.set_eviction_enabled(false);
+            let stable = ConcurrentStableBloomFilter::new(1000, d, 0.01);
+            assert_eq!(stable.k(), expected.k());
+            assert_eq!(stable.p(), expected.p());
+            assert_eq!(stable.stable_point(), expected.stable_point());
+
+            // Without eviction, so that both filters end up in the same state.
+            let f = ConcurrentStableBloomFilter::with_parameters(
+                1000,
+                expected.k(),
+                0,
+                d,
+                FnvBuildHasher::default(),
+            );
+            for i in 0..20 {
+                let data = i.to_string();
+                f.add(data.as_bytes());
+                expected.add(data.as_bytes());
+            }
+            for i in 0..1000 {
+                assert_eq!(f.cell_value(i), expected.cell_value(i));
+            }
+        }
+    }
+
+    // Ensures that test_and_add reports membership before adding and that
+    // eviction decrements cells without underflowing.
+    #[test]
+    fn test_test_and_add_and_eviction() {
+        let f = ConcurrentStableBloomFilter::new(1000, 2, 0.1);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+
+        for i in 0..100_000 {
+            f.add(i.to_string().as_bytes());
+        }
+        assert!(!f.test(b"a"));
+        let zeros = (0..1000).filter(|i| f.cell_value(*i) == 0).count();
+        assert!(zeros > 0 && zeros < 1000);
+        assert!((0..1000).all(|i| f.cell_value(i) <= f.max()));
+    }
+}
@@ -0,0 +1,140 @@
+//! Stream adaptors, enabled by the `async` feature, which deduplicate
+//! asynchronous streams the way `iter::DedupStable` deduplicates iterators.
+
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use futures_core::stream::{FusedStream, Stream};
+use futures_core::task::{Context, Poll};
+use pin_project_lite::pin_project;
+use std::pin::Pin;
+
+pin_project! {
+    /// DedupStream wraps a stream and a filter, dropping the items the filter
+    /// has probably seen before as they are polled. The filter is owned by
+    /// the stream, so no lock is needed to share it across polls.
+    pub struct DedupStream<St, F = StableBloomFilter> {
+        #[pin]
+        stream: St,
+        filter: F,
+    }
+}
+
+impl<St, F> DedupStream<St, F> {
+    /// Creates a new DedupStream which drops the items of the stream the
+    /// provided filter has seen.
+    pub fn new(stream: St, filter: F) -> Self {
+        DedupStream { stream, filter }
+    }
+
+    /// Returns the filter.
+    pub fn inner_filter(&self) -> &F {
+        &self.filter
+    }
+
+    /// Returns the wrapped stream and the filter.
+    pub fn into_inner(self) -> (St, F) {
+        (self.stream, self.filter)
+    }
+}
+
+impl<St> DedupStream<St> {
+    /// Creates a new DedupStream over an internal Stable Bloom Filter with m
+    /// cells and d bits allocated per cell optimized for the target
+    /// false-positive rate.
+    pub fn with_stable_filter(stream: St, m: usize, d: u8, fp_rate: f64) -> Self {
+        Self::new(stream, StableBloomFilter::new(m, d, fp_rate))
+    }
+}
+
+impl<St, F> Stream for DedupStream<St, F>
+where
+    St: Stream,
+    St::Item: AsRef<[u8]>,
+    F: Filter,
+{
+    type Item = St::Item;
+
+    /// Polls the stream until it yields an item the filter hasn't seen,
+    /// adding every item polled to the filter.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    if !this.filter.test_and_add(item.as_ref()) {
+                        return Poll::Ready(Some(item));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.stream.size_hint().1)
+    }
+}
+
+impl<St, F> FusedStream for DedupStream<St, F>
+where
+    St: FusedStream,
+    St::Item: AsRef<[u8]>,
+    F: Filter,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+/// DedupStreamExt adds `dedup_stable` to every stream of byte-like items.
+pub trait DedupStreamExt: Stream + Sized
+where
+    Self::Item: AsRef<[u8]>,
+{
+    /// Wraps the stream so that it drops the items the filter has probably
+    /// seen before.
+    fn dedup_stable<F: Filter>(self, filter: F) -> DedupStream<Self, F> {
+        DedupStream::new(self, filter)
+    }
+}
+
+impl<St> DedupStreamExt for St
+where
+    St: Stream,
+    St::Item: AsRef<[u8]>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DedupStream, DedupStreamExt};
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+    use futures::executor::block_on;
+    use futures::stream::{self, StreamExt};
+
+    // Ensures that repeated items are dropped from a stream, including items
+    // arriving after the stream was pending.
+    #[test]
+    fn test_dedup_stream() {
+        let items = stream::iter(vec!["a", "b", "a", "c", "b"]);
+        let unique: Vec<&str> = block_on(
+            items
+                .dedup_stable(StableBloomFilter::new_unstable(1000, 0.01))
+                .collect(),
+        );
+        assert_eq!(unique, vec!["a", "b", "c"]);
+
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut dedup = DedupStream::with_stable_filter(rx, 10_000, 2, 0.01);
+        tx.unbounded_send(b"x".to_vec()).unwrap();
+        assert_eq!(block_on(dedup.next()), Some(b"x".to_vec()));
+        tx.unbounded_send(b"x".to_vec()).unwrap();
+        tx.unbounded_send(b"y".to_vec()).unwrap();
+        assert_eq!(block_on(dedup.next()), Some(b"y".to_vec()));
+        drop(tx);
+        assert_eq!(block_on(dedup.next()), None);
+        assert!(dedup.inner_filter().test(b"x"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::sketch::CountMinSketch;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Element is a key tracked by `TopK` along with its estimated frequency.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Element {
+    /// estimated number of occurrences
+    pub freq: u64,
+    /// the key
+    pub data: Vec<u8>,
+}
+
+/// TopK uses a Count-Min Sketch to calculate the top-K frequent elements in a
+/// stream. The K most frequent keys seen so far are kept in a min-heap
+/// ordered by their estimated frequency, so that a key whose estimate
+/// outgrows the least frequent tracked key replaces it.
+///
+/// As frequencies are estimated by the sketch, keys may be reported with
+/// overestimated counts, and keys with close frequencies may be swapped.
+pub struct TopK {
+    /// frequency estimates
+    sketch: CountMinSketch,
+    /// number of keys to track
+    k: usize,
+    /// the tracked keys, least frequent on top
+    elements: BinaryHeap<Reverse<Element>>,
+}
+
+impl TopK {
+    /// Creates a new TopK backed by a Count-Min Sketch whose relative
+    /// accuracy is within a factor of epsilon with probability delta. It
+    /// tracks the k most frequently occurring keys.
+    pub fn new(epsilon: f64, delta: f64, k: usize) -> Self {
+        TopK {
+            sketch: CountMinSketch::new(epsilon, delta),
+            k,
+            elements: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    /// Returns the number of keys tracked.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the underlying Count-Min Sketch.
+    pub fn sketch(&self) -> &CountMinSketch {
+        &self.sketch
+    }
+
+    /// Will add the data to the TopK. It returns the TopK to allow for
+    /// chaining.
+    pub fn add(&mut self, data: &[u8]) -> &Self {
+        self.sketch.increment(data);
+        let freq = self.sketch.estimate(data);
+
+        if self.elements.iter().any(|e| e.0.data == data) {
+            // The key is already tracked, so update its frequency.
+            self.elements.retain(|e| e.0.data != data);
+        } else if self.elements.len() >= self.k {
+            match self.elements.peek() {
+                Some(min) if min.0.freq < freq => {
+                    self.elements.pop();
+                }
+                _ => return self,
+            }
+        }
+
+        self.elements.push(Reverse(Element {
+            freq,
+            data: data.to_vec(),
+        }));
+        self
+    }
+
+    /// Returns the top-k elements, most frequent first. Elements with equal
+    /// frequencies are ordered by key.
+    pub fn elements(&self) -> Vec<Element> {
+        let mut elements: Vec<Element> = self.elements.iter().map(|e| e.0.clone()).collect();
+        elements.sort_by(|a, b| b.freq.cmp(&a.freq).then_with(|| a.data.cmp(&b.data)));
+        elements
+    }
+
+    /// Restores the TopK to its original state. It returns the TopK to allow
+    /// for chaining.
+    pub fn reset(&mut self) -> &Self {
+        self.sketch.reset();
+        self.elements.clear();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopK;
+
+    // Ensures that the most frequent keys of a stream are reported with their
+    // frequencies, most frequent first.
+    #[test]
+    fn test_topk_elements() {
+        let mut topk = TopK::new(0.001, 0.99, 3);
+        assert_eq!(topk.k(), 3);
+
+        for (data, occurrences) in [("bob", 1), ("tyler", 4), ("fred", 2), ("alice", 5)].iter() {
+            for _ in 0..*occurrences {
+                topk.add(data.as_bytes());
+            }
+        }
+        for i in 0..100 {
+            topk.add(format!("noise-{}", i).as_bytes());
+        }
+        topk.add(b"fred");
+        topk.add(b"fred");
+
+        let elements = topk.elements();
+        let keys: Vec<&[u8]> = elements.iter().map(|e| e.data.as_slice()).collect();
+        assert_eq!(keys, vec![&b"alice"[..], &b"fred"[..], &b"tyler"[..]]);
+        let freqs: Vec<u64> = elements.iter().map(|e| e.freq).collect();
+        assert_eq!(freqs, vec![5, 4, 4]);
+    }
+
+    // Ensures that a key tracked once isn't reported twice and that Reset
+    // clears the tracked keys.
+    #[test]
+    fn test_topk_reset() {
+        let mut topk = TopK::new(0.01, 0.01, 5);
+        for _ in 0..10 {
+            topk.add(b"a");
+        }
+        assert_eq!(topk.elements().len(), 1);
+        assert_eq!(topk.elements()[0].freq, 10);
+        assert_eq!(topk.sketch().total_count(), 10);
+
+        topk.reset();
+        assert!(topk.elements().is_empty());
+        assert_eq!(topk.sketch().total_count(), 0);
+    }
+}
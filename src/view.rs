@@ -0,0 +1,96 @@
+use crate::fnv::FnvBuildHasher;
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use std::hash::BuildHasher;
+
+/// FilterView is a read-only view of a Stable Bloom Filter. It can be handed
+/// to subsystems which may test for membership but must not add elements or
+/// otherwise modify the filter.
+pub struct FilterView<'a, S = FnvBuildHasher> {
+    filter: &'a StableBloomFilter<S>,
+}
+
+// Implemented by hand since deriving would require S to be Copy as well.
+impl<'a, S> Clone for FilterView<'a, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, S> Copy for FilterView<'a, S> {}
+
+impl<'a, S: BuildHasher> FilterView<'a, S> {
+    pub(crate) fn new(filter: &'a StableBloomFilter<S>) -> Self {
+        FilterView { filter }
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    #[inline]
+    pub fn test(&self, data: &[u8]) -> bool {
+        self.filter.test(data)
+    }
+
+    /// Returns the number of cells in the Stable Bloom Filter.
+    pub fn cells(&self) -> usize {
+        self.filter.cells()
+    }
+
+    /// Returns the number of hash functions.
+    pub fn k(&self) -> usize {
+        self.filter.k()
+    }
+
+    /// Returns the number of cells decremented on every add.
+    pub fn p(&self) -> usize {
+        self.filter.p()
+    }
+
+    /// Returns the limit of the expected fraction of zeros in the filter.
+    pub fn stable_point(&self) -> f64 {
+        self.filter.stable_point()
+    }
+
+    /// Returns the upper bound on false positives when the filter has become
+    /// stable.
+    pub fn false_positive_rate(&self) -> f64 {
+        self.filter.false_positive_rate()
+    }
+
+    /// Returns the current fraction of non-zero cells.
+    pub fn fill_ratio(&self) -> f64 {
+        self.filter.fill_ratio()
+    }
+
+    /// Returns the number of elements added to the filter.
+    pub fn insert_count(&self) -> u64 {
+        self.filter.insert_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+
+    // Ensures that a view answers membership tests and statistics exactly like
+    // the filter it borrows.
+    #[test]
+    fn test_view() {
+        let mut f = StableBloomFilter::new_unstable(1000, 0.01);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+
+        let view = f.view();
+        for i in 0..100 {
+            let data = i.to_string();
+            assert_eq!(view.test(data.as_bytes()), f.test(data.as_bytes()));
+        }
+        assert_eq!(view.cells(), f.cells());
+        assert_eq!(view.k(), f.k());
+        assert_eq!(view.p(), f.p());
+        assert_eq!(view.insert_count(), 50);
+        assert_eq!(view.fill_ratio(), f.fill_ratio());
+    }
+}
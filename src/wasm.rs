@@ -0,0 +1,80 @@
+//! wasm-bindgen bindings, enabled by the `wasm` feature, which expose the
+//! Stable Bloom Filter to JavaScript as `StableBloomFilter`. Data is passed
+//! as `Uint8Array`s and filters are serialized with the binary snapshot
+//! format of the `serialization` module, so that a filter built in the
+//! browser can be read by a backend service and vice versa.
+
+use crate::stable::StableBloomFilter;
+use crate::Filter;
+use wasm_bindgen::prelude::*;
+
+/// WasmStableBloomFilter wraps a Stable Bloom Filter hashing data with FNV.
+#[wasm_bindgen(js_name = StableBloomFilter)]
+pub struct WasmStableBloomFilter {
+    inner: StableBloomFilter,
+}
+
+#[wasm_bindgen(js_class = StableBloomFilter)]
+impl WasmStableBloomFilter {
+    /// Creates a new Stable Bloom Filter with m cells and d bits allocated
+    /// per cell optimized for the target false-positive rate.
+    #[wasm_bindgen(constructor)]
+    pub fn new(m: usize, d: u8, fp_rate: f64) -> Result<WasmStableBloomFilter, JsError> {
+        Ok(WasmStableBloomFilter {
+            inner: StableBloomFilter::try_new(m, d, fp_rate)?,
+        })
+    }
+
+    /// Reads a filter from a snapshot written by `serialize`.
+    pub fn deserialize(data: &[u8]) -> Result<WasmStableBloomFilter, JsError> {
+        Ok(WasmStableBloomFilter {
+            inner: StableBloomFilter::read_from(data)?,
+        })
+    }
+
+    /// Will add the data to the filter.
+    pub fn add(&mut self, data: &[u8]) {
+        self.inner.add(data);
+    }
+
+    /// Will test for membership of the data and returns true if it is a
+    /// member, false if not.
+    pub fn test(&self, data: &[u8]) -> bool {
+        self.inner.test(data)
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    #[wasm_bindgen(js_name = testAndAdd)]
+    pub fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.inner.test_and_add(data)
+    }
+
+    /// Returns a snapshot of the filter's parameters and cells.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.inner
+            .write_to(&mut buf)
+            .expect("writing to a Vec never fails");
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WasmStableBloomFilter;
+
+    // Ensures that the bindings add and test data and round-trip through a
+    // snapshot.
+    #[test]
+    fn test_bindings() {
+        let mut f = WasmStableBloomFilter::new(1000, 2, 0.01).unwrap();
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test(b"a"));
+        f.add(b"b");
+
+        let restored = WasmStableBloomFilter::deserialize(&f.serialize()).unwrap();
+        assert!(restored.test(b"a"));
+        assert!(restored.test(b"b"));
+    }
+}
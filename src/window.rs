@@ -0,0 +1,270 @@
+use crate::stable::StableBloomFilter;
+use crate::{optimal_m, Filter};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Rotation is the boundary on which a SlidingBloomFilter retires its oldest
+/// generation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    /// rotate once the current generation holds this many elements
+    Items(usize),
+    /// rotate once the current generation is this old
+    Interval(Duration),
+}
+
+/// SlidingBloomFilter answers membership over a sliding window of the stream
+/// by keeping N classic Bloom filters, or generations, of which only the
+/// newest receives adds. Once the current generation reaches its boundary,
+/// the oldest generation is cleared and becomes the current one, and tests
+/// are answered against the union of all generations.
+///
+/// Unlike the probabilistic eviction of a Stable Bloom Filter, this gives a
+/// hard guarantee: an element added within the last window items (or within
+/// the last window of time) is never reported as a non-member. Elements older
+/// than that are forgotten once the generation holding them is retired, at
+/// most one generation's worth after the window. More generations make that
+/// cutoff sharper at the cost of more memory for the same false-positive
+/// rate.
+pub struct SlidingBloomFilter {
+    /// generations, the current one first
+    generations: VecDeque<StableBloomFilter>,
+    /// boundary of a generation
+    rotation: Rotation,
+    /// number of elements added to the current generation
+    count: usize,
+    /// time at which the current generation started
+    started: Instant,
+}
+
+impl SlidingBloomFilter {
+    /// Creates a new sliding filter which remembers at least the last window
+    /// elements, split across the provided number of generations and
+    /// optimized for the target false-positive rate of the union. Panics if
+    /// there are fewer than two generations.
+    pub fn new(window: usize, generations: usize, fp_rate: f64) -> Self {
+        assert!(
+            generations > 1,
+            "a sliding filter needs at least two generations"
+        );
+        let per_generation = window.max(1).div_ceil(generations - 1);
+        Self::with_rotation(
+            Rotation::Items(per_generation),
+            per_generation,
+            generations,
+            fp_rate,
+        )
+    }
+
+    /// Creates a new sliding filter which remembers at least the elements
+    /// added within the last window of time, split across the provided
+    /// number of generations. Each generation is sized for hint elements,
+    /// optimized for the target false-positive rate of the union. Panics if
+    /// there are fewer than two generations.
+    pub fn with_interval(window: Duration, generations: usize, hint: usize, fp_rate: f64) -> Self {
+        assert!(
+            generations > 1,
+            "a sliding filter needs at least two generations"
+        );
+        let interval = window / ((generations - 1) as u32);
+        Self::with_rotation(Rotation::Interval(interval), hint, generations, fp_rate)
+    }
+
+    /// Creates the generations, each sized for n elements. A test checks
+    /// every generation, so each gets an equal share of the target rate.
+    fn with_rotation(rotation: Rotation, n: usize, generations: usize, fp_rate: f64) -> Self {
+        let fp_rate = fp_rate / (generations as f64);
+        let m = optimal_m(n.max(1), fp_rate).max(1);
+
+        SlidingBloomFilter {
+            generations: (0..generations)
+                .map(|_| StableBloomFilter::new_unstable(m, fp_rate))
+                .collect(),
+            rotation,
+            count: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the number of generations.
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Returns the boundary on which generations are rotated.
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Returns the total number of cells across all generations.
+    pub fn capacity(&self) -> usize {
+        self.generations.iter().map(|f| f.cells()).sum()
+    }
+
+    /// Returns the number of elements added to the current generation.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Retires the oldest generation, clearing it and making it the current
+    /// one. It returns the filter to allow for chaining.
+    pub fn rotate(&mut self) -> &Self {
+        self.rotate_at(Instant::now());
+        self
+    }
+
+    /// Rotates as many generations as have expired between the start of the
+    /// current generation and the provided instant. This is a no-op with an
+    /// item-count boundary. Returns the number of rotations.
+    pub fn advance_to(&mut self, now: Instant) -> usize {
+        let interval = match self.rotation {
+            Rotation::Interval(interval) => interval,
+            Rotation::Items(_) => return 0,
+        };
+        let mut rotations = 0;
+        while now.saturating_duration_since(self.started) >= interval {
+            if rotations == self.generations.len() {
+                // Every generation has expired, restart the clock from now.
+                self.started = now;
+                break;
+            }
+            let started = self.started + interval;
+            self.rotate_at(started);
+            rotations += 1;
+        }
+        rotations
+    }
+
+    /// Will add the data to the current generation at the provided instant,
+    /// rotating first if the generation has reached its boundary. It returns
+    /// the filter to allow for chaining.
+    pub fn add_at(&mut self, data: &[u8], now: Instant) -> &Self {
+        self.advance_to(now);
+        if let Rotation::Items(n) = self.rotation {
+            if self.count >= n {
+                self.rotate_at(now);
+            }
+        }
+
+        self.generations[0].add(data);
+        self.count += 1;
+        self
+    }
+
+    /// Is equivalent to calling test followed by add_at. It returns true if
+    /// the data is a member, false if not.
+    pub fn test_and_add_at(&mut self, data: &[u8], now: Instant) -> bool {
+        self.advance_to(now);
+        let member = self.test(data);
+        self.add_at(data, now);
+        member
+    }
+
+    /// Restores the filter to its original state. It returns the filter to
+    /// allow for chaining.
+    pub fn reset(&mut self) -> &Self {
+        for f in self.generations.iter_mut() {
+            f.reset();
+        }
+        self.count = 0;
+        self.started = Instant::now();
+        self
+    }
+
+    /// Clears the oldest generation and makes it the current one, started at
+    /// the provided instant.
+    fn rotate_at(&mut self, started: Instant) {
+        if let Some(mut oldest) = self.generations.pop_back() {
+            oldest.reset();
+            self.generations.push_front(oldest);
+        }
+        self.count = 0;
+        self.started = started;
+    }
+}
+
+impl Filter for SlidingBloomFilter {
+    /// Will test for membership of the data against the union of every
+    /// generation. As testing doesn't modify the filter, generations that
+    /// expired since the last add are only retired by the next add or by
+    /// `advance_to`.
+    fn test(&self, data: &[u8]) -> bool {
+        self.generations.iter().any(|f| f.test(data))
+    }
+
+    /// Will add the data to the current generation.
+    fn add(&mut self, data: &[u8]) {
+        self.add_at(data, Instant::now());
+    }
+
+    /// Is equivalent to calling test followed by add. It returns true if the
+    /// data is a member, false if not.
+    fn test_and_add(&mut self, data: &[u8]) -> bool {
+        self.test_and_add_at(data, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rotation, SlidingBloomFilter};
+    use crate::Filter;
+    use std::time::{Duration, Instant};
+
+    // Ensures that every element within the window is a member and that
+    // elements older than the window and a generation are forgotten.
+    #[test]
+    fn test_item_window() {
+        let mut f = SlidingBloomFilter::new(1000, 3, 0.01);
+        assert_eq!(f.rotation(), Rotation::Items(500));
+        assert_eq!(f.generations(), 3);
+
+        for i in 0..10_000u64 {
+            f.add(&i.to_le_bytes());
+            for j in i.saturating_sub(999)..=i {
+                if j % 97 == 0 {
+                    assert!(f.test(&j.to_le_bytes()));
+                }
+            }
+        }
+
+        // Only the last three generations of 500 elements are remembered.
+        let stale = (0..8500u64).filter(|i| f.test(&i.to_le_bytes())).count();
+        assert!(stale < 200);
+    }
+
+    // Ensures that generations rotate on a time boundary.
+    #[test]
+    fn test_interval_window() {
+        let mut f = SlidingBloomFilter::with_interval(Duration::from_secs(60), 2, 100, 0.01);
+        assert_eq!(f.rotation(), Rotation::Interval(Duration::from_secs(60)));
+        let start = Instant::now();
+
+        f.add_at(b"a", start);
+        f.add_at(b"b", start + Duration::from_secs(70));
+        assert!(f.test(b"a"));
+        assert!(f.test(b"b"));
+
+        assert!(!f.test_and_add_at(b"c", start + Duration::from_secs(130)));
+        assert!(!f.test(b"a"));
+        assert!(f.test(b"b"));
+
+        // Expiring every generation at once clears the filter.
+        assert_eq!(f.advance_to(start + Duration::from_secs(3600)), 2);
+        assert!(!f.test(b"b"));
+        assert!(!f.test(b"c"));
+    }
+
+    // Ensures that test_and_add reports membership before adding and that
+    // reset clears every generation.
+    #[test]
+    fn test_test_and_add_and_reset() {
+        let mut f = SlidingBloomFilter::new(100, 2, 0.01);
+        assert!(!f.test_and_add(b"a"));
+        assert!(f.test_and_add(b"a"));
+        assert_eq!(f.count(), 2);
+
+        f.reset();
+        assert!(!f.test(b"a"));
+        assert_eq!(f.count(), 0);
+    }
+}
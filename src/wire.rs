@@ -0,0 +1,122 @@
+//! Adapters serializing the state of Stable Bloom Filters, as captured by
+//! their serde support, in compact binary wire formats: postcard with the
+//! `postcard` feature, for embedded targets, and bincode with the `bincode`
+//! feature, for service-to-service transfer. Both hold the same state as the
+//! JSON form, including runtime settings, unlike the snapshots of
+//! `serialization`, and can only be read back by this crate.
+
+use crate::stable::StableBloomFilter;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+impl<S: Serialize> StableBloomFilter<S> {
+    /// Returns the state of the filter serialized with postcard, which
+    /// encodes integers as varints.
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard(&self) -> Result<Vec<u8>, postcard::Error> {
+        postcard::to_allocvec(self)
+    }
+
+    /// Returns the state of the filter serialized with bincode.
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(self)
+    }
+}
+
+impl<S: DeserializeOwned> StableBloomFilter<S> {
+    /// Restores a filter from the state serialized by `to_postcard`.
+    #[cfg(feature = "postcard")]
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    /// Restores a filter from the state serialized by `to_bincode`.
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "postcard")]
+    use crate::hash::Murmur3Builder;
+    use crate::stable::StableBloomFilter;
+    use crate::Filter;
+
+    /// Returns a filter with a few members and non-default runtime settings.
+    fn filter() -> StableBloomFilter {
+        let mut f = StableBloomFilter::new(1000, 3, 0.01);
+        f.set_eviction_enabled(false);
+        for i in 0..50 {
+            f.add(i.to_string().as_bytes());
+        }
+        f
+    }
+
+    /// Asserts that restored holds the same state as f.
+    fn assert_restored(restored: &StableBloomFilter, f: &StableBloomFilter) {
+        assert_eq!(restored.buckets(), f.buckets());
+        assert_eq!(restored.k(), f.k());
+        assert_eq!(restored.p(), f.p());
+        assert_eq!(restored.hash_scheme(), f.hash_scheme());
+        assert_eq!(restored.insert_count(), f.insert_count());
+        assert!(!restored.is_eviction_enabled());
+        for i in 0..50 {
+            assert!(restored.test(i.to_string().as_bytes()));
+        }
+    }
+
+    // Ensures that a filter restored from postcard keeps its state, also
+    // with a custom hasher, and that truncated input is rejected.
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_round_trip() {
+        let f = filter();
+        let bytes = f.to_postcard().unwrap();
+        let restored: StableBloomFilter = StableBloomFilter::from_postcard(&bytes).unwrap();
+        assert_restored(&restored, &f);
+        let truncated: Result<StableBloomFilter, _> =
+            StableBloomFilter::from_postcard(&bytes[..bytes.len() / 2]);
+        assert!(truncated.is_err());
+
+        let mut seeded =
+            StableBloomFilter::with_hasher(1000, 1, 0.01, Murmur3Builder::with_seed(7));
+        seeded.add(b"a");
+        let bytes = seeded.to_postcard().unwrap();
+        let restored = StableBloomFilter::<Murmur3Builder>::from_postcard(&bytes).unwrap();
+        assert!(restored.test(b"a"));
+        assert_eq!(restored.hash64(b"a"), seeded.hash64(b"a"));
+    }
+
+    // Ensures that a filter restored from bincode keeps its state and that
+    // truncated input is rejected.
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_round_trip() {
+        let f = filter();
+        let bytes = f.to_bincode().unwrap();
+        let restored: StableBloomFilter = StableBloomFilter::from_bincode(&bytes).unwrap();
+        assert_restored(&restored, &f);
+        let truncated: Result<StableBloomFilter, _> =
+            StableBloomFilter::from_bincode(&bytes[..bytes.len() / 2]);
+        assert!(truncated.is_err());
+    }
+
+    // Ensures that both wire formats carry the same state and that postcard
+    // is the more compact one.
+    #[cfg(all(feature = "postcard", feature = "bincode"))]
+    #[test]
+    fn test_wire_formats_agree() {
+        let f = filter();
+        let postcard = f.to_postcard().unwrap();
+        let bincode = f.to_bincode().unwrap();
+        assert!(postcard.len() < bincode.len());
+
+        let from_postcard: StableBloomFilter = StableBloomFilter::from_postcard(&postcard).unwrap();
+        let from_bincode: StableBloomFilter = StableBloomFilter::from_bincode(&bincode).unwrap();
+        assert_eq!(from_postcard.to_bincode().unwrap(), bincode);
+        assert_eq!(from_bincode.to_postcard().unwrap(), postcard);
+    }
+}
@@ -0,0 +1,144 @@
+#![cfg(feature = "cli")]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Runs sbf with args and stdin, and returns its output.
+fn sbf(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sbf"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Returns a path for a snapshot in the temporary directory.
+fn snapshot(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sbf-cli-{}-{}", std::process::id(), name))
+}
+
+// Ensures that keys added to a created snapshot test positive and that
+// merged snapshots hold the keys of both.
+#[test]
+fn test_create_add_test_merge() {
+    let a = snapshot("a");
+    let b = snapshot("b");
+    let merged = snapshot("merged");
+    let (a, b, merged) = (
+        a.to_str().unwrap(),
+        b.to_str().unwrap(),
+        merged.to_str().unwrap(),
+    );
+
+    for path in [a, b].iter() {
+        let out = sbf(&["create", path, "--cells", "10000", "--bits", "2"], "");
+        assert!(out.status.success());
+    }
+    assert!(sbf(&["add", a], "x\ny\n").status.success());
+    assert!(sbf(&["add", b], "z\r\n").status.success());
+
+    let out = sbf(&["test", a], "x\nz\n");
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "true\tx\nfalse\tz\n"
+    );
+
+    assert!(sbf(&["merge", merged, a, b], "").status.success());
+    let out = sbf(&["test", merged], "x\ny\nz\n");
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "true\tx\ntrue\ty\ntrue\tz\n"
+    );
+
+    let out = sbf(&["stats", merged], "");
+    let stats = String::from_utf8(out.stdout).unwrap();
+    assert!(stats.contains("cells: 10000\n"));
+    assert!(stats.contains("bits per cell: 2\n"));
+
+    for path in [a, b, merged].iter() {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+// Ensures that dedupe drops repeated lines and remembers keys across runs
+// through its snapshot.
+#[test]
+fn test_dedupe() {
+    let out = sbf(&["dedupe"], "a\nb\na\nc\nb\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "a\nb\nc\n");
+
+    let path = snapshot("dedupe");
+    let path = path.to_str().unwrap();
+    assert!(sbf(&["create", path, "--cells", "10000"], "")
+        .status
+        .success());
+    sbf(&["dedupe", "--snapshot", path], "a\n");
+    let out = sbf(&["dedupe", "--snapshot", path], "a\nb\n");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "b\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+// Ensures that saving replaces a snapshot without leaving a temporary file
+// behind, and that a snapshot which can't be written leaves nothing behind.
+#[test]
+fn test_save_replaces_snapshot() {
+    let path = snapshot("replace");
+    let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+    let path = path.to_str().unwrap();
+    assert!(sbf(&["create", path, "--cells", "10000"], "")
+        .status
+        .success());
+    assert!(sbf(
+        &["add", path],
+        "a
+"
+    )
+    .status
+    .success());
+    let out = sbf(
+        &["test", path],
+        "a
+",
+    );
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "true	a
+"
+    );
+
+    let leftovers = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let file = entry.file_name();
+            let file = file.to_string_lossy();
+            file.starts_with(&name) && file != name.as_str()
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+    std::fs::remove_file(path).unwrap();
+
+    let out = sbf(&["create", "/nonexistent/x", "--cells", "10"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("/nonexistent/x"));
+}
+
+// Ensures that invalid invocations fail with a non-zero status.
+#[test]
+fn test_invalid() {
+    assert_eq!(sbf(&[], "").status.code(), Some(2));
+    assert_eq!(sbf(&["create", "x"], "").status.code(), Some(1));
+    assert_eq!(sbf(&["stats", "/nonexistent"], "").status.code(), Some(1));
+}
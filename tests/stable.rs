@@ -0,0 +1,25 @@
+use stable_bloom_filter::stable::StableBloomFilter;
+use stable_bloom_filter::Filter;
+
+// Ensures that adding an element to an empty filter sets at most k cells, and
+// that every cell it touches holds the maximum value.
+#[test]
+fn test_add_sets_cells_to_max() {
+    let mut f = StableBloomFilter::new(1000, 2, 0.01);
+    assert_eq!(f.cells(), 1000);
+
+    f.add(b"a");
+
+    let mut set = 0;
+    for i in 0..f.cells() {
+        let value = f.cell_value(i);
+        if value != 0 {
+            assert_eq!(value, f.max());
+            set += 1;
+        }
+    }
+
+    assert!(set >= 1);
+    assert!(set <= f.k());
+    assert!(f.test(b"a"));
+}